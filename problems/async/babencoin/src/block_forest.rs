@@ -1,9 +1,16 @@
-use crate::data::{
-    BlockHash, TransactionHash, VerifiedBlock, VerifiedTransaction, WalletId, HASH_LEN,
+use crate::{
+    chain_params::ChainParams,
+    data::{
+        BlockAttributes, BlockHash, VerifiedBlock, VerifiedBlockHeader, VerifiedTransaction,
+        WalletId, HASH_LEN,
+    },
+    explorer::{ForestSnapshot, NodeStatus, SnapshotEdge, SnapshotNode},
+    mempool::{Mempool, MempoolConfig},
 };
 
 use anyhow::{bail, Context, Result};
-use chrono::Duration;
+use chrono::{Duration, Utc};
+use crossbeam::channel::{self, Receiver, Sender};
 use log::debug;
 use num_bigint::BigUint;
 
@@ -14,25 +21,57 @@ use std::{
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub const EPOCH_SIZE: usize = 16;
-pub const TARGET_BLOCK_MINING_TIME_SECONDS: u64 = 10;
+const LOCATOR_DENSE_PREFIX: usize = 10;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// How the head chain changed when the head moved to another block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeadChange {
+    /// Blocks removed from the head chain, the old head first.
+    pub disconnected: Vec<Arc<VerifiedBlock>>,
+    /// Blocks added to the head chain, the new head last.
+    pub connected: Vec<Arc<VerifiedBlock>>,
+}
+
+impl HeadChange {
+    pub fn is_reorg(&self) -> bool {
+        !self.disconnected.is_empty()
+    }
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct BlockForest {
+    params: ChainParams,
+    genesis_hash: BlockHash,
     head: Arc<VerifiedBlock>,
+    /// Hashes of the head chain blocks, indexed by the block index.
+    head_chain: Vec<BlockHash>,
     blocks: HashMap<BlockHash, Arc<VerifiedBlock>>,
     children_hashes: HashMap<BlockHash, Vec<BlockHash>>,
     bad_block_hashes: HashSet<BlockHash>,
     unknown_block_hashes: HashSet<BlockHash>,
+    headers: HashMap<BlockHash, VerifiedBlockHeader>,
+    best_header: Option<(BlockHash, u64)>,
     balance_snapshots: HashMap<BlockHash, HashMap<WalletId, u64>>,
-    pending_transactions: HashMap<TransactionHash, VerifiedTransaction>,
-    pending_snapshot: HashMap<WalletId, u64>,
+    mempool: Mempool,
+    subscribers: Vec<Sender<HeadChange>>,
 }
 
 impl Default for BlockForest {
     fn default() -> Self {
-        let genesis = Arc::new(VerifiedBlock::genesis());
+        Self::with_params(ChainParams::default(), MempoolConfig::default())
+    }
+}
+
+impl BlockForest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_params(params: ChainParams, mempool_config: MempoolConfig) -> Self {
+        let genesis = Arc::new(params.verified_genesis_block());
 
         let mut blocks = HashMap::new();
         blocks.insert(*genesis.hash(), genesis.clone());
@@ -41,21 +80,24 @@ impl Default for BlockForest {
         balance_snapshots.insert(*genesis.hash(), HashMap::new());
 
         Self {
+            params,
+            genesis_hash: *genesis.hash(),
+            head_chain: vec![*genesis.hash()],
             head: genesis,
             blocks,
             children_hashes: HashMap::new(),
             bad_block_hashes: HashSet::new(),
             unknown_block_hashes: HashSet::new(),
+            headers: HashMap::new(),
+            best_header: None,
             balance_snapshots,
-            pending_transactions: HashMap::new(),
-            pending_snapshot: HashMap::new(),
+            mempool: Mempool::new(mempool_config),
+            subscribers: vec![],
         }
     }
-}
 
-impl BlockForest {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn params(&self) -> &ChainParams {
+        &self.params
     }
 
     pub fn head(&self) -> &Arc<VerifiedBlock> {
@@ -66,29 +108,118 @@ impl BlockForest {
         &self.unknown_block_hashes
     }
 
-    pub fn pending_transactions(&self) -> &HashMap<TransactionHash, VerifiedTransaction> {
-        &self.pending_transactions
+    /// Number of known chain tips other than the head.
+    pub fn fork_count(&self) -> usize {
+        self.blocks
+            .keys()
+            .filter(|hash| {
+                *hash != self.head.hash()
+                    && self
+                        .children_hashes
+                        .get(*hash)
+                        .map_or(0, |children| children.len())
+                        == 0
+            })
+            .count()
+    }
+
+    /// Lists all the blocks the forest knows about, including the bad ones and the
+    /// unknown parents, along with the parent links.
+    pub fn snapshot(&self) -> ForestSnapshot {
+        let mut blocks: Vec<_> = self.blocks.values().collect();
+        blocks.sort_by_key(|block| (block.index, *block.hash()));
+
+        let mut nodes = vec![];
+        let mut edges = vec![];
+        for block in blocks {
+            let status = if block.hash() == self.head.hash() {
+                NodeStatus::Head
+            } else {
+                NodeStatus::Known
+            };
+            nodes.push(SnapshotNode {
+                hash: *block.hash(),
+                status,
+                index: Some(block.index),
+                issuer: Some(block.issuer.clone()),
+                tx_count: block.transactions().len(),
+            });
+            if block.index > 0 {
+                edges.push(SnapshotEdge {
+                    parent: block.prev_hash,
+                    child: *block.hash(),
+                });
+            }
+        }
+
+        for (hashes, status) in [
+            (&self.bad_block_hashes, NodeStatus::Bad),
+            (&self.unknown_block_hashes, NodeStatus::Unknown),
+        ] {
+            let mut hashes: Vec<_> = hashes.iter().collect();
+            hashes.sort();
+            nodes.extend(hashes.into_iter().map(|hash| SnapshotNode {
+                hash: *hash,
+                status,
+                index: None,
+                issuer: None,
+                tx_count: 0,
+            }));
+        }
+
+        ForestSnapshot {
+            head: *self.head.hash(),
+            nodes,
+            edges,
+        }
+    }
+
+    /// Returns a channel that receives every `HeadChange` from now on. The
+    /// subscription ends when the receiver is dropped.
+    pub fn subscribe(&mut self) -> Receiver<HeadChange> {
+        let (sender, receiver) = channel::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn pending_transactions(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Returns at most `limit` pending transactions with the highest fees, in the
+    /// order they can be added to the block following the head. The time-locked
+    /// transactions are skipped until the lock is reached by that block mined now.
+    pub fn select_transactions(&self, limit: usize) -> Vec<VerifiedTransaction> {
+        self.mempool
+            .select(self.head_balances(), limit, self.head.index + 1, Utc::now())
     }
 
     pub fn find_block(&self, hash: &BlockHash) -> Option<&Arc<VerifiedBlock>> {
         self.blocks.get(hash)
     }
 
+    /// Headers that were validated by `add_headers()`, but whose blocks are not known yet.
+    pub fn pending_headers(&self) -> &HashMap<BlockHash, VerifiedBlockHeader> {
+        &self.headers
+    }
+
     pub fn next_max_hash(&self) -> BlockHash {
         let next_index = self.head.index + 1;
-        if next_index % EPOCH_SIZE as u64 > 0 {
+        let epoch_size = self.params.epoch_size;
+        if next_index % epoch_size as u64 > 0 {
             return self.head.max_hash;
         };
 
-        let mut prev_epoch = self.get_ancestors(&self.head, EPOCH_SIZE - 1);
+        let mut prev_epoch = self.get_ancestors(&self.head, epoch_size - 1);
         prev_epoch.reverse();
         prev_epoch.push(&self.head);
 
-        assert_eq!(prev_epoch.len(), EPOCH_SIZE);
+        assert_eq!(prev_epoch.len(), epoch_size);
         self.compute_epoch_max_hash(&prev_epoch)
     }
 
-    pub fn add_block(&mut self, block: VerifiedBlock) -> Result<()> {
+    /// Returns the change of the head chain if the block moved the head.
+    pub fn add_block(&mut self, block: VerifiedBlock) -> Result<Option<HeadChange>> {
         if self.bad_block_hashes.contains(block.hash()) {
             bail!("block {} is known to be bad", base64::encode(block.hash()));
         }
@@ -101,10 +232,11 @@ impl BlockForest {
         }
 
         if self.blocks.contains_key(block.hash()) {
-            return Ok(());
+            return Ok(None);
         }
 
         self.unknown_block_hashes.remove(block.hash());
+        self.headers.remove(block.hash());
 
         let block_arc = Arc::new(block.clone());
         self.blocks.insert(*block.hash(), block_arc.clone());
@@ -124,21 +256,158 @@ impl BlockForest {
             let head_candidate = self.find_head_candidate(&block_arc);
             if head_candidate.index > self.head.index {
                 let new_head = head_candidate.clone();
-                self.switch_head_to(new_head);
+                return Ok(Some(self.switch_head_to(new_head)));
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    pub fn add_transaction(&mut self, tx: VerifiedTransaction) -> Result<()> {
-        if self.pending_transactions.contains_key(tx.hash()) {
-            return Ok(());
+    /// Returns hashes of the blocks with the tip of the known chain first, then
+    /// exponentially sparser ancestors, and the genesis block last. The known chain
+    /// is the longer one of the head chain and the headers chain.
+    pub fn block_locator(&self) -> Vec<BlockHash> {
+        let tip_hash = match self.best_header {
+            Some((hash, index)) if index > self.head.index && self.find_attrs(&hash).is_some() => {
+                hash
+            }
+            _ => *self.head.hash(),
+        };
+
+        let genesis_hash = self.genesis_hash;
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut hash = tip_hash;
+        'outer: loop {
+            locator.push(hash);
+            if locator.len() >= LOCATOR_DENSE_PREFIX {
+                step *= 2;
+            }
+            for _ in 0..step {
+                match self.find_attrs(&hash) {
+                    Some(attrs) if attrs.index > 0 => hash = attrs.prev_hash,
+                    _ => break 'outer,
+                }
+            }
         }
 
-        Self::try_apply_tx_to_snapshot(&tx, &mut self.pending_snapshot)?;
-        self.pending_transactions.insert(*tx.hash(), tx);
-        Ok(())
+        if locator.last() != Some(&genesis_hash) {
+            locator.push(genesis_hash);
+        }
+        locator
+    }
+
+    /// Returns up to `limit` headers of the head chain that follow the first
+    /// locator hash found in the head chain (or the genesis block, if none is).
+    pub fn find_headers(&self, locator: &[BlockHash], limit: usize) -> Vec<VerifiedBlockHeader> {
+        let start_index = locator
+            .iter()
+            .find_map(|hash| {
+                let block = self.blocks.get(hash)?;
+                let head_chain_hash = self.head_chain.get(block.index as usize)?;
+                (head_chain_hash == hash).then_some(block.index as usize)
+            })
+            .unwrap_or(0);
+
+        self.head_chain
+            .iter()
+            .skip(start_index + 1)
+            .take(limit)
+            .map(|hash| self.blocks[hash].header())
+            .collect()
+    }
+
+    /// Validates a contiguous chain of headers against the known blocks and headers
+    /// and remembers it. Returns hashes of the headers whose blocks are not known yet.
+    pub fn add_headers(&mut self, headers: Vec<VerifiedBlockHeader>) -> Result<Vec<BlockHash>> {
+        let first = match headers.first() {
+            Some(h) => h,
+            None => return Ok(vec![]),
+        };
+        if self.find_attrs(&first.prev_hash).is_none() {
+            bail!("header {} parent is unknown", base64::encode(first.hash()));
+        }
+
+        let epoch_size = self.params.epoch_size;
+        let mut window: VecDeque<&BlockAttributes> = self
+            .get_known_ancestors(&first.prev_hash, epoch_size)
+            .into_iter()
+            .rev()
+            .collect();
+        let mut prev_hash = first.prev_hash;
+        for header in headers.iter() {
+            if self.bad_block_hashes.contains(header.hash()) {
+                bail!(
+                    "header {} is known to be bad",
+                    base64::encode(header.hash())
+                );
+            }
+            if header.prev_hash != prev_hash {
+                bail!("headers are not contiguous");
+            }
+
+            let prev = *window.back().unwrap();
+            self.validate_against_parent(header, prev)?;
+
+            if header.index % epoch_size as u64 == 0 {
+                if window.len() != epoch_size {
+                    bail!(
+                        "header {} has not enough known ancestors",
+                        base64::encode(header.hash())
+                    );
+                }
+                let epoch: Vec<_> = window.iter().copied().collect();
+                let expected_max_hash = self.compute_epoch_max_hash(&epoch);
+                if header.max_hash != expected_max_hash {
+                    bail!(
+                        "wrong max_hash: expected {:?}, got {:?}",
+                        expected_max_hash,
+                        header.max_hash
+                    );
+                }
+            }
+
+            window.push_back(header);
+            if window.len() > epoch_size {
+                window.pop_front();
+            }
+            prev_hash = *header.hash();
+        }
+
+        let mut missing_hashes = vec![];
+        for header in headers.into_iter() {
+            let hash = *header.hash();
+            if self.blocks.contains_key(&hash) {
+                continue;
+            }
+
+            let is_best = match self.best_header {
+                Some((_, best_index)) => header.index > best_index,
+                None => true,
+            };
+            if is_best {
+                self.best_header = Some((hash, header.index));
+            }
+
+            self.headers.insert(hash, header);
+            missing_hashes.push(hash);
+        }
+        Ok(missing_hashes)
+    }
+
+    pub fn add_transaction(&mut self, tx: VerifiedTransaction) -> Result<()> {
+        let base = &self.balance_snapshots[self.head.hash()];
+        self.mempool.insert(tx, base)
+    }
+
+    /// Drops the pending transactions older than `MempoolConfig::max_age`.
+    pub fn expire_pending_transactions(&mut self) {
+        let base = &self.balance_snapshots[self.head.hash()];
+        self.mempool.expire(base);
+    }
+
+    fn head_balances(&self) -> &HashMap<WalletId, u64> {
+        &self.balance_snapshots[self.head.hash()]
     }
 
     fn mark_bad_block(&mut self, root_hash: &BlockHash) {
@@ -174,7 +443,7 @@ impl BlockForest {
         let mut stack = vec![*block.hash()];
         let mut bad_children = vec![];
 
-        // Validate all descendants down to 2 * EPOCH_SIZE generations.
+        // Validate all descendants down to two epochs of generations.
         while let Some(hash) = stack.pop() {
             let children_hashes = match self.children_hashes.get(&hash) {
                 Some(h) => h,
@@ -185,7 +454,7 @@ impl BlockForest {
                 let child_block = &self.blocks[child_hash];
                 match self.validate_block(child_block) {
                     Ok(()) => {
-                        if child_block.index - block.index < (2 * EPOCH_SIZE) as u64 {
+                        if child_block.index - block.index < (2 * self.params.epoch_size) as u64 {
                             stack.push(*child_hash);
                         }
                     }
@@ -210,30 +479,7 @@ impl BlockForest {
 
     fn validate_block(&self, block: &VerifiedBlock) -> Result<()> {
         if let Some(prev) = self.find_block(&block.prev_hash) {
-            let expected_index = prev.index + 1;
-            if block.index != expected_index {
-                bail!(
-                    "wrong block id: expected {}, got {}",
-                    expected_index,
-                    block.index
-                );
-            }
-
-            if block.timestamp <= prev.timestamp {
-                bail!(
-                    "block timestamp <= parent timestamp (block ts: {}, parent ts: {})",
-                    block.timestamp,
-                    prev.timestamp,
-                );
-            }
-
-            if block.index % EPOCH_SIZE as u64 > 0 && prev.max_hash != block.max_hash {
-                bail!(
-                    "wrong max_hash: expected {:?}, got {:?}",
-                    prev.max_hash,
-                    block.max_hash,
-                );
-            }
+            self.validate_against_parent(block, prev)?;
         }
 
         if let Some(expected_max_hash) = self.compute_max_hash(block) {
@@ -249,13 +495,47 @@ impl BlockForest {
         Ok(())
     }
 
+    fn validate_against_parent(
+        &self,
+        block: &BlockAttributes,
+        prev: &BlockAttributes,
+    ) -> Result<()> {
+        let expected_index = prev.index + 1;
+        if block.index != expected_index {
+            bail!(
+                "wrong block id: expected {}, got {}",
+                expected_index,
+                block.index
+            );
+        }
+
+        if block.timestamp <= prev.timestamp {
+            bail!(
+                "block timestamp <= parent timestamp (block ts: {}, parent ts: {})",
+                block.timestamp,
+                prev.timestamp,
+            );
+        }
+
+        if block.index % self.params.epoch_size as u64 > 0 && prev.max_hash != block.max_hash {
+            bail!(
+                "wrong max_hash: expected {:?}, got {:?}",
+                prev.max_hash,
+                block.max_hash,
+            );
+        }
+
+        Ok(())
+    }
+
     fn compute_max_hash(&self, block: &VerifiedBlock) -> Option<BlockHash> {
-        if block.index % EPOCH_SIZE as u64 > 0 {
+        let epoch_size = self.params.epoch_size;
+        if block.index % epoch_size as u64 > 0 {
             let parent = self.blocks.get(&block.prev_hash)?;
             Some(parent.max_hash)
         } else {
-            let mut prev_epoch = self.get_ancestors(block, EPOCH_SIZE);
-            if prev_epoch.len() != EPOCH_SIZE {
+            let mut prev_epoch = self.get_ancestors(block, epoch_size);
+            if prev_epoch.len() != epoch_size {
                 return None;
             }
             prev_epoch.reverse();
@@ -263,27 +543,56 @@ impl BlockForest {
         }
     }
 
-    fn get_ancestors(&self, block: &VerifiedBlock, limit: usize) -> Vec<&VerifiedBlock> {
+    fn get_ancestors(&self, block: &BlockAttributes, limit: usize) -> Vec<&BlockAttributes> {
         let mut ancestors = Vec::with_capacity(limit);
         let mut hash = block.prev_hash;
         while let Some(ancestor) = self.find_block(&hash) {
             if ancestors.len() == limit {
                 break;
             }
-            ancestors.push(ancestor as &VerifiedBlock);
+            ancestors.push(ancestor as &BlockAttributes);
             hash = ancestor.prev_hash;
         }
         ancestors
     }
 
-    fn compute_epoch_max_hash(&self, epoch: &[&VerifiedBlock]) -> BlockHash {
-        assert_eq!(epoch.len(), EPOCH_SIZE);
-        let epoch_id = epoch[0].index / 16;
-        assert_eq!(epoch[0].index, epoch_id * EPOCH_SIZE as u64);
-        assert_eq!(
-            epoch.last().unwrap().index,
-            (epoch_id + 1) * EPOCH_SIZE as u64 - 1
-        );
+    fn find_attrs(&self, hash: &BlockHash) -> Option<&BlockAttributes> {
+        if let Some(block) = self.blocks.get(hash) {
+            return Some(block);
+        }
+        self.headers
+            .get(hash)
+            .map(|header| header as &BlockAttributes)
+    }
+
+    /// Like `get_ancestors()`, but starts from the block with the given hash itself
+    /// and also walks through the headers without known blocks.
+    fn get_known_ancestors(&self, hash: &BlockHash, limit: usize) -> Vec<&BlockAttributes> {
+        let mut ancestors = Vec::with_capacity(limit);
+        let mut hash = *hash;
+        while let Some(ancestor) = self.find_attrs(&hash) {
+            if ancestors.len() == limit {
+                break;
+            }
+            ancestors.push(ancestor);
+            if ancestor.index == 0 {
+                break;
+            }
+            hash = ancestor.prev_hash;
+        }
+        ancestors
+    }
+
+    fn compute_epoch_max_hash(&self, epoch: &[&BlockAttributes]) -> BlockHash {
+        let epoch_size = self.params.epoch_size as u64;
+        assert_eq!(epoch.len() as u64, epoch_size);
+        let epoch_id = epoch[0].index / epoch_size;
+        assert_eq!(epoch[0].index, epoch_id * epoch_size);
+        assert_eq!(epoch.last().unwrap().index, (epoch_id + 1) * epoch_size - 1);
+
+        if !self.params.retarget {
+            return epoch[0].max_hash;
+        }
 
         let avg_duration = {
             let mut sum_duration = Duration::zero();
@@ -301,9 +610,10 @@ impl BlockForest {
         };
 
         let old_max_hash = BigUint::from_bytes_be(&epoch[0].max_hash);
-        let factor = (avg_duration.num_seconds() as f64 / TARGET_BLOCK_MINING_TIME_SECONDS as f64)
-            .max(0.001)
-            .min(1000.);
+        let factor = (avg_duration.num_seconds() as f64
+            / self.params.target_block_time.as_secs_f64())
+        .max(0.001)
+        .min(1000.);
 
         let max_hash = if factor > 1. {
             old_max_hash * factor.round() as u64
@@ -327,9 +637,8 @@ impl BlockForest {
     }
 
     fn is_block_connected_to_genesis(&self, hash: &BlockHash) -> bool {
-        let genesis_hash = *VerifiedBlock::genesis().hash();
         let mut last_hash = *hash;
-        while last_hash != genesis_hash {
+        while last_hash != self.genesis_hash {
             if let Some(parent) = self.blocks.get(&last_hash) {
                 last_hash = parent.prev_hash;
             } else {
@@ -420,8 +729,13 @@ impl BlockForest {
         best
     }
 
-    fn switch_head_to(&mut self, new_head: Arc<VerifiedBlock>) {
+    fn switch_head_to(&mut self, new_head: Arc<VerifiedBlock>) -> HeadChange {
         let lca = self.find_lca(&self.head, &new_head);
+        let mut change = HeadChange {
+            disconnected: self.list_blocks(&self.head, lca),
+            connected: self.list_blocks(&new_head, lca),
+        };
+        change.connected.reverse();
 
         let new_branch_tx_hashes: HashSet<_> = self
             .list_transactions(&new_head, lca)
@@ -431,32 +745,25 @@ impl BlockForest {
 
         let old_branch_txs = self.list_transactions(&self.head, lca);
 
-        let mut new_pending_transactions = HashMap::new();
-        let mut new_snapshot = self.balance_snapshots.get(new_head.hash()).unwrap().clone();
-        for tx in old_branch_txs
-            .iter()
-            .chain(self.pending_transactions.values())
-        {
-            if new_branch_tx_hashes.contains(tx.hash())
-                || new_pending_transactions.contains_key(tx.hash())
-            {
-                continue;
-            }
-
-            if let Err(err) = Self::try_apply_tx_to_snapshot(tx, &mut new_snapshot) {
-                debug!(
-                    "discarding transaction {}: {:#}",
-                    base64::encode(tx.hash()),
-                    err,
-                );
-            } else {
-                new_pending_transactions.insert(*tx.hash(), tx.clone());
-            }
-        }
-
+        let base = &self.balance_snapshots[new_head.hash()];
+        self.mempool
+            .reset(base, old_branch_txs, &new_branch_tx_hashes);
         self.head = new_head;
-        self.pending_transactions = new_pending_transactions;
-        self.pending_snapshot = new_snapshot;
+        self.head_chain
+            .truncate(self.head_chain.len() - change.disconnected.len());
+        self.head_chain
+            .extend(change.connected.iter().map(|block| *block.hash()));
+
+        if change.is_reorg() {
+            debug!(
+                "head reorganized: {} blocks disconnected, {} connected",
+                change.disconnected.len(),
+                change.connected.len()
+            );
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+        change
     }
 
     fn find_lca<'a>(
@@ -501,37 +808,59 @@ impl BlockForest {
         Ok(())
     }
 
-    fn try_apply_tx_to_snapshot(
+    /// The sender pays for all the outputs at once, so the outputs can't spend
+    /// more than the sender has even if every one of them fits alone.
+    pub(crate) fn try_apply_tx_to_snapshot(
         tx: &VerifiedTransaction,
         snapshot: &mut HashMap<WalletId, u64>,
     ) -> Result<()> {
+        let total_spent = tx
+            .total_spent()
+            .context("transaction amount overflows u64")?;
         let old_sender_balance = *snapshot.get(&tx.sender).unwrap_or(&0);
         let new_sender_balance = old_sender_balance
-            .checked_sub(tx.amount)
-            .and_then(|value| value.checked_sub(tx.fee))
+            .checked_sub(total_spent)
             .context("sender has insufficient funds")?;
 
-        let old_receiver_balance = *snapshot.get(&tx.receiver).unwrap_or(&0);
-        let new_receiver_balance = old_receiver_balance
-            .checked_add(tx.amount)
-            .context("receiver balance overflows u64")?;
-
-        for (key, value) in [
-            (tx.sender.clone(), new_sender_balance),
-            (tx.receiver.clone(), new_receiver_balance),
-        ]
-        .into_iter()
-        {
+        let mut new_balances = HashMap::new();
+        new_balances.insert(&tx.sender, new_sender_balance);
+        for (receiver, amount) in tx.outputs() {
+            let old_receiver_balance = match new_balances.get(receiver) {
+                Some(balance) => *balance,
+                None => *snapshot.get(receiver).unwrap_or(&0),
+            };
+            let new_receiver_balance = old_receiver_balance
+                .checked_add(amount)
+                .context("receiver balance overflows u64")?;
+            new_balances.insert(receiver, new_receiver_balance);
+        }
+
+        for (key, value) in new_balances.into_iter() {
             if value > 0 {
-                snapshot.insert(key, value);
+                snapshot.insert(key.clone(), value);
             } else {
-                snapshot.remove(&key);
+                snapshot.remove(key);
             }
         }
 
         Ok(())
     }
 
+    /// Returns the blocks from `inclusive_from` down to `exclusive_to`.
+    fn list_blocks(
+        &self,
+        inclusive_from: &Arc<VerifiedBlock>,
+        exclusive_to: &Arc<VerifiedBlock>,
+    ) -> Vec<Arc<VerifiedBlock>> {
+        let mut blocks = vec![];
+        let mut block = inclusive_from;
+        while block.hash() != exclusive_to.hash() {
+            blocks.push(block.clone());
+            block = &self.blocks[&block.prev_hash];
+        }
+        blocks
+    }
+
     fn list_transactions(
         &self,
         inclusive_from: &Arc<VerifiedBlock>,
@@ -546,3 +875,382 @@ impl BlockForest {
         transactions
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{Block, LockTime, TxOutput, MAX_HEADERS_PER_MESSAGE},
+        signature::{generate_ed25519_key, SigningKey},
+        util::parse_pkcs8_private,
+    };
+
+    fn make_chain(params: &ChainParams, len: usize, interval: i64) -> Vec<VerifiedBlock> {
+        let forest = BlockForest::with_params(params.clone(), MempoolConfig::default());
+        let mut blocks = vec![params.verified_genesis_block()];
+        for i in 1..len {
+            let prev = blocks.last().unwrap();
+            let mut block = params.genesis_block();
+            block.index = i as u64;
+            block.prev_hash = *prev.hash();
+            block.timestamp = prev.timestamp + Duration::seconds(interval);
+            block.max_hash = if i % params.epoch_size == 0 {
+                let epoch: Vec<_> = blocks[i - params.epoch_size..]
+                    .iter()
+                    .map(|block| block as &BlockAttributes)
+                    .collect();
+                forest.compute_epoch_max_hash(&epoch)
+            } else {
+                prev.max_hash
+            };
+            while block.compute_hash() > block.max_hash {
+                block.nonce += 1;
+            }
+            blocks.push(block.verified(params).unwrap());
+        }
+        blocks
+    }
+
+    fn make_mainnet_chain(len: usize) -> Vec<VerifiedBlock> {
+        let params = ChainParams::mainnet();
+        let interval = params.target_block_time.as_secs() as i64;
+        make_chain(&params, len, interval)
+    }
+
+    #[test]
+    fn test_headers_sync() {
+        let epoch_size = ChainParams::mainnet().epoch_size;
+        let chain = make_mainnet_chain(3 * epoch_size + 5);
+
+        let mut source = BlockForest::new();
+        for block in chain.iter().skip(1) {
+            source.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(source.head().hash(), chain.last().unwrap().hash());
+
+        let mut target = BlockForest::new();
+        let mut missing_hashes = vec![];
+        loop {
+            let headers = source.find_headers(&target.block_locator(), MAX_HEADERS_PER_MESSAGE);
+            if headers.is_empty() {
+                break;
+            }
+            missing_hashes.extend(target.add_headers(headers).unwrap());
+        }
+
+        let expected_hashes: Vec<_> = chain.iter().skip(1).map(|b| *b.hash()).collect();
+        assert_eq!(missing_hashes, expected_hashes);
+        assert_eq!(
+            target.block_locator().first(),
+            Some(chain.last().unwrap().hash())
+        );
+
+        for block in chain.iter().skip(1).rev() {
+            target.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(target.head().hash(), chain.last().unwrap().hash());
+        assert!(target.pending_headers().is_empty());
+    }
+
+    #[test]
+    fn test_find_headers_with_locator() {
+        let chain = make_mainnet_chain(2 * ChainParams::mainnet().epoch_size);
+
+        let mut forest = BlockForest::new();
+        for block in chain.iter().skip(1) {
+            forest.add_block(block.clone()).unwrap();
+        }
+
+        let locator = vec![[1u8; HASH_LEN], *chain[7].hash()];
+        let headers = forest.find_headers(&locator, 3);
+        let hashes: Vec<_> = headers.iter().map(|h| *h.hash()).collect();
+        assert_eq!(
+            hashes,
+            vec![*chain[8].hash(), *chain[9].hash(), *chain[10].hash()]
+        );
+
+        let locator = forest.block_locator();
+        assert_eq!(locator.first(), Some(chain.last().unwrap().hash()));
+        assert_eq!(locator.last(), Some(chain[0].hash()));
+        assert!(locator.len() < chain.len());
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let params = ChainParams::mainnet();
+        let epoch_size = params.epoch_size;
+        let chain = make_mainnet_chain(epoch_size + 2);
+        let headers: Vec<_> = chain.iter().skip(1).map(|b| b.header()).collect();
+
+        let mut forest = BlockForest::new();
+        assert!(forest.add_headers(headers[1..].to_vec()).is_err());
+
+        let mut shuffled = headers.clone();
+        shuffled.swap(2, 3);
+        assert!(forest.add_headers(shuffled).is_err());
+
+        let mut block: Block = chain[epoch_size].to_block();
+        block.max_hash[HASH_LEN - 1] = 0;
+        let mut bad_headers = headers[..epoch_size - 1].to_vec();
+        bad_headers.push(block.verified(&params).unwrap().header());
+        assert!(forest.add_headers(bad_headers).is_err());
+
+        assert_eq!(forest.add_headers(headers).unwrap().len(), epoch_size + 1);
+    }
+
+    #[test]
+    fn test_mempool_reorg() {
+        let params = ChainParams::mainnet();
+        let priv_key = parse_pkcs8_private(include_str!("../data/test.pem")).unwrap();
+        let genesis = VerifiedBlock::genesis();
+
+        let make_block = |prev: &VerifiedBlock, seconds: i64, txs: Vec<VerifiedTransaction>| {
+            let mut block = Block::genesis();
+            block.index = prev.index + 1;
+            block.prev_hash = *prev.hash();
+            block.timestamp = genesis.timestamp + Duration::seconds(seconds);
+            // Only the first block rewards the sender.
+            if block.index == 1 {
+                block.reward = params.max_reward;
+                block.issuer = priv_key.to_public_key().into();
+            }
+            block.transactions = txs.into_iter().map(|tx| tx.into()).collect();
+            block.merkle_root = block.compute_merkle_root();
+            block.verified(&params).unwrap()
+        };
+
+        let mut forest = BlockForest::new();
+        let first = make_block(&genesis, 10, vec![]);
+        forest.add_block(first.clone()).unwrap();
+
+        let cheap =
+            VerifiedTransaction::sign(&priv_key, genesis.issuer.clone(), 600, 1, "cheap".into())
+                .unwrap();
+        let expensive = VerifiedTransaction::sign(
+            &priv_key,
+            genesis.issuer.clone(),
+            300,
+            20,
+            "expensive".into(),
+        )
+        .unwrap();
+        forest.add_transaction(cheap.clone()).unwrap();
+        forest.add_transaction(expensive.clone()).unwrap();
+        assert_eq!(forest.select_transactions(1), vec![expensive.clone()]);
+
+        let second = make_block(&first, 20, vec![cheap.clone()]);
+        forest.add_block(second.clone()).unwrap();
+        assert_eq!(forest.head().hash(), second.hash());
+        assert!(!forest.pending_transactions().contains(cheap.hash()));
+        assert!(forest.pending_transactions().contains(expensive.hash()));
+
+        // The competing chain spends the funds of the sender, so only the transaction
+        // with the higher fee fits into the mempool.
+        let spend =
+            VerifiedTransaction::sign(&priv_key, genesis.issuer.clone(), 400, 0, "spend".into())
+                .unwrap();
+        let competing_second = make_block(&first, 21, vec![spend]);
+        let competing_third = make_block(&competing_second, 31, vec![]);
+        forest.add_block(competing_second).unwrap();
+        forest.add_block(competing_third.clone()).unwrap();
+        assert_eq!(forest.head().hash(), competing_third.hash());
+        assert!(!forest.pending_transactions().contains(cheap.hash()));
+        assert!(forest.pending_transactions().contains(expensive.hash()));
+        assert_eq!(forest.pending_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_head_change() {
+        let make_block = |prev: &VerifiedBlock, seconds: i64| {
+            let mut block = Block::genesis();
+            block.index = prev.index + 1;
+            block.prev_hash = *prev.hash();
+            block.timestamp = prev.timestamp + Duration::seconds(seconds);
+            Arc::new(block.verified(&ChainParams::mainnet()).unwrap())
+        };
+        let add = |forest: &mut BlockForest, block: &Arc<VerifiedBlock>| {
+            forest.add_block((**block).clone()).unwrap()
+        };
+
+        let genesis = VerifiedBlock::genesis();
+        let first = make_block(&genesis, 10);
+        let second = make_block(&first, 10);
+        let competing_first = make_block(&genesis, 11);
+        let competing_second = make_block(&competing_first, 10);
+        let competing_third = make_block(&competing_second, 10);
+        let competing_fourth = make_block(&competing_third, 10);
+
+        let mut forest = BlockForest::new();
+        let receiver = forest.subscribe();
+        let dropped = forest.subscribe();
+        drop(dropped);
+
+        let change = add(&mut forest, &first).unwrap();
+        assert_eq!(change.connected, vec![first.clone()]);
+        assert!(!change.is_reorg());
+        add(&mut forest, &second).unwrap();
+        assert_eq!(forest.subscribers.len(), 1);
+        assert_eq!(add(&mut forest, &first), None);
+
+        // The head stays on the block that came first among the blocks of the same height.
+        assert_eq!(add(&mut forest, &competing_first), None);
+        assert_eq!(add(&mut forest, &competing_second), None);
+        assert_eq!(forest.head().hash(), second.hash());
+
+        // The competing chain overtakes once its missing block arrives.
+        assert_eq!(add(&mut forest, &competing_fourth), None);
+        let change = add(&mut forest, &competing_third).unwrap();
+        assert!(change.is_reorg());
+        assert_eq!(change.disconnected, vec![second.clone(), first.clone()]);
+        assert_eq!(
+            change.connected,
+            vec![
+                competing_first,
+                competing_second,
+                competing_third,
+                competing_fourth.clone()
+            ]
+        );
+        assert_eq!(forest.head().hash(), competing_fourth.hash());
+
+        // The disconnected blocks are not on the head chain anymore.
+        let hashes: Vec<_> = forest
+            .find_headers(&[*second.hash(), *first.hash()], 10)
+            .iter()
+            .map(|header| *header.hash())
+            .collect();
+        let connected: Vec<_> = change.connected.iter().map(|block| *block.hash()).collect();
+        assert_eq!(hashes, connected);
+
+        let changes: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[1].connected, vec![second]);
+        assert_eq!(changes[2], change);
+    }
+
+    #[test]
+    fn test_mixed_schemes() {
+        let params = ChainParams::mainnet();
+        let rsa_key = parse_pkcs8_private(include_str!("../data/test.pem")).unwrap();
+        let ed25519_key = generate_ed25519_key();
+
+        let make_block = |prev: &VerifiedBlock, issuer: WalletId, txs: Vec<VerifiedTransaction>| {
+            let mut block = Block::genesis();
+            block.index = prev.index + 1;
+            block.prev_hash = *prev.hash();
+            block.timestamp = prev.timestamp + Duration::seconds(10);
+            block.reward = params.max_reward;
+            block.issuer = issuer;
+            block.transactions = txs.into_iter().map(|tx| tx.into()).collect();
+            block.merkle_root = block.compute_merkle_root();
+            block.verified(&params).unwrap()
+        };
+
+        let mut forest = BlockForest::new();
+        let first = make_block(&VerifiedBlock::genesis(), ed25519_key.wallet_id(), vec![]);
+        forest.add_block(first.clone()).unwrap();
+
+        let to_rsa =
+            VerifiedTransaction::sign(&ed25519_key, rsa_key.wallet_id(), 600, 10, "a".into())
+                .unwrap();
+        let to_ed25519 =
+            VerifiedTransaction::sign(&rsa_key, ed25519_key.wallet_id(), 100, 0, "b".into())
+                .unwrap();
+        let second = make_block(&first, rsa_key.wallet_id(), vec![to_rsa, to_ed25519]);
+        forest.add_block(second.clone()).unwrap();
+        assert_eq!(forest.head().hash(), second.hash());
+
+        let balances = forest.head_balances();
+        assert_eq!(balances[&ed25519_key.wallet_id()], 1000 - 610 + 100);
+        assert_eq!(balances[&rsa_key.wallet_id()], 1000 + 10 + 600 - 100);
+    }
+
+    #[test]
+    fn test_multi_output_transactions() {
+        let params = ChainParams::mainnet();
+        let sender = generate_ed25519_key();
+        let first_receiver = generate_ed25519_key().wallet_id();
+        let second_receiver = generate_ed25519_key().wallet_id();
+
+        let make_block = |prev: &VerifiedBlock, txs: Vec<VerifiedTransaction>| {
+            let mut block = Block::genesis();
+            block.index = prev.index + 1;
+            block.prev_hash = *prev.hash();
+            block.timestamp = prev.timestamp + Duration::seconds(10);
+            block.reward = params.max_reward;
+            block.issuer = sender.wallet_id();
+            block.transactions = txs.into_iter().map(|tx| tx.into()).collect();
+            block.merkle_root = block.compute_merkle_root();
+            block.verified(&params).unwrap()
+        };
+        let pay = |amounts: &[u64], not_before: Option<LockTime>| {
+            let outputs = amounts
+                .iter()
+                .zip([&first_receiver, &second_receiver].into_iter().cycle())
+                .map(|(amount, receiver)| TxOutput {
+                    receiver: receiver.clone(),
+                    amount: *amount,
+                })
+                .collect();
+            VerifiedTransaction::sign_with_outputs(&sender, outputs, 0, "".into(), not_before)
+                .unwrap()
+        };
+
+        let mut forest = BlockForest::new();
+        let first = make_block(&VerifiedBlock::genesis(), vec![]);
+        forest.add_block(first.clone()).unwrap();
+
+        // Every output fits into the balance alone, but not together.
+        assert!(forest.add_transaction(pay(&[600, 500], None)).is_err());
+        // The block reward of the sender comes first.
+        let bad = make_block(&first, vec![pay(&[1200, 900], None)]);
+        assert!(forest.add_block(bad).is_err());
+
+        // The same receiver may appear twice.
+        let split = pay(&[300, 200, 100], None);
+        let second = make_block(&first, vec![split]);
+        forest.add_block(second.clone()).unwrap();
+        let balances = forest.head_balances();
+        assert_eq!(balances[&sender.wallet_id()], 2000 - 600);
+        assert_eq!(balances[&first_receiver], 400);
+        assert_eq!(balances[&second_receiver], 200);
+
+        let locked = pay(&[100], Some(LockTime::BlockIndex(4)));
+        let unlocked = pay(&[50], None);
+        forest.add_transaction(locked.clone()).unwrap();
+        forest.add_transaction(unlocked.clone()).unwrap();
+        assert_eq!(forest.select_transactions(10), vec![unlocked.clone()]);
+
+        let third = make_block(&second, vec![unlocked]);
+        forest.add_block(third).unwrap();
+        assert_eq!(forest.select_transactions(10), vec![locked]);
+    }
+
+    #[test]
+    fn test_chain_params() {
+        let mut params = ChainParams::regtest();
+        params.epoch_size = 4;
+        params.target_block_time = std::time::Duration::from_secs(20);
+
+        // Blocks are twice as fast as the target, so the difficulty doubles.
+        params.retarget = true;
+        let chain = make_chain(&params, 6, 10);
+        let mut forest = BlockForest::with_params(params.clone(), MempoolConfig::default());
+        for block in chain.iter().skip(1) {
+            forest.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(forest.head().hash(), chain[5].hash());
+        assert_eq!(chain[4].max_hash[0], 127);
+
+        params.retarget = false;
+        let chain = make_chain(&params, 6, 1);
+        let mut forest = BlockForest::with_params(params, MempoolConfig::default());
+        for block in chain.iter().skip(1) {
+            forest.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(forest.head().hash(), chain[5].hash());
+        assert_eq!(forest.next_max_hash(), [255u8; HASH_LEN]);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{
+    data::{Block, BlockAttributes, BlockHash, VerifiedBlock, WalletId, BLOCK_VERSION, HASH_LEN},
+    merkle::EMPTY_MERKLE_ROOT,
+    util::{
+        deserialize_base64_fixed, deserialize_utc, deserialize_wallet_id, serialize_base64,
+        serialize_utc, serialize_wallet_id,
+    },
+};
+
+use anyhow::{bail, ensure, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use std::{str::FromStr, time::Duration};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    pub fn params(&self) -> ChainParams {
+        match self {
+            Self::Mainnet => ChainParams::mainnet(),
+            Self::Testnet => ChainParams::testnet(),
+            Self::Regtest => ChainParams::regtest(),
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ok(match name {
+            "mainnet" => Self::Mainnet,
+            "testnet" => Self::Testnet,
+            "regtest" => Self::Regtest,
+            _ => bail!("unknown network {}", name),
+        })
+    }
+}
+
+/// Consensus parameters of a chain. Nodes with different parameters can't agree on
+/// the blocks, since they have different genesis blocks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainParams {
+    /// How many blocks are mined with the same `max_hash`.
+    pub epoch_size: usize,
+    /// The average time between blocks the difficulty adjusts to.
+    #[serde(with = "humantime_serde")]
+    pub target_block_time: Duration,
+    /// If false, every block is mined with the `max_hash` of the genesis block.
+    pub retarget: bool,
+    pub max_reward: u64,
+
+    #[serde(serialize_with = "serialize_utc", deserialize_with = "deserialize_utc")]
+    pub genesis_timestamp: DateTime<Utc>,
+    #[serde(
+        serialize_with = "serialize_wallet_id",
+        deserialize_with = "deserialize_wallet_id"
+    )]
+    pub genesis_issuer: WalletId,
+    #[serde(
+        serialize_with = "serialize_base64",
+        deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
+    )]
+    pub genesis_max_hash: BlockHash,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        Self {
+            epoch_size: 16,
+            target_block_time: Duration::from_secs(10),
+            retarget: true,
+            max_reward: 1000,
+            genesis_timestamp: Utc.timestamp_opt(1626002428, 0).unwrap(),
+            genesis_issuer: WalletId::of_genesis(),
+            genesis_max_hash: [255u8; HASH_LEN],
+        }
+    }
+
+    /// Like mainnet, but with faster blocks and its own genesis block.
+    pub fn testnet() -> Self {
+        Self {
+            target_block_time: Duration::from_secs(2),
+            genesis_timestamp: Utc.timestamp_opt(1640995200, 0).unwrap(),
+            ..Self::mainnet()
+        }
+    }
+
+    /// For local networks and tests: the difficulty never changes, so any hash is
+    /// good enough for a block.
+    pub fn regtest() -> Self {
+        Self {
+            target_block_time: Duration::from_secs(1),
+            retarget: false,
+            genesis_timestamp: Utc.timestamp_opt(1609459200, 0).unwrap(),
+            ..Self::mainnet()
+        }
+    }
+
+    /// Checks the parameters that the block forest divides by.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.epoch_size > 0, "epoch_size must be positive");
+        ensure!(
+            !self.target_block_time.is_zero(),
+            "target_block_time must be positive"
+        );
+        Ok(())
+    }
+
+    pub fn genesis_block(&self) -> Block {
+        Block {
+            attrs: BlockAttributes {
+                version: BLOCK_VERSION,
+                index: 0,
+                timestamp: self.genesis_timestamp,
+                reward: 0,
+                nonce: 0,
+                issuer: self.genesis_issuer.clone(),
+                max_hash: self.genesis_max_hash,
+                prev_hash: [0u8; HASH_LEN],
+                merkle_root: EMPTY_MERKLE_ROOT,
+            },
+            transactions: vec![],
+        }
+    }
+
+    pub fn verified_genesis_block(&self) -> VerifiedBlock {
+        self.genesis_block()
+            .verified(self)
+            .expect("genesis block must be valid")
+    }
+
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.genesis_block().compute_hash()
+    }
+}
+
+/// Accepts either the name of a preset, e.g. `regtest`, or all the parameters,
+/// which must pass `ChainParams::validate`.
+pub fn deserialize_chain_params<'de, D>(deserializer: D) -> Result<ChainParams, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Preset(Network),
+        Custom(Box<ChainParams>),
+    }
+
+    Ok(match Repr::deserialize(deserializer)? {
+        Repr::Preset(network) => network.params(),
+        Repr::Custom(params) => {
+            params
+                .validate()
+                .map_err(|err| de::Error::custom(format!("{:#}", err)))?;
+            *params
+        }
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(deserialize_with = "deserialize_chain_params")]
+        chain: ChainParams,
+    }
+
+    #[test]
+    fn test_presets() {
+        let networks = [Network::Mainnet, Network::Testnet, Network::Regtest];
+        let hashes: Vec<_> = networks
+            .iter()
+            .map(|network| *network.params().verified_genesis_block().hash())
+            .collect();
+        assert_eq!(hashes[0], *VerifiedBlock::genesis().hash());
+        assert_ne!(hashes[0], hashes[1]);
+        assert_ne!(hashes[1], hashes[2]);
+        assert_ne!(hashes[0], hashes[2]);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: Config = serde_yaml::from_str("chain: regtest").unwrap();
+        assert_eq!(config.chain, ChainParams::regtest());
+
+        let mut params = ChainParams::testnet();
+        params.max_reward = 10;
+        let yaml = format!("chain: {}", serde_json::to_string(&params).unwrap());
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.chain, params);
+
+        assert!(serde_yaml::from_str::<Config>("chain: simnet").is_err());
+
+        let mut params = ChainParams::regtest();
+        params.epoch_size = 0;
+        let yaml = format!("chain: {}", serde_json::to_string(&params).unwrap());
+        let err = serde_yaml::from_str::<Config>(&yaml).err().unwrap();
+        assert!(err.to_string().contains("epoch_size"), "{}", err);
+
+        let mut params = ChainParams::regtest();
+        params.target_block_time = Duration::ZERO;
+        let yaml = format!("chain: {}", serde_json::to_string(&params).unwrap());
+        let err = serde_yaml::from_str::<Config>(&yaml).err().unwrap();
+        assert!(err.to_string().contains("target_block_time"), "{}", err);
+    }
+
+    #[test]
+    fn test_foreign_genesis() {
+        let mainnet = ChainParams::mainnet();
+        let regtest = ChainParams::regtest();
+        assert!(regtest.genesis_block().verified(&mainnet).is_err());
+
+        let mut block = mainnet.genesis_block();
+        block.index = 1;
+        block.timestamp = regtest.genesis_timestamp + chrono::Duration::seconds(1);
+        block.prev_hash = regtest.genesis_hash();
+        assert!(block.clone().verified(&regtest).is_ok());
+        assert!(block.verified(&mainnet).is_err());
+    }
+}
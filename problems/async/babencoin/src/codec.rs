@@ -0,0 +1,898 @@
+use crate::{
+    data::{
+        Block, BlockAttributes, BlockHash, BlockHeader, LockTime, PeerMessage, Transaction,
+        TxOutput, WalletId, HASH_LEN, TX_VERSION_1,
+    },
+    merkle::{MerkleProof, MerkleStep, Side},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, LocalResult, TimeZone, Utc};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_512};
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, ErrorKind, Read, Write},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const MAX_MESSAGE_SIZE: usize = 65536;
+
+const KEY_HASH_LEN: usize = 16;
+/// The number of keys a session remembers. Keys sent after that are always inline.
+const MAX_KNOWN_KEYS: usize = 4096;
+
+type KeyHash = [u8; KEY_HASH_LEN];
+
+const TAG_BLOCK: u8 = 0;
+const TAG_TRANSACTION: u8 = 1;
+const TAG_REQUEST: u8 = 2;
+const TAG_GET_HEADERS: u8 = 3;
+const TAG_HEADERS: u8 = 4;
+const TAG_GET_PROOF: u8 = 5;
+const TAG_PROOF: u8 = 6;
+const TAG_TRANSACTION_V2: u8 = 7;
+const TAG_BLOCK_V2: u8 = 8;
+
+const SIDE_LEFT: u8 = 0;
+const SIDE_RIGHT: u8 = 1;
+
+const KEY_INLINE: u8 = 0;
+const KEY_REFERENCE: u8 = 1;
+
+const LOCK_NONE: u8 = 0;
+const LOCK_BLOCK_INDEX: u8 = 1;
+const LOCK_TIMESTAMP: u8 = 2;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Wire encoding of the messages within a session.
+///
+/// `Json` frames are JSON messages followed by a zero byte. Other frames are
+/// a little-endian `u32` payload length followed by the payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Json,
+    Binary,
+    BinaryDeflate,
+}
+
+/// The first message of a session that negotiates a codec. Always sent as JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename = "hello")]
+pub struct Hello {
+    pub codecs: Vec<Codec>,
+}
+
+impl Hello {
+    /// Returns the first codec of the dialing side that the accepting side supports.
+    pub fn negotiate(dialer: &Hello, acceptor: &Hello) -> Codec {
+        dialer
+            .codecs
+            .iter()
+            .find(|codec| acceptor.codecs.contains(codec))
+            .copied()
+            .unwrap_or(Codec::Json)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Reads a single frame. Returns `None` if the stream ended at a frame boundary.
+pub fn read_frame<R: BufRead>(reader: &mut R, codec: Codec) -> Result<Option<Vec<u8>>> {
+    match codec {
+        Codec::Json => {
+            let mut frame = vec![];
+            let len = reader
+                .take(MAX_MESSAGE_SIZE as u64 + 1)
+                .read_until(0, &mut frame)
+                .context("failed to read frame")?;
+            if len == 0 {
+                return Ok(None);
+            }
+            ensure!(frame.pop() == Some(0), "message is too large or truncated");
+            Ok(Some(frame))
+        }
+        Codec::Binary | Codec::BinaryDeflate => {
+            let len = match reader.read_u32::<LittleEndian>() {
+                Ok(len) => len as usize,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err).context("failed to read frame length"),
+            };
+            ensure!(len <= MAX_MESSAGE_SIZE, "message is too large");
+
+            let mut frame = vec![0; len];
+            reader
+                .read_exact(&mut frame)
+                .context("failed to read frame")?;
+            Ok(Some(frame))
+        }
+    }
+}
+
+/// Fails without writing anything if the frame is larger than `MAX_MESSAGE_SIZE`,
+/// since the peer would drop the session on reading it.
+pub fn write_frame<W: Write>(writer: &mut W, codec: Codec, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("message of {} bytes is too large", frame.len()),
+        ));
+    }
+    match codec {
+        Codec::Json => {
+            writer.write_all(frame)?;
+            writer.write_all(b"\0")
+        }
+        Codec::Binary | Codec::BinaryDeflate => {
+            writer.write_u32::<LittleEndian>(frame.len() as u32)?;
+            writer.write_all(frame)
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Hashes of the keys sent in full within a session. The keys added while encoding
+/// a message are remembered, so that they can be forgotten if the encoding fails.
+#[derive(Default)]
+struct SentKeys {
+    hashes: HashSet<KeyHash>,
+    added: Vec<KeyHash>,
+}
+
+impl SentKeys {
+    /// Returns false if the key was sent before and can be referenced.
+    fn insert(&mut self, key_hash: KeyHash) -> bool {
+        if self.hashes.contains(&key_hash) {
+            return false;
+        }
+        if self.hashes.len() < MAX_KNOWN_KEYS {
+            self.hashes.insert(key_hash);
+            self.added.push(key_hash);
+        }
+        true
+    }
+
+    fn commit(&mut self) {
+        self.added.clear();
+    }
+
+    fn rollback(&mut self) {
+        for key_hash in self.added.drain(..) {
+            self.hashes.remove(&key_hash);
+        }
+    }
+}
+
+/// Encodes the outgoing messages of a session. Every key is sent in full the first
+/// time it appears in the session, later it is referenced by its hash.
+pub struct MessageEncoder {
+    codec: Codec,
+    sent_keys: SentKeys,
+}
+
+impl MessageEncoder {
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            sent_keys: SentKeys::default(),
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns the frame payload. Fails if it is larger than `MAX_MESSAGE_SIZE`,
+    /// the keys of such a message are not considered sent.
+    pub fn encode(&mut self, message: &PeerMessage) -> Result<Vec<u8>> {
+        let result = match self.codec {
+            Codec::Json => serde_json::to_vec(message).context("failed to serialize message"),
+            Codec::Binary => {
+                let mut buffer = vec![];
+                Self::write_message(&mut buffer, &mut self.sent_keys, message).map(|_| buffer)
+            }
+            Codec::BinaryDeflate => {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                Self::write_message(&mut encoder, &mut self.sent_keys, message)
+                    .and_then(|_| encoder.finish().context("failed to compress message"))
+            }
+        };
+        let result = result.and_then(|frame| {
+            ensure!(
+                frame.len() <= MAX_MESSAGE_SIZE,
+                "message of {} bytes is too large",
+                frame.len()
+            );
+            Ok(frame)
+        });
+        if result.is_ok() {
+            self.sent_keys.commit();
+        } else {
+            self.sent_keys.rollback();
+        }
+        result
+    }
+
+    fn write_message<W: Write>(
+        writer: &mut W,
+        sent_keys: &mut SentKeys,
+        message: &PeerMessage,
+    ) -> Result<()> {
+        match message {
+            PeerMessage::Block(block) => {
+                let versioned = block
+                    .transactions
+                    .iter()
+                    .any(|tx| tx.version != TX_VERSION_1);
+                writer.write_u8(if versioned { TAG_BLOCK_V2 } else { TAG_BLOCK })?;
+                Self::write_attrs(writer, sent_keys, &block.attrs)?;
+                writer.write_u32::<LittleEndian>(block.transactions.len() as u32)?;
+                for tx in block.transactions.iter() {
+                    Self::write_transaction(writer, sent_keys, tx, versioned)?;
+                }
+            }
+            PeerMessage::Transaction(tx) => {
+                let versioned = tx.version != TX_VERSION_1;
+                writer.write_u8(if versioned {
+                    TAG_TRANSACTION_V2
+                } else {
+                    TAG_TRANSACTION
+                })?;
+                Self::write_transaction(writer, sent_keys, tx, versioned)?;
+            }
+            PeerMessage::Request { block_hash } => {
+                writer.write_u8(TAG_REQUEST)?;
+                writer.write_all(block_hash)?;
+            }
+            PeerMessage::GetHeaders { locator } => {
+                writer.write_u8(TAG_GET_HEADERS)?;
+                Self::write_hashes(writer, locator)?;
+            }
+            PeerMessage::Headers { headers } => {
+                writer.write_u8(TAG_HEADERS)?;
+                writer.write_u32::<LittleEndian>(headers.len() as u32)?;
+                for header in headers.iter() {
+                    Self::write_attrs(writer, sent_keys, &header.attrs)?;
+                }
+            }
+            PeerMessage::GetProof {
+                block_hash,
+                transaction_hash,
+            } => {
+                writer.write_u8(TAG_GET_PROOF)?;
+                writer.write_all(block_hash)?;
+                writer.write_all(transaction_hash)?;
+            }
+            PeerMessage::Proof {
+                header,
+                transaction_hash,
+                proof,
+            } => {
+                writer.write_u8(TAG_PROOF)?;
+                Self::write_attrs(writer, sent_keys, &header.attrs)?;
+                writer.write_all(transaction_hash)?;
+                writer.write_u32::<LittleEndian>(proof.steps.len() as u32)?;
+                for step in proof.steps.iter() {
+                    writer.write_u8(match step.side {
+                        Side::Left => SIDE_LEFT,
+                        Side::Right => SIDE_RIGHT,
+                    })?;
+                    writer.write_all(&step.hash)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_attrs<W: Write>(
+        writer: &mut W,
+        sent_keys: &mut SentKeys,
+        attrs: &BlockAttributes,
+    ) -> Result<()> {
+        writer.write_u32::<LittleEndian>(attrs.version)?;
+        writer.write_u64::<LittleEndian>(attrs.index)?;
+        writer.write_u64::<LittleEndian>(attrs.reward)?;
+        writer.write_u64::<LittleEndian>(attrs.nonce)?;
+        writer.write_i64::<LittleEndian>(attrs.timestamp.timestamp())?;
+        Self::write_key(writer, sent_keys, &attrs.issuer)?;
+        writer.write_all(&attrs.max_hash)?;
+        writer.write_all(&attrs.prev_hash)?;
+        writer.write_all(&attrs.merkle_root)?;
+        Ok(())
+    }
+
+    /// Unless `versioned`, the transaction must be of version 1 and is written the
+    /// way it was before versioning.
+    fn write_transaction<W: Write>(
+        writer: &mut W,
+        sent_keys: &mut SentKeys,
+        tx: &Transaction,
+        versioned: bool,
+    ) -> Result<()> {
+        if versioned {
+            writer.write_u32::<LittleEndian>(tx.version)?;
+        }
+        writer.write_u64::<LittleEndian>(tx.amount)?;
+        writer.write_u64::<LittleEndian>(tx.fee)?;
+        write_bytes(writer, tx.comment.as_bytes())?;
+        Self::write_key(writer, sent_keys, &tx.sender)?;
+        Self::write_key(writer, sent_keys, &tx.receiver)?;
+        if tx.version != TX_VERSION_1 {
+            writer.write_u32::<LittleEndian>(tx.extra_outputs.len() as u32)?;
+            for output in tx.extra_outputs.iter() {
+                Self::write_key(writer, sent_keys, &output.receiver)?;
+                writer.write_u64::<LittleEndian>(output.amount)?;
+            }
+            match tx.not_before {
+                None => writer.write_u8(LOCK_NONE)?,
+                Some(LockTime::BlockIndex(index)) => {
+                    writer.write_u8(LOCK_BLOCK_INDEX)?;
+                    writer.write_u64::<LittleEndian>(index)?;
+                }
+                Some(LockTime::Timestamp(timestamp)) => {
+                    writer.write_u8(LOCK_TIMESTAMP)?;
+                    writer.write_i64::<LittleEndian>(timestamp.timestamp())?;
+                }
+            }
+        }
+        write_bytes(writer, &tx.signature)?;
+        Ok(())
+    }
+
+    fn write_key<W: Write>(writer: &mut W, sent_keys: &mut SentKeys, key: &WalletId) -> Result<()> {
+        let key_hash = compute_key_hash(key);
+        if sent_keys.insert(key_hash) {
+            writer.write_u8(KEY_INLINE)?;
+            write_bytes(writer, &key.to_bytes()?)?;
+        } else {
+            writer.write_u8(KEY_REFERENCE)?;
+            writer.write_all(&key_hash)?;
+        }
+        Ok(())
+    }
+
+    fn write_hashes<W: Write>(writer: &mut W, hashes: &[BlockHash]) -> Result<()> {
+        writer.write_u32::<LittleEndian>(hashes.len() as u32)?;
+        for hash in hashes.iter() {
+            writer.write_all(hash)?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decodes the incoming messages of a session. Must see every frame of the
+/// session in order, since keys may be referenced by the earlier messages.
+pub struct MessageDecoder {
+    codec: Codec,
+    known_keys: HashMap<KeyHash, WalletId>,
+}
+
+impl MessageDecoder {
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            known_keys: HashMap::new(),
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn decode(&mut self, frame: &[u8]) -> Result<PeerMessage> {
+        match self.codec {
+            Codec::Json => {
+                let data_str =
+                    std::str::from_utf8(frame).context("message is not a valid utf-8")?;
+                serde_json::from_str(data_str).context("failed to deserialize message")
+            }
+            Codec::Binary => self.decode_binary(frame),
+            Codec::BinaryDeflate => {
+                let mut data = vec![];
+                DeflateDecoder::new(frame)
+                    .take(MAX_MESSAGE_SIZE as u64 * 16)
+                    .read_to_end(&mut data)
+                    .context("failed to decompress message")?;
+                self.decode_binary(&data)
+            }
+        }
+    }
+
+    fn decode_binary(&mut self, mut data: &[u8]) -> Result<PeerMessage> {
+        let reader = &mut data;
+        let message = match reader.read_u8()? {
+            tag @ (TAG_BLOCK | TAG_BLOCK_V2) => {
+                let attrs = self.read_attrs(reader)?;
+                let tx_count = reader.read_u32::<LittleEndian>()? as usize;
+                let mut transactions = Vec::with_capacity(tx_count.min(reader.len()));
+                for _ in 0..tx_count {
+                    transactions.push(self.read_transaction(reader, tag == TAG_BLOCK_V2)?);
+                }
+                PeerMessage::Block(Box::new(Block {
+                    attrs,
+                    transactions,
+                }))
+            }
+            TAG_TRANSACTION => {
+                PeerMessage::Transaction(Box::new(self.read_transaction(reader, false)?))
+            }
+            TAG_TRANSACTION_V2 => {
+                PeerMessage::Transaction(Box::new(self.read_transaction(reader, true)?))
+            }
+            TAG_REQUEST => PeerMessage::Request {
+                block_hash: read_hash(reader)?,
+            },
+            TAG_GET_HEADERS => PeerMessage::GetHeaders {
+                locator: read_hashes(reader)?,
+            },
+            TAG_HEADERS => {
+                let count = reader.read_u32::<LittleEndian>()? as usize;
+                let mut headers = Vec::with_capacity(count.min(reader.len()));
+                for _ in 0..count {
+                    headers.push(BlockHeader {
+                        attrs: self.read_attrs(reader)?,
+                    });
+                }
+                PeerMessage::Headers { headers }
+            }
+            TAG_GET_PROOF => PeerMessage::GetProof {
+                block_hash: read_hash(reader)?,
+                transaction_hash: read_hash(reader)?,
+            },
+            TAG_PROOF => {
+                let header = Box::new(BlockHeader {
+                    attrs: self.read_attrs(reader)?,
+                });
+                let transaction_hash = read_hash(reader)?;
+                let count = reader.read_u32::<LittleEndian>()? as usize;
+                ensure!(
+                    count.saturating_mul(HASH_LEN + 1) <= reader.len(),
+                    "length prefix exceeds message size"
+                );
+                let mut steps = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let side = match reader.read_u8()? {
+                        SIDE_LEFT => Side::Left,
+                        SIDE_RIGHT => Side::Right,
+                        side => bail!("unknown proof step side: {}", side),
+                    };
+                    steps.push(MerkleStep {
+                        side,
+                        hash: read_hash(reader)?,
+                    });
+                }
+                PeerMessage::Proof {
+                    header,
+                    transaction_hash,
+                    proof: MerkleProof { steps },
+                }
+            }
+            tag => bail!("unknown message tag: {}", tag),
+        };
+        ensure!(data.is_empty(), "trailing bytes after message");
+        Ok(message)
+    }
+
+    fn read_attrs(&mut self, reader: &mut &[u8]) -> Result<BlockAttributes> {
+        let version = reader.read_u32::<LittleEndian>()?;
+        let index = reader.read_u64::<LittleEndian>()?;
+        let reward = reader.read_u64::<LittleEndian>()?;
+        let nonce = reader.read_u64::<LittleEndian>()?;
+        let timestamp = read_timestamp(reader)?;
+        Ok(BlockAttributes {
+            version,
+            index,
+            reward,
+            nonce,
+            timestamp,
+            issuer: self.read_key(reader)?,
+            max_hash: read_hash(reader)?,
+            prev_hash: read_hash(reader)?,
+            merkle_root: read_hash(reader)?,
+        })
+    }
+
+    fn read_transaction(&mut self, reader: &mut &[u8], versioned: bool) -> Result<Transaction> {
+        let version = if versioned {
+            reader.read_u32::<LittleEndian>()?
+        } else {
+            TX_VERSION_1
+        };
+        let amount = reader.read_u64::<LittleEndian>()?;
+        let fee = reader.read_u64::<LittleEndian>()?;
+        let comment = String::from_utf8(read_bytes(reader)?).context("invalid comment")?;
+        let sender = self.read_key(reader)?;
+        let receiver = self.read_key(reader)?;
+
+        let mut extra_outputs = vec![];
+        let mut not_before = None;
+        if version != TX_VERSION_1 {
+            let count = reader.read_u32::<LittleEndian>()? as usize;
+            ensure!(count <= reader.len(), "length prefix exceeds message size");
+            for _ in 0..count {
+                extra_outputs.push(TxOutput {
+                    receiver: self.read_key(reader)?,
+                    amount: reader.read_u64::<LittleEndian>()?,
+                });
+            }
+            not_before = match reader.read_u8()? {
+                LOCK_NONE => None,
+                LOCK_BLOCK_INDEX => Some(LockTime::BlockIndex(reader.read_u64::<LittleEndian>()?)),
+                LOCK_TIMESTAMP => Some(LockTime::Timestamp(read_timestamp(reader)?)),
+                kind => bail!("unknown lock time kind: {}", kind),
+            };
+        }
+
+        Ok(Transaction {
+            version,
+            amount,
+            fee,
+            comment,
+            sender,
+            receiver,
+            extra_outputs,
+            not_before,
+            signature: read_bytes(reader)?,
+        })
+    }
+
+    fn read_key(&mut self, reader: &mut &[u8]) -> Result<WalletId> {
+        match reader.read_u8()? {
+            KEY_INLINE => {
+                let key = WalletId::from_bytes(&read_bytes(reader)?)?;
+                if self.known_keys.len() < MAX_KNOWN_KEYS {
+                    self.known_keys
+                        .entry(compute_key_hash(&key))
+                        .or_insert_with(|| key.clone());
+                }
+                Ok(key)
+            }
+            KEY_REFERENCE => {
+                let mut key_hash = [0u8; KEY_HASH_LEN];
+                reader.read_exact(&mut key_hash)?;
+                self.known_keys
+                    .get(&key_hash)
+                    .cloned()
+                    .context("reference to an unknown key")
+            }
+            kind => bail!("unknown key encoding: {}", kind),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn compute_key_hash(key: &WalletId) -> KeyHash {
+    let mut hasher = Sha3_512::new();
+    key.hash_into(&mut hasher);
+    let digest = hasher.finalize();
+
+    let mut key_hash = [0u8; KEY_HASH_LEN];
+    key_hash.copy_from_slice(&digest[..KEY_HASH_LEN]);
+    key_hash
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes(reader: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    ensure!(len <= reader.len(), "length prefix exceeds message size");
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_hash(reader: &mut &[u8]) -> Result<BlockHash> {
+    let mut hash = [0u8; HASH_LEN];
+    reader.read_exact(&mut hash)?;
+    Ok(hash)
+}
+
+fn read_timestamp(reader: &mut &[u8]) -> Result<DateTime<Utc>> {
+    match Utc.timestamp_opt(reader.read_i64::<LittleEndian>()?, 0) {
+        LocalResult::Single(dt) => Ok(dt),
+        _ => bail!("invalid timestamp"),
+    }
+}
+
+fn read_hashes(reader: &mut &[u8]) -> Result<Vec<BlockHash>> {
+    let count = reader.read_u32::<LittleEndian>()? as usize;
+    ensure!(
+        count.saturating_mul(HASH_LEN) <= reader.len(),
+        "length prefix exceeds message size"
+    );
+    (0..count).map(|_| read_hash(reader)).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain_params::ChainParams,
+        data::{VerifiedBlock, VerifiedTransaction, MAX_HEADERS_PER_MESSAGE},
+        signature::{generate_ed25519_key, SigningKey},
+    };
+
+    fn test_message() -> PeerMessage {
+        let block: Block = serde_json::from_str(include_str!("../data/test_block.json")).unwrap();
+        PeerMessage::Block(Box::new(block))
+    }
+
+    fn round_trip(codec: Codec, messages: &[PeerMessage]) -> Vec<usize> {
+        let mut encoder = MessageEncoder::new(codec);
+        let mut stream = vec![];
+        let mut frame_sizes = vec![];
+        for message in messages.iter() {
+            let frame = encoder.encode(message).unwrap();
+            frame_sizes.push(frame.len());
+            write_frame(&mut stream, codec, &frame).unwrap();
+        }
+
+        let mut decoder = MessageDecoder::new(codec);
+        let mut reader = &stream[..];
+        for message in messages.iter() {
+            let frame = read_frame(&mut reader, codec).unwrap().unwrap();
+            let decoded = decoder.decode(&frame).unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(message).unwrap()
+            );
+        }
+        assert!(read_frame(&mut reader, codec).unwrap().is_none());
+
+        frame_sizes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let block_message = test_message();
+        let block = match &block_message {
+            PeerMessage::Block(block) => block.clone().verified(&ChainParams::mainnet()).unwrap(),
+            _ => unreachable!(),
+        };
+        let header = block.header();
+        let transaction_hash = *block.transactions()[0].hash();
+        let messages = vec![
+            block_message.clone(),
+            block_message,
+            PeerMessage::Request {
+                block_hash: *VerifiedBlock::genesis().hash(),
+            },
+            PeerMessage::GetHeaders {
+                locator: vec![*header.hash(), *VerifiedBlock::genesis().hash()],
+            },
+            PeerMessage::Headers {
+                headers: vec![header.clone().into()],
+            },
+            PeerMessage::GetProof {
+                block_hash: *header.hash(),
+                transaction_hash,
+            },
+            PeerMessage::Proof {
+                header: Box::new(header.into()),
+                transaction_hash,
+                proof: block.inclusion_proof(&transaction_hash).unwrap(),
+            },
+        ];
+
+        let json_sizes = round_trip(Codec::Json, &messages);
+        let binary_sizes = round_trip(Codec::Binary, &messages);
+        let deflate_sizes = round_trip(Codec::BinaryDeflate, &messages);
+
+        assert!(binary_sizes[0] < json_sizes[0]);
+        // Keys are referenced by hash after their first appearance.
+        assert!(binary_sizes[1] < binary_sizes[0] / 2);
+        assert!(deflate_sizes[1] <= binary_sizes[1]);
+    }
+
+    #[test]
+    fn test_transaction_versions() {
+        let key = generate_ed25519_key();
+        let genesis_issuer = Block::genesis().issuer.clone();
+        let v1 =
+            VerifiedTransaction::sign(&key, genesis_issuer.clone(), 10, 1, "v1".into()).unwrap();
+        let v2 = VerifiedTransaction::sign_with_outputs(
+            &key,
+            vec![
+                TxOutput {
+                    receiver: genesis_issuer,
+                    amount: 10,
+                },
+                TxOutput {
+                    receiver: generate_ed25519_key().wallet_id(),
+                    amount: 20,
+                },
+            ],
+            1,
+            "v2".into(),
+            Some(LockTime::Timestamp(
+                Utc.timestamp_opt(1700000000, 0).unwrap(),
+            )),
+        )
+        .unwrap();
+
+        let mut block = match test_message() {
+            PeerMessage::Block(block) => block,
+            _ => unreachable!(),
+        };
+        block.transactions.push(v2.clone().into());
+        let messages = vec![
+            PeerMessage::Transaction(Box::new(v1.into())),
+            PeerMessage::Transaction(Box::new(v2.into())),
+            PeerMessage::Block(block),
+            test_message(),
+        ];
+        round_trip(Codec::Json, &messages);
+        round_trip(Codec::Binary, &messages);
+        round_trip(Codec::BinaryDeflate, &messages);
+
+        // Messages without version 2 transactions are encoded as before versioning.
+        let mut encoder = MessageEncoder::new(Codec::Binary);
+        let tags: Vec<_> = messages
+            .iter()
+            .map(|message| encoder.encode(message).unwrap()[0])
+            .collect();
+        assert_eq!(
+            tags,
+            vec![TAG_TRANSACTION, TAG_TRANSACTION_V2, TAG_BLOCK_V2, TAG_BLOCK]
+        );
+    }
+
+    #[test]
+    fn test_verified_after_decoding() {
+        let mut encoder = MessageEncoder::new(Codec::BinaryDeflate);
+        let frame = encoder.encode(&test_message()).unwrap();
+        let mut decoder = MessageDecoder::new(Codec::BinaryDeflate);
+        decoder
+            .decode(&frame)
+            .unwrap()
+            .verified(&ChainParams::mainnet())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unknown_key_reference() {
+        let mut encoder = MessageEncoder::new(Codec::Binary);
+        encoder.encode(&test_message()).unwrap();
+        let frame = encoder.encode(&test_message()).unwrap();
+
+        let mut decoder = MessageDecoder::new(Codec::Binary);
+        assert!(decoder.decode(&frame).is_err());
+    }
+
+    #[test]
+    fn test_frame_size_limit() {
+        for codec in [Codec::Json, Codec::Binary] {
+            let mut buffer = vec![];
+            write_frame(&mut buffer, codec, &vec![b'x'; MAX_MESSAGE_SIZE]).unwrap();
+            let mut reader = buffer.as_slice();
+            assert_eq!(
+                read_frame(&mut reader, codec).unwrap().unwrap().len(),
+                MAX_MESSAGE_SIZE
+            );
+
+            let mut buffer = vec![];
+            assert!(write_frame(&mut buffer, codec, &vec![b'x'; MAX_MESSAGE_SIZE + 1]).is_err());
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_full_headers_message_fits() {
+        let header = BlockHeader {
+            attrs: BlockAttributes {
+                version: u32::MAX,
+                index: u64::MAX,
+                reward: u64::MAX,
+                nonce: u64::MAX,
+                timestamp: DateTime::<Utc>::MIN_UTC,
+                issuer: WalletId::of_genesis(),
+                max_hash: [255u8; HASH_LEN],
+                prev_hash: [255u8; HASH_LEN],
+                merkle_root: [255u8; HASH_LEN],
+            },
+        };
+        let message = PeerMessage::Headers {
+            headers: vec![header; MAX_HEADERS_PER_MESSAGE],
+        };
+        for codec in [Codec::Json, Codec::Binary, Codec::BinaryDeflate] {
+            let frame = MessageEncoder::new(codec).encode(&message).unwrap();
+            assert!(frame.len() <= MAX_MESSAGE_SIZE, "{:?}", codec);
+        }
+    }
+
+    fn headers_message(issuers: impl Iterator<Item = WalletId>) -> PeerMessage {
+        let block: Block = serde_json::from_str(include_str!("../data/test_block.json")).unwrap();
+        let headers = issuers
+            .map(|issuer| BlockHeader {
+                attrs: BlockAttributes {
+                    issuer,
+                    ..block.attrs.clone()
+                },
+            })
+            .collect();
+        PeerMessage::Headers { headers }
+    }
+
+    #[test]
+    fn test_oversize_message() {
+        let keys: Vec<_> = (0..300)
+            .map(|_| generate_ed25519_key().wallet_id())
+            .collect();
+        let oversize = headers_message(keys.iter().cloned());
+        let normal = headers_message(keys.iter().take(2).cloned());
+
+        for codec in [Codec::Json, Codec::Binary] {
+            let mut encoder = MessageEncoder::new(codec);
+            assert!(encoder.encode(&oversize).is_err());
+            let frame = encoder.encode(&normal).unwrap();
+
+            let decoded = MessageDecoder::new(codec).decode(&frame).unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&normal).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_known_keys_limit() {
+        let mut encoder = MessageEncoder::new(Codec::Binary);
+        let mut decoder = MessageDecoder::new(Codec::Binary);
+        for _ in 0..=MAX_KNOWN_KEYS {
+            let key = generate_ed25519_key().wallet_id();
+            let message = headers_message([key.clone(), key].into_iter());
+            decoder.decode(&encoder.encode(&message).unwrap()).unwrap();
+        }
+        assert_eq!(encoder.sent_keys.hashes.len(), MAX_KNOWN_KEYS);
+        assert_eq!(decoder.known_keys.len(), MAX_KNOWN_KEYS);
+    }
+
+    #[test]
+    fn test_sent_keys_rollback() {
+        let mut keys = SentKeys::default();
+        assert!(keys.insert([1; KEY_HASH_LEN]));
+        keys.commit();
+        assert!(keys.insert([2; KEY_HASH_LEN]));
+        assert!(!keys.insert([1; KEY_HASH_LEN]));
+        keys.rollback();
+        assert!(keys.insert([2; KEY_HASH_LEN]));
+        assert!(!keys.insert([1; KEY_HASH_LEN]));
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let dialer = Hello {
+            codecs: vec![Codec::BinaryDeflate, Codec::Binary],
+        };
+        let acceptor = Hello {
+            codecs: vec![Codec::Binary, Codec::BinaryDeflate],
+        };
+        assert_eq!(Hello::negotiate(&dialer, &acceptor), Codec::BinaryDeflate);
+        assert_eq!(
+            Hello::negotiate(&dialer, &Hello { codecs: vec![] }),
+            Codec::Json
+        );
+
+        let json = serde_json::to_string(&dialer).unwrap();
+        assert_eq!(
+            json,
+            r#"{"kind":"hello","codecs":["binary_deflate","binary"]}"#
+        );
+        assert_eq!(serde_json::from_str::<Hello>(&json).unwrap(), dialer);
+    }
+}
@@ -1,65 +1,44 @@
-use crate::util::{
-    deserialize_base64, deserialize_base64_fixed, deserialize_utc, deserialize_wallet_id,
-    parse_pkcs8_public, serialize_base64, serialize_utc, serialize_wallet_id,
+use crate::{
+    chain_params::ChainParams,
+    merkle::{compute_merkle_root, MerkleHash, MerkleProof},
+    signature::SigningKey,
+    util::{
+        deserialize_base64, deserialize_base64_fixed, deserialize_base64_fixed_vec,
+        deserialize_utc, deserialize_wallet_id, serialize_base64, serialize_base64_vec,
+        serialize_utc, serialize_wallet_id,
+    },
 };
 
 use anyhow::{bail, Context, Result};
 use byteorder::{LittleEndian, WriteBytesExt};
-use chrono::{DateTime, TimeZone, Utc};
-use rsa::{padding::PaddingScheme, PublicKey, PublicKeyParts, RSAPrivateKey, RSAPublicKey};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_512};
 
-use std::{
-    hash::Hash,
-    ops::{Deref, DerefMut},
-};
+use std::ops::{Deref, DerefMut};
+
+pub use crate::signature::WalletId;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub const GENESIS_TIMESTAMP: i64 = 1626002428;
-pub const MAX_REWARD: u64 = 1000;
+/// Version 1 blocks hashed the transaction hashes directly into the block hash
+/// and had a different genesis block.
+pub const BLOCK_VERSION: u32 = 2;
 pub const HASH_LEN: usize = 64;
+/// As many headers with a 4096-bit RSA issuer as fit in a JSON frame.
+pub const MAX_HEADERS_PER_MESSAGE: usize = 54;
+
+/// Version 1 transactions have a single output and no time lock.
+pub const TX_VERSION_1: u32 = 1;
+pub const TX_VERSION: u32 = 2;
+/// The maximal number of outputs of a transaction, including `receiver`.
+pub const MAX_TX_OUTPUTS: usize = 16;
 
 pub type BlockHash = [u8; HASH_LEN];
 pub type TransactionHash = [u8; HASH_LEN];
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Debug, Eq)]
-pub struct WalletId {
-    pub public_key: RSAPublicKey,
-}
-
-impl WalletId {
-    pub fn of_genesis() -> Self {
-        parse_pkcs8_public(include_str!("../data/genesis.crt"))
-            .unwrap()
-            .into()
-    }
-}
-
-impl Hash for WalletId {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.public_key.n().hash(state);
-        self.public_key.e().hash(state);
-    }
-}
-
-impl PartialEq for WalletId {
-    fn eq(&self, other: &Self) -> bool {
-        self.public_key.n() == other.public_key.n() && self.public_key.e() == other.public_key.e()
-    }
-}
-
-impl From<RSAPublicKey> for WalletId {
-    fn from(public_key: RSAPublicKey) -> Self {
-        Self { public_key }
-    }
-}
-
-////////////////////////////////////////////////////////////////////////////////
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "kind")]
 #[serde(rename_all = "lowercase")]
@@ -73,14 +52,104 @@ pub enum PeerMessage {
         )]
         block_hash: BlockHash,
     },
+    #[serde(rename = "get_headers")]
+    GetHeaders {
+        #[serde(
+            serialize_with = "serialize_base64_vec",
+            deserialize_with = "deserialize_base64_fixed_vec::<'_, _, HASH_LEN>"
+        )]
+        locator: Vec<BlockHash>,
+    },
+    Headers {
+        headers: Vec<BlockHeader>,
+    },
+    #[serde(rename = "get_proof")]
+    GetProof {
+        #[serde(
+            serialize_with = "serialize_base64",
+            deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
+        )]
+        block_hash: BlockHash,
+        #[serde(
+            serialize_with = "serialize_base64",
+            deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
+        )]
+        transaction_hash: TransactionHash,
+    },
+    Proof {
+        header: Box<BlockHeader>,
+        #[serde(
+            serialize_with = "serialize_base64",
+            deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
+        )]
+        transaction_hash: TransactionHash,
+        proof: MerkleProof,
+    },
 }
 
 impl PeerMessage {
-    pub fn verified(self) -> Result<VerifiedPeerMessage> {
+    /// The `kind` tag of the message.
+    pub fn kind(&self) -> &'static str {
         match self {
-            Self::Block(block) => Ok(VerifiedPeerMessage::Block(Box::new(block.verified()?))),
+            Self::Block(_) => "block",
+            Self::Transaction(_) => "transaction",
+            Self::Request { .. } => "request",
+            Self::GetHeaders { .. } => "get_headers",
+            Self::Headers { .. } => "headers",
+            Self::GetProof { .. } => "get_proof",
+            Self::Proof { .. } => "proof",
+        }
+    }
+
+    pub fn verified(self, params: &ChainParams) -> Result<VerifiedPeerMessage> {
+        match self {
+            Self::Block(block) => Ok(VerifiedPeerMessage::Block(Box::new(
+                block.verified(params)?,
+            ))),
             Self::Transaction(tx) => Ok(VerifiedPeerMessage::Transaction(Box::new(tx.verified()?))),
             Self::Request { block_hash } => Ok(VerifiedPeerMessage::Request { block_hash }),
+            Self::GetHeaders { locator } => Ok(VerifiedPeerMessage::GetHeaders { locator }),
+            Self::Headers { headers } => {
+                if headers.len() > MAX_HEADERS_PER_MESSAGE {
+                    bail!("too many headers in a message");
+                }
+
+                let mut verified_headers = Vec::with_capacity(headers.len());
+                for header in headers.into_iter() {
+                    verified_headers.push(
+                        header
+                            .verified(params)
+                            .context("header verification failed")?,
+                    );
+                }
+                Ok(VerifiedPeerMessage::Headers {
+                    headers: verified_headers,
+                })
+            }
+            Self::GetProof {
+                block_hash,
+                transaction_hash,
+            } => Ok(VerifiedPeerMessage::GetProof {
+                block_hash,
+                transaction_hash,
+            }),
+            Self::Proof {
+                header,
+                transaction_hash,
+                proof,
+            } => {
+                let header = header
+                    .verified(params)
+                    .context("header verification failed")?;
+                if !header.verify_inclusion(&transaction_hash, &proof) {
+                    bail!("invalid inclusion proof");
+                }
+                Ok(VerifiedPeerMessage::Proof {
+                    header: Box::new(header),
+                    transaction_hash,
+                    proof,
+                })
+            }
         }
     }
 }
@@ -93,6 +162,26 @@ impl From<VerifiedPeerMessage> for PeerMessage {
                 PeerMessage::Transaction(Box::new((*tx).into()))
             }
             VerifiedPeerMessage::Request { block_hash } => PeerMessage::Request { block_hash },
+            VerifiedPeerMessage::GetHeaders { locator } => PeerMessage::GetHeaders { locator },
+            VerifiedPeerMessage::Headers { headers } => PeerMessage::Headers {
+                headers: headers.into_iter().map(|header| header.into()).collect(),
+            },
+            VerifiedPeerMessage::GetProof {
+                block_hash,
+                transaction_hash,
+            } => PeerMessage::GetProof {
+                block_hash,
+                transaction_hash,
+            },
+            VerifiedPeerMessage::Proof {
+                header,
+                transaction_hash,
+                proof,
+            } => PeerMessage::Proof {
+                header: Box::new((*header).into()),
+                transaction_hash,
+                proof,
+            },
         }
     }
 }
@@ -103,13 +192,47 @@ impl From<VerifiedPeerMessage> for PeerMessage {
 pub enum VerifiedPeerMessage {
     Block(Box<VerifiedBlock>),
     Transaction(Box<VerifiedTransaction>),
-    Request { block_hash: BlockHash },
+    Request {
+        block_hash: BlockHash,
+    },
+    GetHeaders {
+        locator: Vec<BlockHash>,
+    },
+    Headers {
+        headers: Vec<VerifiedBlockHeader>,
+    },
+    GetProof {
+        block_hash: BlockHash,
+        transaction_hash: TransactionHash,
+    },
+    /// The proof is already checked against the header.
+    Proof {
+        header: Box<VerifiedBlockHeader>,
+        transaction_hash: TransactionHash,
+        proof: MerkleProof,
+    },
+}
+
+impl VerifiedPeerMessage {
+    /// The `kind` tag of the message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Block(_) => "block",
+            Self::Transaction(_) => "transaction",
+            Self::Request { .. } => "request",
+            Self::GetHeaders { .. } => "get_headers",
+            Self::Headers { .. } => "headers",
+            Self::GetProof { .. } => "get_proof",
+            Self::Proof { .. } => "proof",
+        }
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct BlockAttributes {
+    pub version: u32,
     pub index: u64,
     pub reward: u64,
     pub nonce: u64,
@@ -134,6 +257,60 @@ pub struct BlockAttributes {
         deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
     )]
     pub prev_hash: BlockHash,
+
+    #[serde(
+        serialize_with = "serialize_base64",
+        deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
+    )]
+    pub merkle_root: MerkleHash,
+}
+
+impl BlockAttributes {
+    /// The transactions are committed to by `merkle_root`.
+    pub fn compute_hash(&self) -> BlockHash {
+        let mut hasher = Sha3_512::new();
+        hasher.write_u32::<LittleEndian>(self.version).unwrap();
+        hasher.write_u64::<LittleEndian>(self.index).unwrap();
+        hasher
+            .write_i64::<LittleEndian>(self.timestamp.timestamp())
+            .unwrap();
+        hasher.write_u64::<LittleEndian>(self.reward).unwrap();
+        hasher.write_u64::<LittleEndian>(self.nonce).unwrap();
+        self.issuer.hash_into(&mut hasher);
+        hasher.update(self.max_hash);
+        hasher.update(self.prev_hash);
+        hasher.update(self.merkle_root);
+
+        let digest = hasher.finalize();
+        assert_eq!(digest.len(), HASH_LEN);
+
+        let mut hash = [0u8; HASH_LEN];
+        hash.copy_from_slice(&digest);
+        hash
+    }
+
+    fn verify(&self, params: &ChainParams) -> Result<()> {
+        if self.version != BLOCK_VERSION {
+            bail!(
+                "unsupported block version: expected {}, got {}",
+                BLOCK_VERSION,
+                self.version
+            );
+        }
+        if self.timestamp < params.genesis_timestamp {
+            bail!("block timestamp is less than genesis timestamp");
+        }
+        if self.timestamp > Utc::now() {
+            bail!("block timestamp is greater than now");
+        }
+        if self.reward > params.max_reward {
+            bail!("block reward is greater than max reward");
+        }
+        if self.index == 1 && self.prev_hash != params.genesis_hash() {
+            bail!("block index is 1, but prev_hash != genesis");
+        }
+        Ok(())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -160,51 +337,45 @@ impl DerefMut for Block {
 }
 
 impl Block {
+    /// The mainnet genesis block, see `ChainParams::genesis_block()`.
     pub fn genesis() -> Block {
-        Block {
-            attrs: BlockAttributes {
-                index: 0,
-                timestamp: Utc.timestamp(GENESIS_TIMESTAMP, 0),
-                reward: 0,
-                nonce: 0,
-                issuer: WalletId::of_genesis(),
-                max_hash: [255u8; HASH_LEN],
-                prev_hash: [0u8; HASH_LEN],
-            },
-            transactions: vec![],
-        }
+        ChainParams::mainnet().genesis_block()
     }
 
     pub fn compute_hash(&self) -> BlockHash {
-        Self::compute_hash_inner(
-            &self.attrs,
-            self.transactions.iter().map(|tx| tx.compute_hash()),
-        )
+        self.attrs.compute_hash()
     }
 
-    pub fn verified(self) -> Result<VerifiedBlock> {
-        if self.timestamp.timestamp() < GENESIS_TIMESTAMP {
-            bail!("block timestamp is less than genesis timestamp");
-        }
-        if self.timestamp > Utc::now() {
-            bail!("block timestamp is greater than now");
-        }
-        if self.reward > MAX_REWARD {
-            bail!("block reward is greater than max reward");
-        }
-        if self.index == 0 && self != Self::genesis() {
+    pub fn compute_merkle_root(&self) -> MerkleHash {
+        let hashes: Vec<_> = self
+            .transactions
+            .iter()
+            .map(|tx| tx.compute_hash())
+            .collect();
+        compute_merkle_root(&hashes)
+    }
+
+    pub fn verified(self, params: &ChainParams) -> Result<VerifiedBlock> {
+        self.attrs.verify(params)?;
+        if self.index == 0 && self != params.genesis_block() {
             bail!("block index is 0, but not the genesis block");
         }
-        if self.index == 1 && self.prev_hash != VerifiedBlock::genesis().hash {
-            bail!("block index is 1, but prev_hash != genesis");
-        }
 
         let mut transactions = Vec::with_capacity(self.transactions.len());
         for tx in self.transactions.into_iter() {
-            transactions.push(tx.verified().context("transaction verification failed")?);
+            let tx = tx.verified().context("transaction verification failed")?;
+            if !tx.is_final(self.attrs.index, self.attrs.timestamp) {
+                bail!("transaction is locked until {:?}", tx.not_before.unwrap());
+            }
+            transactions.push(tx);
         }
 
-        let hash = Self::compute_hash_inner(&self.attrs, transactions.iter().map(|tx| *tx.hash()));
+        let transaction_hashes: Vec<_> = transactions.iter().map(|tx| *tx.hash()).collect();
+        if compute_merkle_root(&transaction_hashes) != self.attrs.merkle_root {
+            bail!("merkle_root doesn't match the transactions");
+        }
+
+        let hash = self.attrs.compute_hash();
         if hash > self.attrs.max_hash {
             bail!("block hash is greater than max_hash");
         }
@@ -215,33 +386,6 @@ impl Block {
             hash,
         })
     }
-
-    fn compute_hash_inner(
-        attrs: &BlockAttributes,
-        transaction_hashes: impl IntoIterator<Item = TransactionHash>,
-    ) -> BlockHash {
-        let mut hasher = Sha3_512::new();
-        hasher.write_u64::<LittleEndian>(attrs.index).unwrap();
-        hasher
-            .write_i64::<LittleEndian>(attrs.timestamp.timestamp())
-            .unwrap();
-        hasher.write_u64::<LittleEndian>(attrs.reward).unwrap();
-        hasher.write_u64::<LittleEndian>(attrs.nonce).unwrap();
-        hasher.update(attrs.issuer.public_key.n().to_bytes_le());
-        hasher.update(attrs.issuer.public_key.e().to_bytes_le());
-        hasher.update(&attrs.max_hash);
-        hasher.update(&attrs.prev_hash);
-        for tx_hash in transaction_hashes.into_iter() {
-            hasher.update(tx_hash);
-        }
-
-        let digest = hasher.finalize();
-        assert_eq!(digest.len(), HASH_LEN);
-
-        let mut hash = [0u8; HASH_LEN];
-        hash.copy_from_slice(&digest);
-        hash
-    }
 }
 
 impl From<VerifiedBlock> for Block {
@@ -271,8 +415,9 @@ impl Deref for VerifiedBlock {
 }
 
 impl VerifiedBlock {
+    /// The mainnet genesis block.
     pub fn genesis() -> VerifiedBlock {
-        Block::genesis().verified().unwrap()
+        ChainParams::mainnet().verified_genesis_block()
     }
 
     pub fn hash(&self) -> &BlockHash {
@@ -283,6 +428,22 @@ impl VerifiedBlock {
         &self.transactions
     }
 
+    pub fn header(&self) -> VerifiedBlockHeader {
+        VerifiedBlockHeader {
+            attrs: self.attrs.clone(),
+            hash: self.hash,
+        }
+    }
+
+    /// Returns `None` if the transaction is not in the block.
+    pub fn inclusion_proof(&self, transaction_hash: &TransactionHash) -> Option<MerkleProof> {
+        let transaction_hashes: Vec<_> = self.transactions.iter().map(|tx| *tx.hash()).collect();
+        let index = transaction_hashes
+            .iter()
+            .position(|hash| hash == transaction_hash)?;
+        MerkleProof::build(&transaction_hashes, index)
+    }
+
     pub fn to_block(&self) -> Block {
         Block {
             attrs: self.attrs.clone(),
@@ -297,8 +458,120 @@ impl VerifiedBlock {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockHeader {
+    #[serde(flatten)]
+    pub attrs: BlockAttributes,
+}
+
+impl Deref for BlockHeader {
+    type Target = BlockAttributes;
+
+    fn deref(&self) -> &Self::Target {
+        &self.attrs
+    }
+}
+
+impl BlockHeader {
+    pub fn compute_hash(&self) -> BlockHash {
+        self.attrs.compute_hash()
+    }
+
+    /// Checks everything `Block::verified` does, except for the transactions themselves.
+    pub fn verified(self, params: &ChainParams) -> Result<VerifiedBlockHeader> {
+        self.attrs.verify(params)?;
+        if self.index == 0 && self.attrs != params.genesis_block().attrs {
+            bail!("block index is 0, but not the genesis block");
+        }
+
+        let hash = self.compute_hash();
+        if hash > self.attrs.max_hash {
+            bail!("block hash is greater than max_hash");
+        }
+
+        Ok(VerifiedBlockHeader {
+            attrs: self.attrs,
+            hash,
+        })
+    }
+}
+
+impl From<VerifiedBlockHeader> for BlockHeader {
+    fn from(other: VerifiedBlockHeader) -> Self {
+        Self { attrs: other.attrs }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedBlockHeader {
+    attrs: BlockAttributes,
+    hash: BlockHash,
+}
+
+impl Deref for VerifiedBlockHeader {
+    type Target = BlockAttributes;
+
+    fn deref(&self) -> &Self::Target {
+        &self.attrs
+    }
+}
+
+impl VerifiedBlockHeader {
+    pub fn hash(&self) -> &BlockHash {
+        &self.hash
+    }
+
+    /// Checks that the transaction is included in the block, without the block itself.
+    pub fn verify_inclusion(
+        &self,
+        transaction_hash: &TransactionHash,
+        proof: &MerkleProof,
+    ) -> bool {
+        proof.verify(transaction_hash, &self.merkle_root)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TxOutput {
+    #[serde(
+        serialize_with = "serialize_wallet_id",
+        deserialize_with = "deserialize_wallet_id"
+    )]
+    pub receiver: WalletId,
+    pub amount: u64,
+}
+
+/// The earliest block a transaction can be included in.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockTime {
+    BlockIndex(u64),
+    Timestamp(
+        #[serde(serialize_with = "serialize_utc", deserialize_with = "deserialize_utc")]
+        DateTime<Utc>,
+    ),
+}
+
+impl LockTime {
+    pub fn is_reached(&self, index: u64, timestamp: DateTime<Utc>) -> bool {
+        match self {
+            Self::BlockIndex(not_before) => index >= *not_before,
+            Self::Timestamp(not_before) => timestamp >= *not_before,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Transaction {
+    /// Omitted in JSON for version 1, so that version 1 transactions are
+    /// serialized the same way as before versioning.
+    #[serde(default = "tx_version_1", skip_serializing_if = "is_tx_version_1")]
+    pub version: u32,
+
     pub amount: u64,
     pub fee: u64,
     pub comment: String,
@@ -315,6 +588,14 @@ pub struct Transaction {
     )]
     pub receiver: WalletId,
 
+    /// Outputs besides `receiver`, version 2 only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_outputs: Vec<TxOutput>,
+
+    /// Version 2 only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<LockTime>,
+
     #[serde(
         serialize_with = "serialize_base64",
         deserialize_with = "deserialize_base64"
@@ -322,28 +603,105 @@ pub struct Transaction {
     pub signature: Vec<u8>,
 }
 
+fn tx_version_1() -> u32 {
+    TX_VERSION_1
+}
+
+fn is_tx_version_1(version: &u32) -> bool {
+    *version == TX_VERSION_1
+}
+
 impl Transaction {
     pub fn verified(self) -> Result<VerifiedTransaction> {
+        self.validate()?;
         let hash = self.compute_hash();
 
-        self.sender.public_key.verify(
-            PaddingScheme::PKCS1v15Sign { hash: None },
-            &hash,
-            &self.signature,
-        )?;
+        self.sender.verify(&hash, &self.signature)?;
 
         Ok(VerifiedTransaction { inner: self, hash })
     }
 
+    fn validate(&self) -> Result<()> {
+        match self.version {
+            TX_VERSION_1 => {
+                if !self.extra_outputs.is_empty() || self.not_before.is_some() {
+                    bail!("version 1 transaction can't have extra outputs or a time lock");
+                }
+            }
+            TX_VERSION => {
+                if self.extra_outputs.len() + 1 > MAX_TX_OUTPUTS {
+                    bail!("transaction has more than {} outputs", MAX_TX_OUTPUTS);
+                }
+                if self.outputs().any(|(_, amount)| amount == 0) {
+                    bail!("transaction has an empty output");
+                }
+            }
+            version => bail!("unsupported transaction version: {}", version),
+        }
+        self.total_spent()
+            .context("transaction amount overflows u64")?;
+        Ok(())
+    }
+
+    /// `receiver` with `amount` first, then `extra_outputs`.
+    pub fn outputs(&self) -> impl Iterator<Item = (&WalletId, u64)> {
+        std::iter::once((&self.receiver, self.amount)).chain(
+            self.extra_outputs
+                .iter()
+                .map(|output| (&output.receiver, output.amount)),
+        )
+    }
+
+    /// The amounts of all the outputs plus the fee, or `None` on overflow.
+    pub fn total_spent(&self) -> Option<u64> {
+        self.outputs()
+            .try_fold(self.fee, |total, (_, amount)| total.checked_add(amount))
+    }
+
+    /// Whether the transaction can be included in a block with the given index
+    /// and timestamp.
+    pub fn is_final(&self, index: u64, timestamp: DateTime<Utc>) -> bool {
+        self.not_before
+            .is_none_or(|not_before| not_before.is_reached(index, timestamp))
+    }
+
     pub fn compute_hash(&self) -> TransactionHash {
         let mut hasher = Sha3_512::new();
-        hasher.write_u64::<LittleEndian>(self.amount).unwrap();
-        hasher.write_u64::<LittleEndian>(self.fee).unwrap();
-        hasher.update(self.comment.as_bytes());
-        hasher.update(self.sender.public_key.n().to_bytes_le());
-        hasher.update(self.sender.public_key.e().to_bytes_le());
-        hasher.update(self.receiver.public_key.n().to_bytes_le());
-        hasher.update(self.receiver.public_key.e().to_bytes_le());
+        if self.version == TX_VERSION_1 {
+            hasher.write_u64::<LittleEndian>(self.amount).unwrap();
+            hasher.write_u64::<LittleEndian>(self.fee).unwrap();
+            hasher.update(self.comment.as_bytes());
+            self.sender.hash_into(&mut hasher);
+            self.receiver.hash_into(&mut hasher);
+        } else {
+            hasher.write_u32::<LittleEndian>(self.version).unwrap();
+            hasher.write_u64::<LittleEndian>(self.fee).unwrap();
+            hasher
+                .write_u32::<LittleEndian>(self.comment.len() as u32)
+                .unwrap();
+            hasher.update(self.comment.as_bytes());
+            self.sender.hash_into(&mut hasher);
+            hasher
+                .write_u32::<LittleEndian>(self.extra_outputs.len() as u32 + 1)
+                .unwrap();
+            for (receiver, amount) in self.outputs() {
+                receiver.hash_into(&mut hasher);
+                hasher.write_u64::<LittleEndian>(amount).unwrap();
+            }
+            match self.not_before {
+                None => hasher.update([0u8]),
+                Some(LockTime::BlockIndex(index)) => {
+                    hasher.update([1u8]);
+                    hasher.write_u64::<LittleEndian>(index).unwrap();
+                }
+                Some(LockTime::Timestamp(timestamp)) => {
+                    hasher.update([2u8]);
+                    hasher
+                        .write_i64::<LittleEndian>(timestamp.timestamp())
+                        .unwrap();
+                }
+            }
+        }
 
         let digest = hasher.finalize();
         assert_eq!(digest.len(), HASH_LEN);
@@ -384,24 +742,64 @@ impl VerifiedTransaction {
         &self.hash
     }
 
+    /// Signs a version 1 transaction.
     pub fn sign(
-        sender: &RSAPrivateKey,
+        sender: &impl SigningKey,
         receiver: WalletId,
         amount: u64,
         fee: u64,
         comment: String,
     ) -> Result<VerifiedTransaction> {
-        let mut transaction = Transaction {
-            sender: sender.to_public_key().into(),
-            signature: vec![],
-            receiver,
-            amount,
-            fee,
-            comment,
-        };
+        Self::sign_transaction(
+            sender,
+            Transaction {
+                version: TX_VERSION_1,
+                sender: sender.wallet_id(),
+                signature: vec![],
+                receiver,
+                amount,
+                fee,
+                comment,
+                extra_outputs: vec![],
+                not_before: None,
+            },
+        )
+    }
+
+    /// Signs a version 2 transaction. The first of `outputs` becomes `receiver`
+    /// and `amount`.
+    pub fn sign_with_outputs(
+        sender: &impl SigningKey,
+        outputs: Vec<TxOutput>,
+        fee: u64,
+        comment: String,
+        not_before: Option<LockTime>,
+    ) -> Result<VerifiedTransaction> {
+        let mut outputs = outputs.into_iter();
+        let first = outputs.next().context("transaction must have an output")?;
+        Self::sign_transaction(
+            sender,
+            Transaction {
+                version: TX_VERSION,
+                sender: sender.wallet_id(),
+                signature: vec![],
+                receiver: first.receiver,
+                amount: first.amount,
+                fee,
+                comment,
+                extra_outputs: outputs.collect(),
+                not_before,
+            },
+        )
+    }
 
+    fn sign_transaction(
+        sender: &impl SigningKey,
+        mut transaction: Transaction,
+    ) -> Result<VerifiedTransaction> {
+        transaction.validate()?;
         let hash = transaction.compute_hash();
-        transaction.signature = sender.sign(PaddingScheme::PKCS1v15Sign { hash: None }, &hash)?;
+        transaction.signature = sender.sign(&hash)?;
 
         Ok(VerifiedTransaction {
             inner: transaction,
@@ -415,12 +813,14 @@ impl VerifiedTransaction {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::parse_pkcs8_private;
+    use crate::{signature::generate_ed25519_key, util::parse_pkcs8_private};
+
+    use chrono::TimeZone;
 
     #[test]
     fn test_genesis() {
         VerifiedBlock::genesis();
-        Block::genesis().verified().unwrap();
+        Block::genesis().verified(&ChainParams::mainnet()).unwrap();
     }
 
     #[test]
@@ -433,36 +833,221 @@ mod tests {
 
     #[test]
     fn test_block_json() {
+        let params = ChainParams::mainnet();
         let block: Block = serde_json::from_str(include_str!("../data/test_block.json")).unwrap();
-        let verified = block.verified().unwrap();
+        let verified = block.verified(&params).unwrap();
 
         let priv_key = parse_pkcs8_private(include_str!("../data/test.pem")).unwrap();
         let genesis = VerifiedBlock::genesis();
+        let tx = VerifiedTransaction::sign(&priv_key, genesis.issuer.clone(), 500, 30, "hi".into())
+            .unwrap();
 
         assert_eq!(
             verified,
             Block {
                 attrs: BlockAttributes {
+                    version: BLOCK_VERSION,
                     index: 1,
-                    reward: MAX_REWARD,
+                    reward: params.max_reward,
                     nonce: 27532,
                     timestamp: Utc.timestamp(1626003028, 0),
                     issuer: priv_key.to_public_key().into(),
                     max_hash: [255u8; HASH_LEN],
                     prev_hash: *genesis.hash(),
+                    merkle_root: compute_merkle_root(&[*tx.hash()]),
                 },
-                transactions: vec![VerifiedTransaction::sign(
-                    &priv_key,
-                    genesis.issuer.clone(),
-                    500,
-                    30,
-                    "hi".into()
-                )
-                .unwrap()
-                .into(),],
+                transactions: vec![tx.into()],
             }
-            .verified()
+            .verified(&params)
             .unwrap()
         );
     }
+
+    #[test]
+    fn test_block_header() {
+        let params = ChainParams::mainnet();
+        let block: Block = serde_json::from_str(include_str!("../data/test_block.json")).unwrap();
+        let verified = block.verified(&params).unwrap();
+
+        let header: BlockHeader = verified.header().into();
+        assert_eq!(header.compute_hash(), *verified.hash());
+
+        let message = PeerMessage::Headers {
+            headers: vec![header.clone()],
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        let verified_message = serde_json::from_str::<PeerMessage>(&json)
+            .unwrap()
+            .verified(&params)
+            .unwrap();
+        match verified_message {
+            VerifiedPeerMessage::Headers { headers } => {
+                assert_eq!(headers, vec![verified.header()]);
+            }
+            _ => panic!("expected headers message"),
+        }
+
+        let mut bad_header = header;
+        bad_header.attrs.max_hash = [0u8; HASH_LEN];
+        assert!(bad_header.verified(&params).is_err());
+
+        BlockHeader::from(VerifiedBlock::genesis().header())
+            .verified(&params)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_inclusion_proof() {
+        let params = ChainParams::mainnet();
+        let block: Block = serde_json::from_str(include_str!("../data/test_block.json")).unwrap();
+        let verified = block.clone().verified(&params).unwrap();
+        let tx_hash = *verified.transactions()[0].hash();
+
+        let proof = verified.inclusion_proof(&tx_hash).unwrap();
+        assert!(verified.header().verify_inclusion(&tx_hash, &proof));
+        assert!(verified.inclusion_proof(&[0u8; HASH_LEN]).is_none());
+
+        let message = PeerMessage::Proof {
+            header: Box::new(verified.header().into()),
+            transaction_hash: tx_hash,
+            proof: proof.clone(),
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        serde_json::from_str::<PeerMessage>(&json)
+            .unwrap()
+            .verified(&params)
+            .unwrap();
+
+        let bad_message = PeerMessage::Proof {
+            header: Box::new(VerifiedBlock::genesis().header().into()),
+            transaction_hash: tx_hash,
+            proof,
+        };
+        assert!(bad_message.verified(&params).is_err());
+
+        let mut bad_block = block;
+        bad_block.transactions.clear();
+        assert!(bad_block.verified(&params).is_err());
+    }
+
+    #[test]
+    fn test_transaction_versions() {
+        let key = generate_ed25519_key();
+        let receiver = generate_ed25519_key().wallet_id();
+
+        let v1 = VerifiedTransaction::sign(&key, receiver.clone(), 10, 1, "v1".into()).unwrap();
+        let json = serde_json::to_value(&*v1).unwrap();
+        for field in ["version", "extra_outputs", "not_before"] {
+            assert!(json.get(field).is_none());
+        }
+        let parsed: Transaction = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.version, TX_VERSION_1);
+        assert_eq!(*parsed.verified().unwrap().hash(), *v1.hash());
+
+        let outputs = vec![
+            TxOutput {
+                receiver: receiver.clone(),
+                amount: 10,
+            },
+            TxOutput {
+                receiver: key.wallet_id(),
+                amount: 20,
+            },
+        ];
+        let not_before = Some(LockTime::Timestamp(
+            Utc.timestamp_opt(1700000000, 0).unwrap(),
+        ));
+        let v2 = VerifiedTransaction::sign_with_outputs(&key, outputs, 1, "v2".into(), not_before)
+            .unwrap();
+        assert_eq!(v2.total_spent(), Some(31));
+        let json = serde_json::to_string(&*v2).unwrap();
+        let parsed: Transaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, *v2);
+        parsed.clone().verified().unwrap();
+
+        let mut tampered = parsed.clone();
+        tampered.extra_outputs[0].amount = 1;
+        assert!(tampered.verified().is_err());
+
+        for index in 0..2 {
+            let mut outputs = vec![
+                TxOutput {
+                    receiver: receiver.clone(),
+                    amount: 10,
+                },
+                TxOutput {
+                    receiver: key.wallet_id(),
+                    amount: 20,
+                },
+            ];
+            outputs[index].amount = 0;
+            assert!(
+                VerifiedTransaction::sign_with_outputs(&key, outputs, 1, "".into(), None).is_err()
+            );
+        }
+
+        let mut unlocked = parsed.clone();
+        unlocked.not_before = None;
+        assert!(unlocked.verified().is_err());
+
+        let mut downgraded = parsed.clone();
+        downgraded.version = TX_VERSION_1;
+        assert!(downgraded.verified().is_err());
+
+        let mut unknown = parsed;
+        unknown.version = 3;
+        assert!(unknown.verified().is_err());
+
+        let too_many = (0..MAX_TX_OUTPUTS + 1)
+            .map(|_| TxOutput {
+                receiver: receiver.clone(),
+                amount: 1,
+            })
+            .collect();
+        assert!(
+            VerifiedTransaction::sign_with_outputs(&key, too_many, 0, "".into(), None).is_err()
+        );
+
+        let overflow = vec![
+            TxOutput {
+                receiver: receiver.clone(),
+                amount: u64::MAX,
+            },
+            TxOutput {
+                receiver,
+                amount: 1,
+            },
+        ];
+        assert!(
+            VerifiedTransaction::sign_with_outputs(&key, overflow, 0, "".into(), None).is_err()
+        );
+    }
+
+    #[test]
+    fn test_time_locked_transaction() {
+        let params = ChainParams::mainnet();
+        let key = generate_ed25519_key();
+        let tx = VerifiedTransaction::sign_with_outputs(
+            &key,
+            vec![TxOutput {
+                receiver: Block::genesis().issuer.clone(),
+                amount: 1,
+            }],
+            0,
+            "locked".into(),
+            Some(LockTime::BlockIndex(2)),
+        )
+        .unwrap();
+
+        let mut block = Block::genesis();
+        block.index = 1;
+        block.timestamp += chrono::Duration::seconds(10);
+        block.prev_hash = *VerifiedBlock::genesis().hash();
+        block.transactions = vec![tx.into()];
+        block.merkle_root = block.compute_merkle_root();
+        assert!(block.clone().verified(&params).is_err());
+
+        block.index = 2;
+        block.verified(&params).unwrap();
+    }
 }
@@ -0,0 +1,369 @@
+use crate::{
+    block_forest::BlockForest,
+    chain_params::ChainParams,
+    codec::{read_frame, write_frame, Codec, MessageDecoder, MessageEncoder},
+    data::{BlockHash, PeerMessage, VerifiedPeerMessage, WalletId, MAX_HEADERS_PER_MESSAGE},
+    util::{serialize_base64, serialize_wallet_id},
+};
+
+use anyhow::{Context, Result};
+use crossbeam::channel::{self, RecvTimeoutError};
+use log::*;
+use serde::{Serialize, Serializer};
+use sha3::{Digest, Sha3_512};
+
+use std::{
+    fmt::Write as _,
+    io::BufReader,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const SHORT_LEN: usize = 8;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeStatus {
+    /// A valid block outside of the head.
+    Known,
+    Head,
+    /// A block that failed validation, or a descendant of one.
+    Bad,
+    /// A block known only as the parent of another block.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SnapshotNode {
+    #[serde(serialize_with = "serialize_base64")]
+    pub hash: BlockHash,
+    pub status: NodeStatus,
+    /// `None` for the bad and unknown blocks, whose contents are not kept.
+    pub index: Option<u64>,
+    #[serde(serialize_with = "serialize_issuer")]
+    pub issuer: Option<WalletId>,
+    pub tx_count: usize,
+}
+
+/// A link from a block to its parent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SnapshotEdge {
+    #[serde(serialize_with = "serialize_base64")]
+    pub parent: BlockHash,
+    #[serde(serialize_with = "serialize_base64")]
+    pub child: BlockHash,
+}
+
+/// Point-in-time view of a `BlockForest`, see `BlockForest::snapshot()`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ForestSnapshot {
+    #[serde(serialize_with = "serialize_base64")]
+    pub head: BlockHash,
+    /// Known blocks ordered by index, then the bad and the unknown ones.
+    pub nodes: Vec<SnapshotNode>,
+    pub edges: Vec<SnapshotEdge>,
+}
+
+impl ForestSnapshot {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize snapshot")
+    }
+
+    /// Renders the snapshot as a Graphviz graph, edges go from parents to children.
+    pub fn to_dot(&self) -> String {
+        let mut output = String::new();
+        writeln!(output, "digraph forest {{").unwrap();
+        writeln!(output, "    rankdir=LR;").unwrap();
+        writeln!(output, "    node [shape=box];").unwrap();
+        for node in self.nodes.iter() {
+            let short_hash = short(&base64::encode(node.hash));
+            let (label, attrs) = match node.status {
+                NodeStatus::Unknown => (format!("? {}", short_hash), ", style=dashed"),
+                NodeStatus::Bad => (format!("bad {}", short_hash), ", color=red"),
+                status => {
+                    let issuer = node
+                        .issuer
+                        .as_ref()
+                        .map_or_else(|| "?".to_string(), fingerprint);
+                    let label = format!(
+                        "#{} {}\\nissuer {}\\n{} txs",
+                        node.index.unwrap_or_default(),
+                        short_hash,
+                        issuer,
+                        node.tx_count
+                    );
+                    let attrs = if status == NodeStatus::Head {
+                        ", style=bold"
+                    } else {
+                        ""
+                    };
+                    (label, attrs)
+                }
+            };
+            writeln!(
+                output,
+                "    \"{}\" [label=\"{}\"{}];",
+                base64::encode(node.hash),
+                label,
+                attrs
+            )
+            .unwrap();
+        }
+        for edge in self.edges.iter() {
+            writeln!(
+                output,
+                "    \"{}\" -> \"{}\";",
+                base64::encode(edge.parent),
+                base64::encode(edge.child)
+            )
+            .unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+        output
+    }
+}
+
+fn serialize_issuer<S>(issuer: &Option<WalletId>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match issuer {
+        Some(issuer) => serialize_wallet_id(issuer, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Short digest of the wallet, since the keys themselves share long prefixes.
+fn fingerprint(wallet: &WalletId) -> String {
+    match wallet.to_bytes() {
+        Ok(bytes) => short(&base64::encode(Sha3_512::digest(&bytes))),
+        Err(_) => "?".to_string(),
+    }
+}
+
+fn short(value: &str) -> String {
+    value.chars().take(SHORT_LEN).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Connects to the node at `address` and collects the blocks it sends for `duration`:
+/// its head chain, which is downloaded with headers requests, and every block it
+/// gossips, along with the missing ancestors of those blocks.
+pub fn fetch_forest(address: &str, chain: ChainParams, duration: Duration) -> Result<BlockForest> {
+    let stream =
+        TcpStream::connect(address).with_context(|| format!("failed to connect to {}", address))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let (sender, receiver) = channel::unbounded();
+    thread::spawn(move || {
+        let mut decoder = MessageDecoder::new(Codec::Json);
+        loop {
+            let message = read_frame(&mut reader, Codec::Json)
+                .and_then(|frame| frame.map(|frame| decoder.decode(&frame)).transpose());
+            match message {
+                Ok(Some(message)) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("failed to read message: {:#}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut forest = BlockForest::with_params(chain.clone(), Default::default());
+    let mut encoder = MessageEncoder::new(Codec::Json);
+    let mut send = |message: PeerMessage| -> Result<()> {
+        let frame = encoder.encode(&message)?;
+        write_frame(&mut writer, Codec::Json, &frame).context("failed to send message")
+    };
+
+    send(PeerMessage::GetHeaders {
+        locator: forest.block_locator(),
+    })?;
+
+    let deadline = Instant::now() + duration;
+    loop {
+        let message = match receiver.recv_deadline(deadline) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => {
+                warn!("node {} closed the connection", address);
+                break;
+            }
+        };
+        let message = match message.verified(&chain) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("node {} sent an invalid message: {:#}", address, err);
+                continue;
+            }
+        };
+
+        match message {
+            VerifiedPeerMessage::Block(block) => {
+                let prev_hash = block.prev_hash;
+                if let Err(err) = forest.add_block(*block) {
+                    debug!("block rejected: {:#}", err);
+                }
+                if forest.unknown_block_hashes().contains(&prev_hash) {
+                    send(PeerMessage::Request {
+                        block_hash: prev_hash,
+                    })?;
+                }
+            }
+            VerifiedPeerMessage::Headers { headers } => {
+                let is_full = headers.len() == MAX_HEADERS_PER_MESSAGE;
+                let missing_hashes = match forest.add_headers(headers) {
+                    Ok(hashes) => hashes,
+                    Err(err) => {
+                        warn!("node {} sent bad headers: {:#}", address, err);
+                        continue;
+                    }
+                };
+                for block_hash in missing_hashes {
+                    send(PeerMessage::Request { block_hash })?;
+                }
+                if is_full {
+                    send(PeerMessage::GetHeaders {
+                        locator: forest.block_locator(),
+                    })?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(forest)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::VerifiedBlock;
+
+    use chrono::Duration as ChronoDuration;
+
+    use std::net::TcpListener;
+
+    fn make_block(params: &ChainParams, prev: &VerifiedBlock, seconds: i64) -> VerifiedBlock {
+        let mut block = params.genesis_block();
+        block.index = prev.index + 1;
+        block.prev_hash = *prev.hash();
+        block.timestamp = prev.timestamp + ChronoDuration::seconds(seconds);
+        block.verified(params).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let params = ChainParams::mainnet();
+        let genesis = params.verified_genesis_block();
+        let first = make_block(&params, &genesis, 10);
+        let second = make_block(&params, &first, 10);
+        let competing = make_block(&params, &genesis, 11);
+        let bad = make_block(&params, &first, 0);
+        let orphan = make_block(&params, &make_block(&params, &second, 10), 10);
+
+        let mut forest = BlockForest::new();
+        for block in [&first, &second, &competing, &orphan] {
+            forest.add_block(block.clone()).unwrap();
+        }
+        assert!(forest.add_block(bad.clone()).is_err());
+
+        let snapshot = forest.snapshot();
+        assert_eq!(snapshot.head, *second.hash());
+        let nodes: Vec<_> = snapshot
+            .nodes
+            .iter()
+            .map(|node| (node.hash, node.status, node.index))
+            .collect();
+        assert_eq!(nodes.len(), 7);
+        assert_eq!(nodes[0], (*genesis.hash(), NodeStatus::Known, Some(0)));
+        assert!(nodes.contains(&(*second.hash(), NodeStatus::Head, Some(2))));
+        assert!(nodes.contains(&(*competing.hash(), NodeStatus::Known, Some(1))));
+        assert!(nodes.contains(&(*orphan.hash(), NodeStatus::Known, Some(4))));
+        assert_eq!(nodes[5], (*bad.hash(), NodeStatus::Bad, None));
+        assert_eq!(nodes[6], (orphan.prev_hash, NodeStatus::Unknown, None));
+        assert_eq!(snapshot.nodes[0].issuer, Some(genesis.issuer.clone()));
+
+        assert_eq!(snapshot.edges.len(), 4);
+        assert!(snapshot.edges.contains(&SnapshotEdge {
+            parent: orphan.prev_hash,
+            child: *orphan.hash(),
+        }));
+
+        let json: serde_json::Value = serde_json::from_str(&snapshot.to_json().unwrap()).unwrap();
+        assert_eq!(json["head"], base64::encode(second.hash()));
+        assert_eq!(json["nodes"][6]["status"], "unknown");
+        assert!(json["nodes"][6]["issuer"].is_null());
+
+        let dot = snapshot.to_dot();
+        assert!(dot.starts_with("digraph forest {\n"));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\";",
+            base64::encode(first.hash()),
+            base64::encode(second.hash())
+        )));
+        assert!(dot.contains(", style=bold];"));
+        assert!(dot.contains(", color=red];"));
+        assert!(dot.contains(", style=dashed];"));
+    }
+
+    #[test]
+    fn test_fetch_forest() {
+        let params = ChainParams::regtest();
+        let mut chain = vec![params.verified_genesis_block()];
+        for _ in 0..MAX_HEADERS_PER_MESSAGE + 5 {
+            let block = make_block(&params, chain.last().unwrap(), 1);
+            chain.push(block);
+        }
+        let mut source = BlockForest::with_params(params.clone(), Default::default());
+        for block in chain.iter().skip(1) {
+            source.add_block(block.clone()).unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut decoder = MessageDecoder::new(Codec::Json);
+            let mut encoder = MessageEncoder::new(Codec::Json);
+            while let Ok(Some(frame)) = read_frame(&mut reader, Codec::Json) {
+                let reply = match decoder.decode(&frame).unwrap() {
+                    PeerMessage::GetHeaders { locator } => PeerMessage::Headers {
+                        headers: source
+                            .find_headers(&locator, MAX_HEADERS_PER_MESSAGE)
+                            .into_iter()
+                            .map(|header| header.into())
+                            .collect(),
+                    },
+                    PeerMessage::Request { block_hash } => PeerMessage::Block(Box::new(
+                        source.find_block(&block_hash).unwrap().to_block(),
+                    )),
+                    _ => continue,
+                };
+                let frame = encoder.encode(&reply).unwrap();
+                write_frame(&mut writer, Codec::Json, &frame).unwrap();
+            }
+        });
+
+        let forest = fetch_forest(&address, params, Duration::from_secs(2)).unwrap();
+        assert_eq!(forest.head().hash(), chain.last().unwrap().hash());
+    }
+}
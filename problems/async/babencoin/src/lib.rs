@@ -1,6 +1,15 @@
 #![forbid(unsafe_code)]
 
 pub mod block_forest;
+pub mod chain_params;
+pub mod codec;
 pub mod data;
+pub mod explorer;
+pub mod mempool;
+pub mod merkle;
+pub mod metrics;
+pub mod mining;
 pub mod node;
+pub mod signature;
+pub mod sim;
 pub mod util;
@@ -1,12 +1,17 @@
 #![forbid(unsafe_code)]
 
-use babencoin::node::{run_forever, Config};
+use babencoin::{
+    chain_params::Network,
+    explorer::fetch_forest,
+    node::{run_forever, Config},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use humantime_serde::re::humantime;
 use log::*;
 use structopt::StructOpt;
 
-use std::{fs::File, io::Read};
+use std::{fs::File, io::Read, time::Duration};
 
 const DEFAULT_LOG_VERBOSITY: usize = 3;
 
@@ -15,7 +20,32 @@ const DEFAULT_LOG_VERBOSITY: usize = 3;
 struct Opts {
     /// Config path
     #[structopt(short = "c", long = "config")]
-    config_path: String,
+    config_path: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Connect to a node and dump the block forest it shares
+    Explore {
+        /// Address of the node
+        address: String,
+        /// mainnet, testnet or regtest
+        #[structopt(long, default_value = "mainnet")]
+        chain: Network,
+        /// How long to collect the blocks
+        #[structopt(
+            long,
+            default_value = "10s",
+            parse(try_from_str = humantime::parse_duration)
+        )]
+        duration: Duration,
+        /// json or dot
+        #[structopt(long, default_value = "json")]
+        format: String,
+    },
 }
 
 fn read_config(path: &str) -> Result<Config> {
@@ -52,8 +82,29 @@ fn do_main() -> Result<()> {
         .init()
         .expect("failed to initialize logging");
 
-    let config = read_config(&opts.config_path)?;
-    run_forever(config)
+    match opts.command {
+        Some(Command::Explore {
+            address,
+            chain,
+            duration,
+            format,
+        }) => explore(&address, chain, duration, &format),
+        None => {
+            let config_path = opts.config_path.context("config path is not given")?;
+            let config = read_config(&config_path)?;
+            run_forever(config)
+        }
+    }
+}
+
+fn explore(address: &str, chain: Network, duration: Duration, format: &str) -> Result<()> {
+    let snapshot = fetch_forest(address, chain.params(), duration)?.snapshot();
+    match format {
+        "json" => println!("{}", snapshot.to_json()?),
+        "dot" => print!("{}", snapshot.to_dot()),
+        _ => bail!("unknown format {}", format),
+    }
+    Ok(())
 }
 
 fn main() {
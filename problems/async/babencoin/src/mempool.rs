@@ -0,0 +1,625 @@
+use crate::{
+    block_forest::BlockForest,
+    data::{TransactionHash, VerifiedTransaction, WalletId},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub type Balances = HashMap<WalletId, u64>;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MempoolConfig {
+    /// Zero means unlimited.
+    #[serde(default = "default_max_size")]
+    pub max_size: usize,
+    /// Zero means transactions never expire.
+    #[serde(with = "humantime_serde", default = "default_max_age")]
+    pub max_age: Duration,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_max_size(),
+            max_age: default_max_age(),
+        }
+    }
+}
+
+fn default_max_size() -> usize {
+    10000
+}
+
+fn default_max_age() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+#[derive(Clone)]
+struct Entry {
+    tx: VerifiedTransaction,
+    seq: u64,
+    added_at: Instant,
+}
+
+impl Entry {
+    /// Higher fee goes first, older transaction wins a tie.
+    fn priority(&self) -> (u64, Reverse<u64>) {
+        (self.tx.fee, Reverse(self.seq))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Pending transactions ordered by fee.
+///
+/// All the transactions in the pool can be applied on top of the head balances
+/// together. When the pool is full, the transactions with the lowest fees are
+/// evicted, along with the transactions that spend their outputs. A transaction
+/// that conflicts with pending transactions of the same sender replaces them if
+/// its fee is greater than their total fee.
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: HashMap<TransactionHash, Entry>,
+    by_priority: BTreeSet<(u64, Reverse<u64>, TransactionHash)>,
+    by_age: BTreeSet<(Instant, u64, TransactionHash)>,
+    /// The pending transactions of each sender by priority.
+    senders: HashMap<WalletId, BTreeSet<(u64, Reverse<u64>, TransactionHash)>>,
+    snapshot: Balances,
+    next_seq: u64,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+            by_priority: BTreeSet::new(),
+            by_age: BTreeSet::new(),
+            senders: HashMap::new(),
+            snapshot: HashMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: &TransactionHash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &TransactionHash) -> Option<&VerifiedTransaction> {
+        self.entries.get(hash).map(|entry| &entry.tx)
+    }
+
+    /// Iterates over the transactions in the order of decreasing fee.
+    pub fn iter(&self) -> impl Iterator<Item = &VerifiedTransaction> {
+        self.by_priority
+            .iter()
+            .rev()
+            .map(move |(_, _, hash)| &self.entries[hash].tx)
+    }
+
+    /// Returns at most `limit` transactions with the highest fees that can be applied
+    /// on top of `base` in the returned order and included in a block with the given
+    /// index and timestamp.
+    pub fn select(
+        &self,
+        base: &Balances,
+        limit: usize,
+        index: u64,
+        timestamp: DateTime<Utc>,
+    ) -> Vec<VerifiedTransaction> {
+        let candidates = self
+            .entries
+            .values()
+            .filter(|entry| entry.tx.is_final(index, timestamp))
+            .collect();
+        let (selected, _) = Self::select_entries(base, candidates, limit);
+        selected.into_iter().map(|entry| entry.tx.clone()).collect()
+    }
+
+    pub(crate) fn insert(&mut self, tx: VerifiedTransaction, base: &Balances) -> Result<()> {
+        if self.entries.contains_key(tx.hash()) {
+            return Ok(());
+        }
+        self.expire(base);
+        if self.entries.is_empty() {
+            self.snapshot = base.clone();
+        }
+
+        let hash = *tx.hash();
+        if let Err(err) = BlockForest::try_apply_tx_to_snapshot(&tx, &mut self.snapshot) {
+            return self.try_replace(tx).map_err(|_| err);
+        }
+        self.push(tx, Instant::now());
+
+        if self.config.max_size > 0 && self.entries.len() > self.config.max_size {
+            let (_, _, lowest) = *self.by_priority.iter().next().unwrap();
+            self.evict(&lowest, base);
+            if !self.entries.contains_key(&hash) {
+                bail!("mempool is full and the transaction fee is too low");
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the expired transactions.
+    pub(crate) fn expire(&mut self, base: &Balances) {
+        if self.config.max_age.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        while let Some(&(added_at, _, hash)) = self.by_age.iter().next() {
+            if now.duration_since(added_at) < self.config.max_age {
+                break;
+            }
+            self.evict(&hash, base);
+        }
+    }
+
+    /// Re-validates the pool against the new `base`. `returned` are the transactions
+    /// of the disconnected blocks, `included` are the hashes of the transactions of
+    /// the connected blocks.
+    pub(crate) fn reset(
+        &mut self,
+        base: &Balances,
+        returned: Vec<VerifiedTransaction>,
+        included: &HashSet<TransactionHash>,
+    ) {
+        let now = Instant::now();
+        self.by_priority.clear();
+        self.by_age.clear();
+        self.senders.clear();
+        let mut candidates: Vec<_> = self
+            .entries
+            .drain()
+            .map(|(_, entry)| entry)
+            .filter(|entry| {
+                self.config.max_age.is_zero()
+                    || now.duration_since(entry.added_at) < self.config.max_age
+            })
+            .collect();
+        for tx in returned.into_iter() {
+            candidates.push(Entry {
+                tx,
+                seq: self.next_seq,
+                added_at: now,
+            });
+            self.next_seq += 1;
+        }
+
+        let limit = match self.config.max_size {
+            0 => usize::MAX,
+            max_size => max_size,
+        };
+        let mut seen = HashSet::new();
+        let (selected, snapshot) = Self::select_entries(
+            base,
+            candidates
+                .iter()
+                .filter(|entry| {
+                    !included.contains(entry.tx.hash()) && seen.insert(*entry.tx.hash())
+                })
+                .collect(),
+            limit,
+        );
+
+        let selected_hashes: HashSet<_> = selected.iter().map(|entry| *entry.tx.hash()).collect();
+        for entry in candidates.iter() {
+            if !selected_hashes.contains(entry.tx.hash()) && !included.contains(entry.tx.hash()) {
+                debug!("discarding transaction {}", base64::encode(entry.tx.hash()));
+            }
+        }
+
+        let selected: Vec<_> = selected.into_iter().cloned().collect();
+        self.snapshot = snapshot;
+        for entry in selected.into_iter() {
+            self.insert_entry(entry);
+        }
+    }
+
+    /// Reverts the pending transactions of the sender with the lowest fees in the
+    /// snapshot one by one until the new transaction applies. A transaction whose
+    /// outputs are already spent can't be replaced.
+    fn try_replace(&mut self, tx: VerifiedTransaction) -> Result<()> {
+        let conflicts: Vec<_> = match self.senders.get(&tx.sender) {
+            Some(pending) => pending
+                .iter()
+                .take_while(|(fee, _, _)| *fee < tx.fee)
+                .map(|(fee, _, hash)| (*fee, *hash))
+                .collect(),
+            None => vec![],
+        };
+
+        let mut reverted = vec![];
+        let mut replaced_fees = 0u64;
+        for (fee, hash) in conflicts.into_iter() {
+            replaced_fees = replaced_fees.saturating_add(fee);
+            if replaced_fees >= tx.fee
+                || Self::try_revert_tx_in_snapshot(&self.entries[&hash].tx, &mut self.snapshot)
+                    .is_err()
+            {
+                break;
+            }
+            reverted.push(hash);
+
+            if BlockForest::try_apply_tx_to_snapshot(&tx, &mut self.snapshot).is_ok() {
+                for hash in reverted.iter() {
+                    debug!("transaction {} is replaced", base64::encode(hash));
+                    self.remove_entry(hash);
+                }
+                self.push(tx, Instant::now());
+                return Ok(());
+            }
+        }
+
+        for hash in reverted.iter().rev() {
+            BlockForest::try_apply_tx_to_snapshot(&self.entries[hash].tx, &mut self.snapshot)
+                .expect("reverted transaction must apply again");
+        }
+        bail!("sender has insufficient funds")
+    }
+
+    fn push(&mut self, tx: VerifiedTransaction, added_at: Instant) {
+        let entry = Entry {
+            tx,
+            seq: self.next_seq,
+            added_at,
+        };
+        self.next_seq += 1;
+        self.insert_entry(entry);
+    }
+
+    fn insert_entry(&mut self, entry: Entry) {
+        let (fee, seq) = entry.priority();
+        let hash = *entry.tx.hash();
+        self.by_priority.insert((fee, seq, hash));
+        self.by_age.insert((entry.added_at, entry.seq, hash));
+        self.senders
+            .entry(entry.tx.sender.clone())
+            .or_default()
+            .insert((fee, seq, hash));
+        self.entries.insert(hash, entry);
+    }
+
+    fn remove_entry(&mut self, hash: &TransactionHash) -> Option<Entry> {
+        let entry = self.entries.remove(hash)?;
+        let (fee, seq) = entry.priority();
+        self.by_priority.remove(&(fee, seq, *hash));
+        self.by_age.remove(&(entry.added_at, entry.seq, *hash));
+        if let Some(pending) = self.senders.get_mut(&entry.tx.sender) {
+            pending.remove(&(fee, seq, *hash));
+            if pending.is_empty() {
+                self.senders.remove(&entry.tx.sender);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Drops the transaction. While a receiver has spent more than it would have
+    /// without the outputs of the transaction, its pending transactions with the
+    /// lowest fees are dropped too.
+    fn evict(&mut self, hash: &TransactionHash, base: &Balances) {
+        let entry = match self.remove_entry(hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+        debug!("evicting transaction {}", base64::encode(hash));
+
+        let tx = &entry.tx;
+        let mut received = HashMap::new();
+        for (receiver, amount) in tx.outputs() {
+            if *receiver != tx.sender {
+                let total: &mut u64 = received.entry(receiver).or_default();
+                *total = total.saturating_add(amount);
+            }
+        }
+        for (receiver, amount) in received.into_iter() {
+            while self.snapshot.get(receiver).copied().unwrap_or(0) < amount {
+                let dependent = match self.senders.get(receiver).and_then(|p| p.iter().next()) {
+                    Some((_, _, dependent)) => *dependent,
+                    None => break,
+                };
+                self.evict(&dependent, base);
+            }
+        }
+
+        if Self::try_revert_tx_in_snapshot(tx, &mut self.snapshot).is_err() {
+            self.reset(base, vec![], &HashSet::new());
+        }
+    }
+
+    /// Undoes `BlockForest::try_apply_tx_to_snapshot()`.
+    fn try_revert_tx_in_snapshot(tx: &VerifiedTransaction, snapshot: &mut Balances) -> Result<()> {
+        let total_spent = tx
+            .total_spent()
+            .context("transaction amount overflows u64")?;
+
+        let mut new_balances = HashMap::new();
+        for (receiver, amount) in tx.outputs() {
+            let old_receiver_balance = match new_balances.get(receiver) {
+                Some(balance) => *balance,
+                None => *snapshot.get(receiver).unwrap_or(&0),
+            };
+            let new_receiver_balance = old_receiver_balance
+                .checked_sub(amount)
+                .context("receiver has spent the received amount")?;
+            new_balances.insert(receiver, new_receiver_balance);
+        }
+        let old_sender_balance = match new_balances.get(&tx.sender) {
+            Some(balance) => *balance,
+            None => *snapshot.get(&tx.sender).unwrap_or(&0),
+        };
+        let new_sender_balance = old_sender_balance
+            .checked_add(total_spent)
+            .context("sender balance overflows u64")?;
+        new_balances.insert(&tx.sender, new_sender_balance);
+
+        for (key, value) in new_balances.into_iter() {
+            if value > 0 {
+                snapshot.insert(key.clone(), value);
+            } else {
+                snapshot.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Greedily applies the candidates in the order of decreasing priority. The
+    /// candidates that can't be applied yet are retried while there is progress,
+    /// since they may depend on the transactions with lower fees.
+    fn select_entries<'a>(
+        base: &Balances,
+        mut candidates: Vec<&'a Entry>,
+        limit: usize,
+    ) -> (Vec<&'a Entry>, Balances) {
+        candidates.sort_by_key(|entry| Reverse(entry.priority()));
+
+        let mut snapshot = base.clone();
+        let mut selected = vec![];
+        while !candidates.is_empty() && selected.len() < limit {
+            let selected_before = selected.len();
+            let mut rejected = vec![];
+            for entry in candidates.into_iter() {
+                if selected.len() >= limit {
+                    break;
+                }
+                if BlockForest::try_apply_tx_to_snapshot(&entry.tx, &mut snapshot).is_ok() {
+                    selected.push(entry);
+                } else {
+                    rejected.push(entry);
+                }
+            }
+            if selected.len() == selected_before {
+                break;
+            }
+            candidates = rejected;
+        }
+        (selected, snapshot)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::Block,
+        signature::{generate_ed25519_key, SigningKey},
+        util::parse_pkcs8_private,
+    };
+
+    use rsa::RSAPrivateKey;
+
+    use std::thread;
+
+    fn sender_key() -> RSAPrivateKey {
+        parse_pkcs8_private(include_str!("../data/test.pem")).unwrap()
+    }
+
+    fn make_tx(amount: u64, fee: u64, comment: &str) -> VerifiedTransaction {
+        let receiver = Block::genesis().attrs.issuer;
+        VerifiedTransaction::sign(&sender_key(), receiver, amount, fee, comment.into()).unwrap()
+    }
+
+    fn base(balance: u64) -> Balances {
+        let mut balances = HashMap::new();
+        balances.insert(sender_key().to_public_key().into(), balance);
+        balances
+    }
+
+    fn fees(mempool: &Mempool) -> Vec<u64> {
+        mempool.iter().map(|tx| tx.fee).collect()
+    }
+
+    #[test]
+    fn test_fee_order() {
+        let base = base(1000);
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        for (i, fee) in [3, 1, 5, 2].into_iter().enumerate() {
+            mempool
+                .insert(make_tx(10, fee, &i.to_string()), &base)
+                .unwrap();
+        }
+        assert_eq!(fees(&mempool), vec![5, 3, 2, 1]);
+
+        let selected: Vec<_> = mempool
+            .select(&base, 2, 1, Utc::now())
+            .iter()
+            .map(|tx| tx.fee)
+            .collect();
+        assert_eq!(selected, vec![5, 3]);
+
+        let poor = self::base(25);
+        let selected: Vec<_> = mempool
+            .select(&poor, 10, 1, Utc::now())
+            .iter()
+            .map(|tx| tx.fee)
+            .collect();
+        assert_eq!(selected, vec![5]);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let base = base(1000);
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: 2,
+            max_age: Duration::ZERO,
+        });
+        mempool.insert(make_tx(10, 2, "a"), &base).unwrap();
+        mempool.insert(make_tx(10, 3, "b"), &base).unwrap();
+        assert!(mempool.insert(make_tx(10, 1, "c"), &base).is_err());
+        assert_eq!(fees(&mempool), vec![3, 2]);
+
+        mempool.insert(make_tx(10, 4, "d"), &base).unwrap();
+        assert_eq!(fees(&mempool), vec![4, 3]);
+        let sender = sender_key().to_public_key().into();
+        assert_eq!(mempool.snapshot[&sender], 1000 - 14 - 13);
+    }
+
+    #[test]
+    fn test_eviction_with_dependents() {
+        let base = base(1000);
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: 2,
+            max_age: Duration::ZERO,
+        });
+        let receiver_key = generate_ed25519_key();
+        let funding = VerifiedTransaction::sign(
+            &sender_key(),
+            receiver_key.wallet_id(),
+            100,
+            1,
+            "funding".into(),
+        )
+        .unwrap();
+        let dependent = VerifiedTransaction::sign(
+            &receiver_key,
+            Block::genesis().attrs.issuer,
+            50,
+            5,
+            "dependent".into(),
+        )
+        .unwrap();
+        mempool.insert(funding.clone(), &base).unwrap();
+        mempool.insert(dependent.clone(), &base).unwrap();
+
+        // The funding transaction has the lowest fee, and the dependent one can't stay without it.
+        mempool.insert(make_tx(10, 3, "c"), &base).unwrap();
+        assert_eq!(fees(&mempool), vec![3]);
+        assert!(!mempool.contains(funding.hash()));
+        assert!(!mempool.contains(dependent.hash()));
+        let sender = sender_key().to_public_key().into();
+        assert_eq!(mempool.snapshot[&sender], 1000 - 13);
+        assert!(!mempool.snapshot.contains_key(&receiver_key.wallet_id()));
+    }
+
+    #[test]
+    fn test_eviction_keeps_funded_dependents() {
+        let receiver_key = generate_ed25519_key();
+        let mut base = base(1000);
+        base.insert(receiver_key.wallet_id(), 100);
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: 2,
+            max_age: Duration::ZERO,
+        });
+        let funding = VerifiedTransaction::sign(
+            &sender_key(),
+            receiver_key.wallet_id(),
+            100,
+            1,
+            "funding".into(),
+        )
+        .unwrap();
+        let dependent = VerifiedTransaction::sign(
+            &receiver_key,
+            Block::genesis().attrs.issuer,
+            50,
+            5,
+            "dependent".into(),
+        )
+        .unwrap();
+        mempool.insert(funding.clone(), &base).unwrap();
+        mempool.insert(dependent.clone(), &base).unwrap();
+
+        // The receiver can pay for the dependent transaction with its own coins.
+        mempool.insert(make_tx(10, 3, "c"), &base).unwrap();
+        assert_eq!(fees(&mempool), vec![5, 3]);
+        assert!(!mempool.contains(funding.hash()));
+        assert_eq!(mempool.snapshot[&receiver_key.wallet_id()], 100 - 55);
+    }
+
+    #[test]
+    fn test_expiration() {
+        let base = base(1000);
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: 0,
+            max_age: Duration::from_millis(100),
+        });
+        let old_tx = make_tx(10, 1, "old");
+        mempool.insert(old_tx.clone(), &base).unwrap();
+        thread::sleep(Duration::from_millis(150));
+
+        let new_tx = make_tx(10, 1, "new");
+        mempool.insert(new_tx.clone(), &base).unwrap();
+        assert!(!mempool.contains(old_tx.hash()));
+        assert!(mempool.contains(new_tx.hash()));
+    }
+
+    #[test]
+    fn test_replacement() {
+        let base = base(100);
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let first = make_tx(50, 1, "first");
+        let second = make_tx(40, 5, "second");
+        mempool.insert(first.clone(), &base).unwrap();
+        mempool.insert(second.clone(), &base).unwrap();
+
+        // Total fee of the conflicting transactions is not less than the new fee.
+        assert!(mempool.insert(make_tx(60, 6, "greedy"), &base).is_err());
+        assert_eq!(mempool.len(), 2);
+
+        let third = make_tx(30, 3, "third");
+        mempool.insert(third.clone(), &base).unwrap();
+        assert!(!mempool.contains(first.hash()));
+        assert!(mempool.contains(second.hash()));
+        assert!(mempool.contains(third.hash()));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let pending = make_tx(50, 1, "pending");
+        mempool.insert(pending.clone(), &base(100)).unwrap();
+
+        let returned = make_tx(40, 2, "returned");
+        let included = make_tx(10, 3, "included");
+        mempool.reset(
+            &base(95),
+            vec![returned.clone(), included.clone()],
+            &[*included.hash()].into_iter().collect(),
+        );
+        assert_eq!(fees(&mempool), vec![2, 1]);
+
+        mempool.reset(&base(60), vec![], &HashSet::new());
+        assert_eq!(fees(&mempool), vec![2]);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    data::{TransactionHash, HASH_LEN},
+    util::{deserialize_base64_fixed, serialize_base64},
+};
+
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_512};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub type MerkleHash = [u8; HASH_LEN];
+
+/// Merkle root of a block without transactions.
+pub const EMPTY_MERKLE_ROOT: MerkleHash = [0u8; HASH_LEN];
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes the root of the Merkle tree over the transaction hashes.
+///
+/// Leaves and inner nodes are hashed with different prefixes, so that an inner
+/// node can't be passed off as a transaction. A node without a pair is promoted
+/// to the next level as is.
+pub fn compute_merkle_root(transaction_hashes: &[TransactionHash]) -> MerkleHash {
+    if transaction_hashes.is_empty() {
+        return EMPTY_MERKLE_ROOT;
+    }
+
+    let mut level: Vec<_> = transaction_hashes.iter().map(hash_leaf).collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_node(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleStep {
+    /// Which side of the path the sibling is on.
+    pub side: Side,
+    #[serde(
+        serialize_with = "serialize_base64",
+        deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>"
+    )]
+    pub hash: MerkleHash,
+}
+
+/// Proof that a transaction is included in a block: the siblings on the path
+/// from the transaction leaf to the root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub steps: Vec<MerkleStep>,
+}
+
+impl MerkleProof {
+    /// Builds a proof for the transaction at `index`.
+    pub fn build(transaction_hashes: &[TransactionHash], index: usize) -> Option<MerkleProof> {
+        if index >= transaction_hashes.len() {
+            return None;
+        }
+
+        let mut steps = vec![];
+        let mut index = index;
+        let mut level: Vec<_> = transaction_hashes.iter().map(hash_leaf).collect();
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                steps.push(MerkleStep {
+                    side: if sibling < index {
+                        Side::Left
+                    } else {
+                        Side::Right
+                    },
+                    hash: level[sibling],
+                });
+            }
+
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            index /= 2;
+        }
+
+        Some(MerkleProof { steps })
+    }
+
+    pub fn verify(&self, transaction_hash: &TransactionHash, merkle_root: &MerkleHash) -> bool {
+        let mut hash = hash_leaf(transaction_hash);
+        for step in self.steps.iter() {
+            hash = match step.side {
+                Side::Left => hash_node(&step.hash, &hash),
+                Side::Right => hash_node(&hash, &step.hash),
+            };
+        }
+        hash == *merkle_root
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn hash_leaf(transaction_hash: &TransactionHash) -> MerkleHash {
+    finalize(Sha3_512::new().chain([LEAF_PREFIX]).chain(transaction_hash))
+}
+
+fn hash_node(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    finalize(
+        Sha3_512::new()
+            .chain([NODE_PREFIX])
+            .chain(left)
+            .chain(right),
+    )
+}
+
+fn finalize(hasher: Sha3_512) -> MerkleHash {
+    let mut hash = [0u8; HASH_LEN];
+    hash.copy_from_slice(&hasher.finalize());
+    hash
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(count: u8) -> Vec<TransactionHash> {
+        (0..count).map(|i| [i; HASH_LEN]).collect()
+    }
+
+    #[test]
+    fn test_proofs() {
+        for count in 1..=9 {
+            let hashes = hashes(count);
+            let root = compute_merkle_root(&hashes);
+            for (index, hash) in hashes.iter().enumerate() {
+                let proof = MerkleProof::build(&hashes, index).unwrap();
+                assert!(proof.verify(hash, &root));
+                assert!(!proof.verify(&[count; HASH_LEN], &root));
+            }
+            assert!(MerkleProof::build(&hashes, hashes.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_root() {
+        assert_eq!(compute_merkle_root(&[]), EMPTY_MERKLE_ROOT);
+
+        let hashes = hashes(3);
+        assert_eq!(compute_merkle_root(&hashes[..1]), hash_leaf(&hashes[0]));
+        assert_ne!(
+            compute_merkle_root(&hashes),
+            compute_merkle_root(&hashes[..2])
+        );
+
+        let mut swapped = hashes.clone();
+        swapped.swap(0, 1);
+        assert_ne!(compute_merkle_root(&hashes), compute_merkle_root(&swapped));
+    }
+
+    #[test]
+    fn test_tampered_proof() {
+        let hashes = hashes(4);
+        let root = compute_merkle_root(&hashes);
+        let mut proof = MerkleProof::build(&hashes, 2).unwrap();
+        assert_eq!(proof.steps[0].side, Side::Right);
+        proof.steps[0].side = Side::Left;
+        assert!(!proof.verify(&hashes[2], &root));
+    }
+}
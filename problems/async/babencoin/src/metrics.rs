@@ -0,0 +1,358 @@
+use crate::node::Shutdown;
+
+use anyhow::{Context, Result};
+use crossbeam::channel::RecvTimeoutError;
+use log::*;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default, Debug)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Stores an `f64` as its bits.
+#[derive(Default, Debug)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: f64) {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + delta).to_bits())
+            })
+            .unwrap();
+    }
+
+    pub fn inc(&self) {
+        self.add(1.);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1.);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+type LabelSet = Vec<(String, String)>;
+
+enum Series {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+}
+
+impl Series {
+    fn value(&self) -> f64 {
+        match self {
+            Self::Counter(counter) => counter.get() as f64,
+            Self::Gauge(gauge) => gauge.get(),
+        }
+    }
+}
+
+struct Family {
+    help: String,
+    kind: &'static str,
+    series: BTreeMap<LabelSet, Series>,
+}
+
+/// In-process registry of the metrics, rendered in the Prometheus text format.
+///
+/// A metric is identified by its name and labels. Asking for the same metric twice
+/// returns the same counter or gauge.
+#[derive(Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<String, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        let series = self.get_or_insert(name, help, "counter", labels, || {
+            Series::Counter(Arc::default())
+        });
+        match series {
+            Series::Counter(counter) => counter,
+            Series::Gauge(_) => panic!("metric {} is not a counter", name),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        let series =
+            self.get_or_insert(
+                name,
+                help,
+                "gauge",
+                labels,
+                || Series::Gauge(Arc::default()),
+            );
+        match series {
+            Series::Gauge(gauge) => gauge,
+            Series::Counter(_) => panic!("metric {} is not a gauge", name),
+        }
+    }
+
+    /// Returns the current value of the metric, if it was ever registered.
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let families = self.families.lock().unwrap();
+        families
+            .get(name)?
+            .series
+            .get(&to_label_set(labels))
+            .map(|series| series.value())
+    }
+
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut output = String::new();
+        for (name, family) in families.iter() {
+            writeln!(output, "# HELP {} {}", name, family.help).unwrap();
+            writeln!(output, "# TYPE {} {}", name, family.kind).unwrap();
+            for (labels, series) in family.series.iter() {
+                output.push_str(name);
+                if !labels.is_empty() {
+                    let labels: Vec<_> = labels
+                        .iter()
+                        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                        .collect();
+                    write!(output, "{{{}}}", labels.join(",")).unwrap();
+                }
+                writeln!(output, " {}", series.value()).unwrap();
+            }
+        }
+        output
+    }
+
+    fn get_or_insert(
+        &self,
+        name: &str,
+        help: &str,
+        kind: &'static str,
+        labels: &[(&str, &str)],
+        make: impl FnOnce() -> Series,
+    ) -> Series {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            kind,
+            series: BTreeMap::new(),
+        });
+        if family.kind != kind {
+            panic!("metric {} is a {}, not a {}", name, family.kind, kind);
+        }
+
+        match family
+            .series
+            .entry(to_label_set(labels))
+            .or_insert_with(make)
+        {
+            Series::Counter(counter) => Series::Counter(counter.clone()),
+            Series::Gauge(gauge) => Series::Gauge(gauge.clone()),
+        }
+    }
+}
+
+fn to_label_set(labels: &[(&str, &str)]) -> LabelSet {
+    let mut set: LabelSet = labels
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    set.sort();
+    set
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Serves `GET /metrics` on the given address in a background thread until `shutdown`
+/// is triggered. Returns the address the endpoint is bound to and the thread.
+pub fn serve(
+    registry: Arc<Registry>,
+    address: &str,
+    shutdown: Shutdown,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("failed to bind metrics endpoint to {}", address))?;
+    let local_addr = listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+
+    let thread = thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || loop {
+            let result = match listener.accept() {
+                Ok((stream, _)) => handle_request(&registry, stream),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    match shutdown.receiver().recv_timeout(ACCEPT_POLL_INTERVAL) {
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        _ => break,
+                    }
+                }
+                Err(err) => Err(err).context("failed to accept connection"),
+            };
+            if let Err(err) = result {
+                debug!("metrics request failed: {:#}", err);
+            }
+        })
+        .context("failed to spawn metrics thread")?;
+
+    Ok((local_addr, thread))
+}
+
+fn handle_request(registry: &Registry, stream: TcpStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", registry.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::ShutdownReason;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::new();
+        let blocks = registry.counter("messages_total", "Messages.", &[("kind", "block")]);
+        blocks.inc_by(3);
+        registry
+            .counter("messages_total", "Messages.", &[("kind", "block")])
+            .inc();
+        registry
+            .counter("messages_total", "Messages.", &[("kind", "request")])
+            .inc();
+        let sessions = registry.gauge("sessions", "Sessions.", &[]);
+        sessions.inc();
+        sessions.inc();
+        sessions.dec();
+
+        assert_eq!(
+            registry.get("messages_total", &[("kind", "block")]),
+            Some(4.)
+        );
+        assert_eq!(registry.get("sessions", &[]), Some(1.));
+        assert_eq!(registry.get("sessions", &[("kind", "block")]), None);
+        assert_eq!(registry.get("missing", &[]), None);
+
+        assert_eq!(
+            registry.render(),
+            "# HELP messages_total Messages.\n\
+             # TYPE messages_total counter\n\
+             messages_total{kind=\"block\"} 4\n\
+             messages_total{kind=\"request\"} 1\n\
+             # HELP sessions Sessions.\n\
+             # TYPE sessions gauge\n\
+             sessions 1\n"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_kind_mismatch() {
+        let registry = Registry::new();
+        registry.counter("sessions", "Sessions.", &[]);
+        registry.gauge("sessions", "Sessions.", &[]);
+    }
+
+    #[test]
+    fn test_serve() {
+        let registry = Arc::new(Registry::new());
+        registry.gauge("head_index", "Head index.", &[]).set(42.);
+        let shutdown = Shutdown::new();
+        let (addr, thread) = serve(registry, "127.0.0.1:0", shutdown.clone()).unwrap();
+
+        let fetch = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = fetch("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\nhead_index 42\n"));
+        assert!(fetch("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        shutdown.trigger(ShutdownReason::Requested);
+        thread.join().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
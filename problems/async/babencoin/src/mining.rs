@@ -0,0 +1,425 @@
+use crate::{
+    data::{BlockAttributes, BlockHash, HASH_LEN},
+    metrics::Gauge,
+};
+
+use crossbeam::channel::{self, Receiver, Sender};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// How many hashes a thread computes between the checks whether the job is
+/// cancelled. Bounds the time a stale job keeps running after `cancel()`.
+pub const CANCEL_CHECK_INTERVAL: u64 = 64;
+
+const HASH_RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes the hash a block is compared with `max_hash` by.
+pub trait PowBackend: Send + Sync {
+    fn hash(&self, attrs: &BlockAttributes) -> BlockHash;
+}
+
+/// The consensus hash, i.e. `BlockAttributes::compute_hash()`.
+pub struct Sha3Pow;
+
+impl PowBackend for Sha3Pow {
+    fn hash(&self, attrs: &BlockAttributes) -> BlockHash {
+        attrs.compute_hash()
+    }
+}
+
+/// Mixes the nonce with the parent hash instead of hashing the block. It is much
+/// cheaper than the consensus hash, but the blocks it finds are valid only on
+/// chains where any hash fits `max_hash`, such as regtest.
+pub struct TestPow;
+
+impl PowBackend for TestPow {
+    fn hash(&self, attrs: &BlockAttributes) -> BlockHash {
+        let mut seed = [0u8; 8];
+        seed.copy_from_slice(&attrs.prev_hash[..8]);
+        let mut state = attrs.nonce ^ u64::from_le_bytes(seed);
+
+        let mut hash = [0u8; HASH_LEN];
+        for chunk in hash.chunks_mut(8) {
+            state = splitmix64(state);
+            chunk.copy_from_slice(&state.to_be_bytes());
+        }
+        hash
+    }
+}
+
+fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowKind {
+    #[default]
+    Sha3,
+    Test,
+}
+
+impl PowKind {
+    pub fn backend(&self) -> Arc<dyn PowBackend> {
+        match self {
+            Self::Sha3 => Arc::new(Sha3Pow),
+            Self::Test => Arc::new(TestPow),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Splits all the nonces into `count` disjoint ranges of (almost) the same size.
+pub fn partition_nonces(count: usize) -> Vec<RangeInclusive<u64>> {
+    assert!(count > 0, "nonces can't be split into zero ranges");
+
+    let total = u64::MAX as u128 + 1;
+    let count = count as u128;
+    (0..count)
+        .map(|i| {
+            let start = total * i / count;
+            let end = total * (i + 1) / count - 1;
+            start as u64..=end as u64
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MiningReport {
+    /// The nonce with which the hash fits `max_hash`, or `None` if the job was
+    /// cancelled or all the nonces were tried.
+    pub nonce: Option<u64>,
+    /// Hashes computed by each thread.
+    pub hashes: Vec<u64>,
+    pub elapsed: Duration,
+}
+
+impl MiningReport {
+    /// Hashes per second of all the threads together.
+    pub fn hash_rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0. {
+            return 0.;
+        }
+        self.hashes.iter().sum::<u64>() as f64 / seconds
+    }
+}
+
+struct JobState {
+    attrs: BlockAttributes,
+    started_at: Instant,
+    stopped: AtomicBool,
+    nonce: Mutex<Option<u64>>,
+    hashes: Vec<AtomicU64>,
+    running: AtomicUsize,
+    report_sender: Sender<MiningReport>,
+}
+
+impl JobState {
+    fn finish_thread(&self) {
+        if self.running.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        let report = MiningReport {
+            nonce: *self.nonce.lock().unwrap(),
+            hashes: self
+                .hashes
+                .iter()
+                .map(|h| h.load(Ordering::Relaxed))
+                .collect(),
+            elapsed: self.started_at.elapsed(),
+        };
+        let _ = self.report_sender.send(report);
+    }
+}
+
+/// A running search for a nonce. Dropping the job cancels it.
+pub struct MiningJob {
+    state: Arc<JobState>,
+    report_receiver: Receiver<MiningReport>,
+}
+
+impl MiningJob {
+    /// Makes the threads stop within `CANCEL_CHECK_INTERVAL` hashes. Doesn't wait
+    /// for them.
+    pub fn cancel(&self) {
+        self.state.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Receives the report once all the threads have stopped, e.g. to `select!`
+    /// on it.
+    pub fn report_receiver(&self) -> &Receiver<MiningReport> {
+        &self.report_receiver
+    }
+
+    /// Waits until a nonce is found, all the nonces are tried or the job is
+    /// cancelled from another thread.
+    pub fn wait(self) -> MiningReport {
+        self.report_receiver
+            .recv()
+            .expect("mining threads exited without a report")
+    }
+}
+
+impl Drop for MiningJob {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Searches for a nonce in several threads. Each thread of a job tries its own
+/// range of `partition_nonces()`, so no nonce is hashed twice.
+pub struct Miner {
+    pool: ThreadPool,
+    backend: Arc<dyn PowBackend>,
+    hash_rates: Vec<Arc<Gauge>>,
+}
+
+impl Miner {
+    /// `hash_rate` returns the gauge for the given thread, which is updated every
+    /// second while the thread is mining.
+    pub fn new(
+        thread_count: usize,
+        backend: Arc<dyn PowBackend>,
+        hash_rate: impl Fn(usize) -> Arc<Gauge>,
+    ) -> Self {
+        let thread_count = thread_count.max(1);
+        Self {
+            pool: ThreadPoolBuilder::new()
+                .num_threads(thread_count)
+                .thread_name(|i| format!("miner-{}", i))
+                .build()
+                .expect("failed to create mining thread pool"),
+            backend,
+            hash_rates: (0..thread_count).map(hash_rate).collect(),
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.hash_rates.len()
+    }
+
+    /// Starts looking for a nonce with which the hash of `attrs` doesn't exceed
+    /// `attrs.max_hash`. The nonce of `attrs` is ignored.
+    pub fn start(&self, attrs: BlockAttributes) -> MiningJob {
+        let ranges = partition_nonces(self.thread_count());
+        let (report_sender, report_receiver) = channel::bounded(1);
+        let state = Arc::new(JobState {
+            attrs,
+            started_at: Instant::now(),
+            stopped: AtomicBool::new(false),
+            nonce: Mutex::new(None),
+            hashes: ranges.iter().map(|_| AtomicU64::new(0)).collect(),
+            running: AtomicUsize::new(ranges.len()),
+            report_sender,
+        });
+
+        for (thread, range) in ranges.into_iter().enumerate() {
+            let state = state.clone();
+            let backend = self.backend.clone();
+            let hash_rate = self.hash_rates[thread].clone();
+            self.pool.spawn(move || {
+                search(&state, thread, range, backend.as_ref(), &hash_rate);
+                state.finish_thread();
+            });
+        }
+
+        MiningJob {
+            state,
+            report_receiver,
+        }
+    }
+}
+
+fn search(
+    state: &JobState,
+    thread: usize,
+    range: RangeInclusive<u64>,
+    backend: &dyn PowBackend,
+    hash_rate: &Gauge,
+) {
+    let mut attrs = state.attrs.clone();
+    let mut reported_at = Instant::now();
+    let mut reported_hashes = 0;
+    let mut hashes = 0;
+
+    for nonce in range {
+        if hashes % CANCEL_CHECK_INTERVAL == 0 {
+            if state.stopped.load(Ordering::Relaxed) {
+                break;
+            }
+            let elapsed = reported_at.elapsed();
+            if elapsed >= HASH_RATE_INTERVAL {
+                hash_rate.set((hashes - reported_hashes) as f64 / elapsed.as_secs_f64());
+                reported_at = Instant::now();
+                reported_hashes = hashes;
+            }
+        }
+
+        attrs.nonce = nonce;
+        hashes += 1;
+        if backend.hash(&attrs) <= attrs.max_hash {
+            let mut found = state.nonce.lock().unwrap();
+            if found.is_none() {
+                *found = Some(nonce);
+            }
+            state.stopped.store(true, Ordering::Relaxed);
+            break;
+        }
+    }
+
+    state.hashes[thread].store(hashes, Ordering::Relaxed);
+    let elapsed = reported_at.elapsed().as_secs_f64();
+    if elapsed > 0. {
+        hash_rate.set((hashes - reported_hashes) as f64 / elapsed);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{data::Block, metrics::Registry};
+
+    use std::{collections::HashSet, thread};
+
+    /// Never finds a block, but remembers the nonces it was asked about.
+    #[derive(Default)]
+    struct RecordingPow {
+        nonces: Mutex<Vec<u64>>,
+    }
+
+    impl PowBackend for RecordingPow {
+        fn hash(&self, attrs: &BlockAttributes) -> BlockHash {
+            self.nonces.lock().unwrap().push(attrs.nonce);
+            [255u8; HASH_LEN]
+        }
+    }
+
+    fn unreachable_attrs() -> BlockAttributes {
+        let mut attrs = Block::genesis().attrs;
+        attrs.max_hash = [0u8; HASH_LEN];
+        attrs
+    }
+
+    fn miner(thread_count: usize, backend: Arc<dyn PowBackend>) -> Miner {
+        Miner::new(thread_count, backend, |_| Arc::new(Gauge::default()))
+    }
+
+    #[test]
+    fn test_partition_nonces() {
+        assert_eq!(partition_nonces(1), vec![0..=u64::MAX]);
+
+        for count in [2, 3, 7, 16] {
+            let ranges = partition_nonces(count);
+            assert_eq!(ranges.len(), count);
+            assert_eq!(*ranges[0].start(), 0);
+            assert_eq!(*ranges[count - 1].end(), u64::MAX);
+            for pair in ranges.windows(2) {
+                assert_eq!(*pair[0].end() + 1, *pair[1].start());
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_nonce_searched_twice() {
+        let backend = Arc::new(RecordingPow::default());
+        let miner = miner(4, backend.clone());
+
+        let job = miner.start(unreachable_attrs());
+        thread::sleep(Duration::from_millis(100));
+        job.cancel();
+        let report = job.wait();
+        assert_eq!(report.nonce, None);
+
+        let nonces = backend.nonces.lock().unwrap();
+        assert_eq!(nonces.len() as u64, report.hashes.iter().sum::<u64>());
+        let unique: HashSet<_> = nonces.iter().copied().collect();
+        assert_eq!(unique.len(), nonces.len());
+
+        let ranges = partition_nonces(4);
+        for (range, hashes) in ranges.iter().zip(report.hashes.iter()) {
+            assert!(*hashes > 0);
+            let in_range = nonces.iter().filter(|n| range.contains(n)).count();
+            assert_eq!(in_range as u64, *hashes);
+        }
+    }
+
+    #[test]
+    fn test_cancel_latency() {
+        let miner = miner(2, Arc::new(Sha3Pow));
+        let job = miner.start(unreachable_attrs());
+        thread::sleep(Duration::from_millis(50));
+
+        let cancelled_at = Instant::now();
+        job.cancel();
+        let report = job.wait();
+        assert_eq!(report.nonce, None);
+        assert!(cancelled_at.elapsed() < Duration::from_millis(500));
+
+        // The pool is free for the next job right away.
+        let mut attrs = Block::genesis().attrs;
+        attrs.max_hash = [255u8; HASH_LEN];
+        assert!(miner.start(attrs).wait().nonce.is_some());
+    }
+
+    #[test]
+    fn test_find_nonce() {
+        let mut attrs = Block::genesis().attrs;
+        attrs.max_hash = [255u8; HASH_LEN];
+        attrs.max_hash[0] = 15;
+
+        for backend in [PowKind::Sha3, PowKind::Test] {
+            let backend = backend.backend();
+            let report = miner(3, backend.clone()).start(attrs.clone()).wait();
+            attrs.nonce = report.nonce.unwrap();
+            assert!(backend.hash(&attrs) <= attrs.max_hash);
+        }
+        assert_eq!(Sha3Pow.hash(&attrs), attrs.compute_hash());
+    }
+
+    #[test]
+    fn test_hash_rate() {
+        let registry = Registry::new();
+        let miner = Miner::new(2, Arc::new(Sha3Pow), |thread| {
+            registry.gauge("hash_rate", "", &[("thread", &thread.to_string())])
+        });
+
+        let job = miner.start(unreachable_attrs());
+        thread::sleep(Duration::from_millis(100));
+        drop(job);
+        thread::sleep(Duration::from_millis(100));
+
+        for thread in ["0", "1"] {
+            assert!(registry.get("hash_rate", &[("thread", thread)]).unwrap() > 0.);
+        }
+
+        let job = miner.start(unreachable_attrs());
+        thread::sleep(Duration::from_millis(100));
+        job.cancel();
+        assert!(job.wait().hash_rate() > 0.);
+    }
+}
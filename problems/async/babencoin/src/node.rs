@@ -1,57 +1,318 @@
+mod ban_list;
+mod block_downloader;
 mod gossip_service;
+mod metrics;
 mod mining_service;
 mod peer_service;
+mod shutdown;
 
-use gossip_service::{GossipService, GossipServiceConfig};
+pub mod clock;
+pub mod transport;
+
+use ban_list::Bans;
+use block_downloader::BlockDownloader;
+use clock::Clock;
+use gossip_service::{GossipChannels, GossipService, GossipServiceConfig};
+use metrics::{MetricsConfig, NodeMetrics};
 use mining_service::{MiningService, MiningServiceConfig};
-use peer_service::{PeerService, PeerServiceConfig};
+use peer_service::{PeerChannels, PeerService, PeerServiceConfig};
+pub use shutdown::{Shutdown, ShutdownReason};
+use transport::{TcpTransport, Transport};
+
+use crate::{
+    chain_params::{deserialize_chain_params, ChainParams},
+    metrics::{self as registry, Registry},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use crossbeam::channel;
+use log::*;
 use serde::{Deserialize, Serialize};
 
-use std::thread;
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct Config {
+    /// Mainnet if omitted.
+    #[serde(default, deserialize_with = "deserialize_chain_params")]
+    pub chain: ChainParams,
     pub peer_service: PeerServiceConfig,
     pub gossip_service: GossipServiceConfig,
     pub mining_service: MiningServiceConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
 }
 
 pub fn run_forever(config: Config) -> Result<()> {
+    run_forever_with(
+        config,
+        Arc::new(TcpTransport),
+        Arc::new(Registry::new()),
+        Clock::System,
+    )
+}
+
+/// Runs the node over the given transport, e.g. an in-memory one in simulations,
+/// and reports its metrics to the given registry. The services measure time with
+/// `clock`. Returns only if a service terminates.
+pub fn run_forever_with(
+    config: Config,
+    transport: Arc<dyn Transport>,
+    registry: Arc<Registry>,
+    clock: Clock,
+) -> Result<()> {
+    match start_with(config, transport, registry, clock)?.wait() {
+        ShutdownReason::Requested => Ok(()),
+        reason => bail!("node stopped: {}", reason),
+    }
+}
+
+pub fn start(config: Config) -> Result<NodeHandle> {
+    start_with(
+        config,
+        Arc::new(TcpTransport),
+        Arc::new(Registry::new()),
+        Clock::System,
+    )
+}
+
+/// Starts the services of the node in background threads.
+pub fn start_with(
+    config: Config,
+    transport: Arc<dyn Transport>,
+    registry: Arc<Registry>,
+    clock: Clock,
+) -> Result<NodeHandle> {
+    let shutdown = Shutdown::new();
+    let mut handle = NodeHandle {
+        shutdown: shutdown.clone(),
+        threads: vec![],
+    };
+
+    if let Some(address) = &config.metrics.listen_address {
+        let (addr, thread) = registry::serve(registry.clone(), address, shutdown.clone())
+            .context("failed to start metrics endpoint")?;
+        info!("serving metrics on {}", addr);
+        handle.threads.push(("metrics", thread));
+    }
+    let metrics = NodeMetrics::new(registry);
+
     let (peer_event_sender, peer_event_receiver) = channel::bounded(1000);
     let (command_sender, command_receiver) = channel::bounded(1000);
     let (block_sender, block_receiver) = channel::bounded(1000);
     let (mining_info_sender, mining_info_receiver) = channel::bounded(1000);
 
-    let mut peer_service =
-        PeerService::new(config.peer_service, peer_event_sender, command_receiver)
-            .context("failed to create peer service")?;
+    let bans = Bans::new(
+        config.peer_service.ban_score_threshold,
+        config.peer_service.ban_duration,
+    );
+    let mut peer_service = PeerService::new(
+        config.peer_service,
+        config.chain.clone(),
+        transport,
+        PeerChannels {
+            peer_event_sender,
+            command_receiver,
+        },
+        bans,
+        metrics.clone(),
+        clock.clone(),
+        shutdown.clone(),
+    )
+    .context("failed to create peer service")?;
 
+    let block_downloader = BlockDownloader::new(
+        config.gossip_service.sync_max_in_flight_per_session,
+        config.gossip_service.sync_request_timeout,
+    );
     let mut gossip_service = GossipService::new(
         config.gossip_service,
-        peer_event_receiver,
-        command_sender,
-        block_receiver,
-        mining_info_sender,
+        config.chain.clone(),
+        GossipChannels {
+            event_receiver: peer_event_receiver,
+            command_sender,
+            block_receiver,
+            mining_info_sender,
+        },
+        block_downloader,
+        metrics.clone(),
+        clock.clone(),
+        shutdown.clone(),
     );
 
-    let mut mining_service =
-        MiningService::new(config.mining_service, mining_info_receiver, block_sender);
+    let mut mining_service = MiningService::new(
+        config.mining_service,
+        config.chain,
+        mining_info_receiver,
+        block_sender,
+        metrics,
+        clock,
+        shutdown,
+    );
+
+    handle.spawn("gossip", move || gossip_service.run())?;
+    handle.spawn("mining", move || mining_service.run())?;
+    handle.spawn("peer", move || peer_service.run())?;
+    Ok(handle)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Running node. Dropping the handle shuts the node down.
+pub struct NodeHandle {
+    shutdown: Shutdown,
+    threads: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl NodeHandle {
+    /// Asks the services to stop and waits for them. The services drop their
+    /// channels, and the peer service sends the messages already queued for the
+    /// sessions before closing them.
+    ///
+    /// Returns `Requested`, unless a service had stopped the node before.
+    pub fn shutdown(mut self) -> ShutdownReason {
+        self.shutdown.trigger(ShutdownReason::Requested);
+        self.join()
+    }
+
+    /// Waits until a service terminates, then stops the others.
+    pub fn wait(mut self) -> ShutdownReason {
+        let _ = self.shutdown.receiver().recv();
+        self.join()
+    }
+
+    fn spawn(
+        &mut self,
+        service: &'static str,
+        run: impl FnOnce() -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        let shutdown = self.shutdown.clone();
+        let thread = thread::Builder::new()
+            .name(format!("{}-service", service))
+            .spawn(move || {
+                let reason = match panic::catch_unwind(AssertUnwindSafe(run)) {
+                    Ok(Ok(())) => ShutdownReason::ServiceExited(service),
+                    Ok(Err(err)) => ShutdownReason::ServiceFailed {
+                        service,
+                        error: format!("{:#}", err),
+                    },
+                    Err(payload) => ShutdownReason::ServiceFailed {
+                        service,
+                        error: panic_message(payload.as_ref()),
+                    },
+                };
+                if !shutdown.is_triggered() {
+                    error!("stopping the node: {}", reason);
+                }
+                shutdown.trigger(reason);
+            });
+
+        match thread {
+            Ok(thread) => {
+                self.threads.push((service, thread));
+                Ok(())
+            }
+            Err(err) => {
+                self.shutdown.trigger(ShutdownReason::ServiceFailed {
+                    service,
+                    error: err.to_string(),
+                });
+                self.join();
+                Err(err).context(format!("failed to spawn {} service", service))
+            }
+        }
+    }
+
+    fn join(&mut self) -> ShutdownReason {
+        for (service, thread) in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("{} service thread panicked", service);
+            }
+        }
+        self.shutdown
+            .reason()
+            .expect("services stopped without a shutdown reason")
+    }
+}
+
+impl Drop for NodeHandle {
+    fn drop(&mut self) {
+        if !self.threads.is_empty() {
+            self.shutdown.trigger(ShutdownReason::Requested);
+            self.join();
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
 
-    thread::spawn(move || {
-        gossip_service.run();
-        panic!("gossip service terminated");
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    thread::spawn(move || {
-        mining_service.run();
-        panic!("mining service terminated");
-    });
+    #[test]
+    fn test_example_config() {
+        let config: Config = serde_yaml::from_str(include_str!("../config/example.yaml")).unwrap();
+        assert_eq!(config.peer_service.ban_score_threshold, 100);
+        assert_eq!(config.gossip_service.sync_max_in_flight_per_session, 16);
+    }
 
-    peer_service.run();
-    panic!("peer service terminated");
+    #[test]
+    fn test_config_defaults() {
+        // The config of the nodes that were running before the newer parameters appeared.
+        let yaml = r#"
+peer_service:
+  dial_cooldown: 3s
+  listen_address: localhost:9090
+  dial_addresses: []
+gossip_service:
+  eager_requests_interval: 10s
+mining_service:
+  thread_count: 1
+  max_tx_per_block: 10
+  public_key: "MIICIDALBgkqhkiG9w0BAQEDggIPADCCAgoCggIBALxKt7onRkrDCXqAypnqbmqk+xQbvJfAxW/mhifdC4at4KpXa2jQhjCL9mtmLqK6TEtFKsD+KmcGUJ9ww9SddnhT9cZgb6lZ6p0e1JhJwXzCiuz0ft8F7tFVbKG2DE3lLWYNu7d/fozC8v2gPvqknzhJLTjMDbdGriXrQCesGsj7YJvE2Je72GXNfcgq/y1ZwPKcYzpuetNgQupXckmgLFqsXAuqinruPxHA2RzqxD+CTUMZX7UGuH/cjJmneb2hPYtzvXHOycWBmOuQ28zDTTebepXIyTKZw355iJl+x/SYg/++ja5tIRnD+djxSF5DqRrcnertEbg7ugjXPx2EJSghI0ulZADA5/Y26VyfzeN259lm+VVQrlcKcg/DD9m6mIFjxfmOTZwaom74Kpkbbi4IBzxAqwZW5/L/Sl6ZJH9y3Ucw6zlIL66H/7qpGMsVQtlO+1RZxem3y9CIKvavuhpsiokqhnwglzXBamb/i7M6j5/3xXsZ5RIFH4dzyv/pCw7xIqUCPchPNNEaIcGn+6PoleoXZB0bXCnSnYHlnnC2oABUv8bmy4DF8xDzG6sWlM9UNY9V+wmKnxALm1cvRImnq6L6rjwDZMXvWNioZDFlgXd6O+ThGiXUNAArhv4+VOv2yrui7wLgnvhtmmWnSCgtIH85nlX1PO9j0osJX3k1AgMBAAE="
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let default = Config::default();
+        assert_eq!(config.chain, default.chain);
+        assert_eq!(
+            config.peer_service.ban_score_threshold,
+            default.peer_service.ban_score_threshold
+        );
+        assert_eq!(
+            config.peer_service.ban_duration,
+            default.peer_service.ban_duration
+        );
+        assert_eq!(
+            config.peer_service.handshake_timeout,
+            default.peer_service.handshake_timeout
+        );
+        assert_eq!(
+            config.gossip_service.max_requests_per_second,
+            default.gossip_service.max_requests_per_second
+        );
+        assert_eq!(
+            config.gossip_service.sync_max_in_flight_per_session,
+            default.gossip_service.sync_max_in_flight_per_session
+        );
+        assert_eq!(config.gossip_service.mempool.max_size, 10000);
+        assert!(config.metrics.listen_address.is_none());
+    }
 }
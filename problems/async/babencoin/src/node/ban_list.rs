@@ -0,0 +1,308 @@
+#![forbid(unsafe_code)]
+
+use crate::{data::PeerMessage, node::peer_service::SessionId};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// The message is not a valid JSON or is not a valid `PeerMessage`.
+    MalformedMessage,
+    /// The block was rejected by `Block::verified`.
+    InvalidBlock,
+    /// The transaction was rejected by `Transaction::verified`.
+    InvalidTransaction,
+    /// The block was rejected by `BlockForest::add_block`.
+    BadBlock,
+    /// The headers were rejected by `BlockForest::add_headers`.
+    BadHeaders,
+    /// The peer sends requests faster than allowed.
+    RequestFlood,
+}
+
+impl Misbehaviour {
+    /// The misbehaviour of sending a message that `PeerMessage::verified` rejects.
+    pub fn of_unverified(message: &PeerMessage) -> Self {
+        match message {
+            PeerMessage::Block(_) | PeerMessage::Headers { .. } => Self::InvalidBlock,
+            PeerMessage::Transaction(_) => Self::InvalidTransaction,
+            _ => Self::MalformedMessage,
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        match self {
+            Self::MalformedMessage => 50,
+            Self::InvalidBlock => 50,
+            Self::InvalidTransaction => 20,
+            Self::BadBlock => 50,
+            Self::BadHeaders => 50,
+            Self::RequestFlood => 10,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Accumulates misbehaviour scores of the sessions.
+pub struct BanScores {
+    threshold: u32,
+    scores: HashMap<SessionId, u32>,
+}
+
+impl BanScores {
+    /// Zero `threshold` disables scoring.
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            scores: HashMap::new(),
+        }
+    }
+
+    pub fn score(&self, session_id: SessionId) -> u32 {
+        self.scores.get(&session_id).copied().unwrap_or(0)
+    }
+
+    /// Returns true if the session score has just crossed the threshold.
+    pub fn add(&mut self, session_id: SessionId, misbehaviour: Misbehaviour) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+
+        let score = self.scores.entry(session_id).or_insert(0);
+        let was_below = *score < self.threshold;
+        *score = score.saturating_add(misbehaviour.score());
+        was_below && *score >= self.threshold
+    }
+
+    pub fn remove(&mut self, session_id: SessionId) {
+        self.scores.remove(&session_id);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Set of banned addresses. Each ban expires after `duration`.
+pub struct BanList {
+    duration: Duration,
+    banned_until: HashMap<IpAddr, Instant>,
+}
+
+impl BanList {
+    /// Zero `duration` disables banning.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            banned_until: HashMap::new(),
+        }
+    }
+
+    pub fn ban(&mut self, addr: IpAddr, now: Instant) {
+        if self.duration.is_zero() {
+            return;
+        }
+        // Bans are rare, so this is the place to forget the expired ones
+        // that were never looked up again.
+        self.banned_until.retain(|_, until| *until > now);
+        self.banned_until.insert(addr, now + self.duration);
+    }
+
+    pub fn is_banned(&mut self, addr: &IpAddr, now: Instant) -> bool {
+        match self.banned_until.get(addr) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                self.banned_until.remove(addr);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Misbehaviour scores of the sessions together with the addresses banned for them.
+pub struct Bans {
+    scores: BanScores,
+    list: BanList,
+}
+
+impl Bans {
+    pub fn new(threshold: u32, duration: Duration) -> Self {
+        Self {
+            scores: BanScores::new(threshold),
+            list: BanList::new(duration),
+        }
+    }
+
+    /// Adds the misbehaviour to the score of the session. Returns true if the score
+    /// has just crossed the threshold: the session must be dropped then, and `addr`
+    /// is banned.
+    pub fn report(
+        &mut self,
+        session_id: SessionId,
+        addr: IpAddr,
+        misbehaviour: Misbehaviour,
+        now: Instant,
+    ) -> bool {
+        if !self.scores.add(session_id, misbehaviour) {
+            return false;
+        }
+        self.list.ban(addr, now);
+        true
+    }
+
+    pub fn is_banned(&mut self, addr: &IpAddr, now: Instant) -> bool {
+        self.list.is_banned(addr, now)
+    }
+
+    pub fn on_session_closed(&mut self, session_id: SessionId) {
+        self.scores.remove(session_id);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Limits the number of block requests a session may send within any second.
+pub struct RequestLimiter {
+    max_per_second: u32,
+    requested_at: HashMap<SessionId, VecDeque<Instant>>,
+}
+
+impl RequestLimiter {
+    /// Zero `max_per_second` disables the limit.
+    pub fn new(max_per_second: u32) -> Self {
+        Self {
+            max_per_second,
+            requested_at: HashMap::new(),
+        }
+    }
+
+    /// Returns `RequestFlood` if the request exceeds the limit. Excess requests
+    /// don't count towards it.
+    pub fn on_request(&mut self, session_id: SessionId, now: Instant) -> Option<Misbehaviour> {
+        if self.max_per_second == 0 {
+            return None;
+        }
+
+        let requested_at = self.requested_at.entry(session_id).or_default();
+        while let Some(first) = requested_at.front() {
+            if now.duration_since(*first) < Duration::from_secs(1) {
+                break;
+            }
+            requested_at.pop_front();
+        }
+        if requested_at.len() >= self.max_per_second as usize {
+            return Some(Misbehaviour::RequestFlood);
+        }
+        requested_at.push_back(now);
+        None
+    }
+
+    pub fn on_session_closed(&mut self, session_id: SessionId) {
+        self.requested_at.remove(&session_id);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::Block;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_ban_scores() {
+        let mut scores = BanScores::new(100);
+        assert!(!scores.add(1, Misbehaviour::InvalidBlock));
+        assert!(!scores.add(2, Misbehaviour::InvalidBlock));
+        assert!(scores.add(1, Misbehaviour::BadBlock));
+        assert!(!scores.add(1, Misbehaviour::BadBlock));
+        assert_eq!(scores.score(1), 150);
+
+        scores.remove(1);
+        assert_eq!(scores.score(1), 0);
+        assert_eq!(scores.score(2), 50);
+
+        let mut disabled = BanScores::new(0);
+        assert!(!disabled.add(1, Misbehaviour::MalformedMessage));
+    }
+
+    #[test]
+    fn test_ban_list() {
+        let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let now = Instant::now();
+        let mut ban_list = BanList::new(Duration::from_secs(60));
+        assert!(!ban_list.is_banned(&addr, now));
+        ban_list.ban(addr, now);
+        assert!(ban_list.is_banned(&addr, now + Duration::from_secs(59)));
+        assert!(!ban_list.is_banned(&addr, now + Duration::from_secs(60)));
+        assert!(!ban_list.is_banned(&addr, now));
+
+        let mut disabled = BanList::new(Duration::ZERO);
+        disabled.ban(addr, now);
+        assert!(!disabled.is_banned(&addr, now));
+    }
+
+    #[test]
+    fn test_bans() {
+        let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let now = Instant::now();
+        let mut bans = Bans::new(100, Duration::from_secs(60));
+        assert!(!bans.report(1, addr, Misbehaviour::BadBlock, now));
+        assert!(!bans.is_banned(&addr, now));
+        assert!(bans.report(1, addr, Misbehaviour::BadHeaders, now));
+        assert!(bans.is_banned(&addr, now));
+
+        bans.on_session_closed(1);
+        assert!(!bans.report(1, addr, Misbehaviour::BadBlock, now));
+    }
+
+    #[test]
+    fn test_of_unverified() {
+        let block: Block =
+            serde_json::from_str(include_str!("../../data/test_block.json")).unwrap();
+        let tx = block.transactions[0].clone();
+        assert_eq!(
+            Misbehaviour::of_unverified(&PeerMessage::Block(Box::new(block))),
+            Misbehaviour::InvalidBlock
+        );
+        assert_eq!(
+            Misbehaviour::of_unverified(&PeerMessage::Transaction(Box::new(tx))),
+            Misbehaviour::InvalidTransaction
+        );
+    }
+
+    #[test]
+    fn test_request_limiter() {
+        let now = Instant::now();
+        let mut limiter = RequestLimiter::new(2);
+        assert_eq!(limiter.on_request(1, now), None);
+        assert_eq!(limiter.on_request(1, now), None);
+        assert_eq!(limiter.on_request(2, now), None);
+        assert_eq!(
+            limiter.on_request(1, now + Duration::from_millis(999)),
+            Some(Misbehaviour::RequestFlood)
+        );
+        assert_eq!(limiter.on_request(1, now + Duration::from_secs(1)), None);
+
+        limiter.on_session_closed(1);
+        assert_eq!(limiter.on_request(1, now + Duration::from_secs(1)), None);
+
+        let mut disabled = RequestLimiter::new(0);
+        for _ in 0..10 {
+            assert_eq!(disabled.on_request(1, now), None);
+        }
+    }
+}
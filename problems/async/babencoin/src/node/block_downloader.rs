@@ -0,0 +1,209 @@
+#![forbid(unsafe_code)]
+
+use crate::{data::BlockHash, node::peer_service::SessionId};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Distributes block requests among the sessions, so that blocks whose headers
+/// are already known are downloaded from several peers in parallel.
+pub struct BlockDownloader {
+    max_in_flight_per_session: usize,
+    request_timeout: Duration,
+    /// Queued blocks by their position in the queue, which is negative for the
+    /// requeued ones, so that they go first.
+    queue: BTreeMap<i64, BlockHash>,
+    queue_positions: HashMap<BlockHash, i64>,
+    next_front: i64,
+    next_back: i64,
+    in_flight: HashMap<BlockHash, (SessionId, Instant)>,
+    session_loads: HashMap<SessionId, usize>,
+}
+
+impl BlockDownloader {
+    pub fn new(max_in_flight_per_session: usize, request_timeout: Duration) -> Self {
+        Self {
+            max_in_flight_per_session,
+            request_timeout,
+            queue: BTreeMap::new(),
+            queue_positions: HashMap::new(),
+            next_front: -1,
+            next_back: 0,
+            in_flight: HashMap::new(),
+            session_loads: HashMap::new(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    pub fn enqueue(&mut self, hashes: impl IntoIterator<Item = BlockHash>) {
+        for hash in hashes {
+            if self.in_flight.contains_key(&hash) || self.queue_positions.contains_key(&hash) {
+                continue;
+            }
+            self.queue_positions.insert(hash, self.next_back);
+            self.queue.insert(self.next_back, hash);
+            self.next_back += 1;
+        }
+    }
+
+    /// Must be called for every received block. Returns true if the block was requested.
+    pub fn on_block(&mut self, hash: &BlockHash) -> bool {
+        if let Some(position) = self.queue_positions.remove(hash) {
+            self.queue.remove(&position);
+            return true;
+        }
+
+        match self.in_flight.remove(hash) {
+            Some((session_id, _)) => {
+                self.release(session_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Puts the blocks requested from the session back to the queue.
+    pub fn on_session_closed(&mut self, session_id: SessionId) {
+        let hashes: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (id, _))| *id == session_id)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in hashes {
+            self.in_flight.remove(&hash);
+            self.requeue(hash);
+        }
+        self.session_loads.remove(&session_id);
+    }
+
+    /// Assigns queued blocks to the least loaded of the given sessions. Timed out
+    /// requests are reassigned. Returns the requests to be sent.
+    pub fn schedule(
+        &mut self,
+        session_ids: &[SessionId],
+        now: Instant,
+    ) -> Vec<(SessionId, BlockHash)> {
+        let expired: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (_, requested_at))| {
+                now.duration_since(*requested_at) >= self.request_timeout
+            })
+            .map(|(hash, (session_id, _))| (*hash, *session_id))
+            .collect();
+        for (hash, session_id) in expired {
+            self.in_flight.remove(&hash);
+            self.release(session_id);
+            self.requeue(hash);
+        }
+
+        let mut requests = vec![];
+        while !self.queue.is_empty() {
+            let session_id = match session_ids
+                .iter()
+                .map(|id| (*id, self.session_loads.get(id).copied().unwrap_or(0)))
+                .filter(|(_, load)| *load < self.max_in_flight_per_session)
+                .min_by_key(|(_, load)| *load)
+            {
+                Some((id, _)) => id,
+                None => break,
+            };
+
+            let (_, hash) = self.queue.pop_first().unwrap();
+            self.queue_positions.remove(&hash);
+            self.in_flight.insert(hash, (session_id, now));
+            *self.session_loads.entry(session_id).or_insert(0) += 1;
+            requests.push((session_id, hash));
+        }
+        requests
+    }
+
+    fn requeue(&mut self, hash: BlockHash) {
+        if !self.queue_positions.contains_key(&hash) {
+            self.queue_positions.insert(hash, self.next_front);
+            self.queue.insert(self.next_front, hash);
+            self.next_front -= 1;
+        }
+    }
+
+    fn release(&mut self, session_id: SessionId) {
+        if let Some(load) = self.session_loads.get_mut(&session_id) {
+            *load -= 1;
+            if *load == 0 {
+                self.session_loads.remove(&session_id);
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::HASH_LEN;
+
+    fn hash(i: u8) -> BlockHash {
+        [i; HASH_LEN]
+    }
+
+    #[test]
+    fn test_schedule() {
+        let now = Instant::now();
+        let mut downloader = BlockDownloader::new(2, Duration::from_secs(10));
+        downloader.enqueue((0..5).map(hash));
+        downloader.enqueue((0..5).map(hash));
+
+        let requests = downloader.schedule(&[1, 2], now);
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests.iter().filter(|(id, _)| *id == 1).count(), 2);
+        assert_eq!(requests.iter().filter(|(id, _)| *id == 2).count(), 2);
+        assert!(downloader.schedule(&[1, 2], now).is_empty());
+
+        let (session_id, block_hash) = requests[0];
+        assert!(downloader.on_block(&block_hash));
+        assert!(!downloader.on_block(&block_hash));
+        assert_eq!(
+            downloader.schedule(&[1, 2], now),
+            vec![(session_id, hash(4))]
+        );
+
+        for i in 0..5 {
+            downloader.on_block(&hash(i));
+        }
+        assert!(downloader.is_idle());
+    }
+
+    #[test]
+    fn test_session_closed() {
+        let now = Instant::now();
+        let mut downloader = BlockDownloader::new(10, Duration::from_secs(10));
+        downloader.enqueue((0..3).map(hash));
+        assert_eq!(downloader.schedule(&[1], now).len(), 3);
+
+        downloader.on_session_closed(1);
+        let requests = downloader.schedule(&[2], now);
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|(id, _)| *id == 2));
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let now = Instant::now();
+        let mut downloader = BlockDownloader::new(1, Duration::from_millis(50));
+        downloader.enqueue([hash(0)]);
+        assert_eq!(downloader.schedule(&[1], now), vec![(1, hash(0))]);
+        assert!(downloader.schedule(&[1, 2], now).is_empty());
+
+        let later = now + Duration::from_millis(50);
+        assert_eq!(downloader.schedule(&[2], later), vec![(2, hash(0))]);
+    }
+}
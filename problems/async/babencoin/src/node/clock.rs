@@ -0,0 +1,159 @@
+#![forbid(unsafe_code)]
+
+use crossbeam::channel::{self, Receiver, Sender};
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Source of time of the node services and the in-memory network. The services
+/// must use it instead of `Instant::now()`, `thread::sleep()` and `channel::after()`,
+/// so that simulations can run them on virtual time.
+#[derive(Clone, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Virtual(Arc<VirtualClock>),
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Self::System => Instant::now(),
+            Self::Virtual(clock) => clock.now(),
+        }
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration);
+    }
+
+    pub fn sleep_until(&self, deadline: Instant) {
+        while !self.wait_until(deadline, Duration::from_secs(1)) {}
+    }
+
+    /// Sleeps until `deadline`, but no longer than `timeout` of real time.
+    /// Returns whether the deadline has come.
+    pub fn wait_until(&self, deadline: Instant, timeout: Duration) -> bool {
+        match self {
+            Self::System => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                thread::sleep(left.min(timeout));
+                left <= timeout
+            }
+            Self::Virtual(clock) => clock.wait_until(deadline, timeout),
+        }
+    }
+
+    /// Like `channel::after()`: the channel receives the time once `duration` passes.
+    pub fn after(&self, duration: Duration) -> Receiver<Instant> {
+        match self {
+            Self::System => channel::after(duration),
+            Self::Virtual(clock) => clock.after(duration),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct VirtualState {
+    now: Instant,
+    timers: BTreeMap<(Instant, u64), Sender<Instant>>,
+    next_timer: u64,
+}
+
+/// Time that passes only when `advance()` is called.
+pub struct VirtualClock {
+    state: Mutex<VirtualState>,
+    advanced: Condvar,
+}
+
+impl VirtualClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(VirtualState {
+                now: Instant::now(),
+                timers: BTreeMap::new(),
+                next_timer: 0,
+            }),
+            advanced: Condvar::new(),
+        })
+    }
+
+    pub fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    /// Moves the time forward, waking up the sleepers and firing the timers
+    /// whose deadlines have come.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+        let now = state.now;
+        while let Some(entry) = state.timers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let _ = entry.remove().send(now);
+        }
+        drop(state);
+        self.advanced.notify_all();
+    }
+
+    fn wait_until(&self, deadline: Instant, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .advanced
+            .wait_timeout_while(state, timeout, |state| state.now < deadline)
+            .unwrap();
+        state.now >= deadline
+    }
+
+    fn after(&self, duration: Duration) -> Receiver<Instant> {
+        let (sender, receiver) = channel::bounded(1);
+        let mut state = self.state.lock().unwrap();
+        if duration.is_zero() {
+            let _ = sender.send(state.now);
+            return receiver;
+        }
+        let key = (state.now + duration, state.next_timer);
+        state.next_timer += 1;
+        state.timers.insert(key, sender);
+        receiver
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let virtual_clock = VirtualClock::new();
+        let clock = Clock::Virtual(virtual_clock.clone());
+        let start = clock.now();
+
+        let timer = clock.after(Duration::from_secs(10));
+        assert!(!clock.wait_until(start + Duration::from_secs(1), Duration::from_millis(10)));
+        assert!(timer.try_recv().is_err());
+
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || clock.sleep_until(start + Duration::from_secs(5)))
+        };
+        virtual_clock.advance(Duration::from_secs(5));
+        sleeper.join().unwrap();
+        assert!(timer.try_recv().is_err());
+
+        virtual_clock.advance(Duration::from_secs(5));
+        assert_eq!(timer.try_recv().unwrap(), start + Duration::from_secs(10));
+        assert_eq!(clock.now() - start, Duration::from_secs(10));
+    }
+}
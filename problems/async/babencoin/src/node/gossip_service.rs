@@ -2,9 +2,19 @@
 
 use crate::{
     block_forest::BlockForest,
-    data::{BlockHash, TransactionHash, VerifiedBlock, VerifiedPeerMessage, VerifiedTransaction},
+    chain_params::ChainParams,
+    data::{
+        BlockHash, TransactionHash, VerifiedBlock, VerifiedPeerMessage, VerifiedTransaction,
+        MAX_HEADERS_PER_MESSAGE,
+    },
+    mempool::MempoolConfig,
+    node::ban_list::Misbehaviour,
+    node::block_downloader::BlockDownloader,
+    node::clock::Clock,
+    node::metrics::NodeMetrics,
     node::mining_service::MiningInfo,
     node::peer_service::{PeerCommand, PeerCommandKind, PeerEvent, PeerEventKind, SessionId},
+    node::shutdown::Shutdown,
 };
 
 use anyhow::{Context, Result};
@@ -24,35 +34,83 @@ use std::{
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct GossipServiceConfig {
     #[serde(with = "humantime_serde")]
     pub eager_requests_interval: Duration,
+    #[serde(default = "default_max_requests_per_second")]
+    pub max_requests_per_second: u32,
+    #[serde(default = "default_sync_max_in_flight_per_session")]
+    pub sync_max_in_flight_per_session: usize,
+    #[serde(with = "humantime_serde", default = "default_sync_request_timeout")]
+    pub sync_request_timeout: Duration,
+    #[serde(default)]
+    pub mempool: MempoolConfig,
+}
+
+impl Default for GossipServiceConfig {
+    fn default() -> Self {
+        Self {
+            eager_requests_interval: Duration::ZERO,
+            max_requests_per_second: default_max_requests_per_second(),
+            sync_max_in_flight_per_session: default_sync_max_in_flight_per_session(),
+            sync_request_timeout: default_sync_request_timeout(),
+            mempool: MempoolConfig::default(),
+        }
+    }
+}
+
+fn default_max_requests_per_second() -> u32 {
+    1000
+}
+
+fn default_sync_max_in_flight_per_session() -> usize {
+    16
+}
+
+fn default_sync_request_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// The channels of the gossip service to the peer service and the mining service.
+pub struct GossipChannels {
+    pub event_receiver: Receiver<PeerEvent>,
+    pub command_sender: Sender<PeerCommand>,
+    pub block_receiver: Receiver<VerifiedBlock>,
+    pub mining_info_sender: Sender<MiningInfo>,
 }
 
 pub struct GossipService {
     config: GossipServiceConfig,
+    chain: ChainParams,
     event_receiver: Receiver<PeerEvent>,
     command_sender: Sender<PeerCommand>,
     block_receiver: Receiver<VerifiedBlock>,
     mining_info_sender: Sender<MiningInfo>,
     block_forest: BlockForest,
+    block_downloader: BlockDownloader,
+    metrics: NodeMetrics,
+    clock: Clock,
+    shutdown: Shutdown,
     // TODO: your code goes here.
 }
 
 impl GossipService {
+    /// `block_downloader` is created from the `sync_*` parameters of the config.
     pub fn new(
         config: GossipServiceConfig,
-        event_receiver: Receiver<PeerEvent>,
-        command_sender: Sender<PeerCommand>,
-        block_receiver: Receiver<VerifiedBlock>,
-        mining_info_sender: Sender<MiningInfo>,
+        chain: ChainParams,
+        channels: GossipChannels,
+        block_downloader: BlockDownloader,
+        metrics: NodeMetrics,
+        clock: Clock,
+        shutdown: Shutdown,
     ) -> Self {
         // TODO: your code goes here.
         unimplemented!()
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
     }
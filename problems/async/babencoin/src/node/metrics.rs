@@ -0,0 +1,179 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    block_forest::BlockForest,
+    metrics::{Gauge, Registry},
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Where to serve `GET /metrics`. If `None`, the metrics are only kept in memory.
+    pub listen_address: Option<String>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Metrics of the node services, shared by all of them.
+#[derive(Clone)]
+pub struct NodeMetrics {
+    registry: Arc<Registry>,
+    sessions: Arc<Gauge>,
+    head_index: Arc<Gauge>,
+    fork_count: Arc<Gauge>,
+    unknown_blocks: Arc<Gauge>,
+    mempool_size: Arc<Gauge>,
+}
+
+impl NodeMetrics {
+    pub fn new(registry: Arc<Registry>) -> Self {
+        Self {
+            sessions: registry.gauge("babencoin_sessions", "Connected sessions.", &[]),
+            head_index: registry.gauge("babencoin_head_index", "Index of the head block.", &[]),
+            fork_count: registry.gauge(
+                "babencoin_fork_count",
+                "Known chain tips other than the head.",
+                &[],
+            ),
+            unknown_blocks: registry.gauge(
+                "babencoin_unknown_blocks",
+                "Blocks known only as ancestors of other blocks.",
+                &[],
+            ),
+            mempool_size: registry.gauge("babencoin_mempool_size", "Pending transactions.", &[]),
+            registry,
+        }
+    }
+
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    /// Counts a connected session until the returned value is dropped.
+    pub fn open_session(&self) -> SessionMetrics {
+        self.sessions.inc();
+        SessionMetrics {
+            registry: self.registry.clone(),
+            sessions: self.sessions.clone(),
+        }
+    }
+
+    /// Hashes per second computed by the mining thread.
+    pub fn hash_rate(&self, thread: usize) -> Arc<Gauge> {
+        self.registry.gauge(
+            "babencoin_hash_rate",
+            "Hashes per second computed by the mining thread.",
+            &[("thread", &thread.to_string())],
+        )
+    }
+
+    /// Updates the gauges of the block forest and the mempool.
+    pub fn observe_forest(&self, block_forest: &BlockForest) {
+        self.head_index.set(block_forest.head().index as f64);
+        self.fork_count.set(block_forest.fork_count() as f64);
+        self.unknown_blocks
+            .set(block_forest.unknown_block_hashes().len() as f64);
+        self.mempool_size
+            .set(block_forest.pending_transactions().len() as f64);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Metrics of a single session. The session stops being counted when this is dropped,
+/// so keep it in the state of the session.
+pub struct SessionMetrics {
+    registry: Arc<Registry>,
+    sessions: Arc<Gauge>,
+}
+
+impl SessionMetrics {
+    /// `kind` is the one of `PeerMessage::kind()`.
+    pub fn message_received(&self, kind: &str) {
+        self.registry
+            .counter(
+                "babencoin_messages_received_total",
+                "Messages received from the peers.",
+                &[("kind", kind)],
+            )
+            .inc();
+    }
+
+    /// `kind` is the one of `PeerMessage::kind()`.
+    pub fn message_sent(&self, kind: &str) {
+        self.registry
+            .counter(
+                "babencoin_messages_sent_total",
+                "Messages sent to the peers.",
+                &[("kind", kind)],
+            )
+            .inc();
+    }
+}
+
+impl Drop for SessionMetrics {
+    fn drop(&mut self) {
+        self.sessions.dec();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        chain_params::ChainParams,
+        data::{Block, VerifiedBlock},
+    };
+
+    #[test]
+    fn test_observe_forest() {
+        let metrics = NodeMetrics::new(Arc::new(Registry::new()));
+        let mut block_forest = BlockForest::new();
+
+        let mut block = Block::genesis();
+        block.index = 1;
+        block.timestamp += chrono::Duration::seconds(1);
+        block.prev_hash = *VerifiedBlock::genesis().hash();
+        let first = block.clone().verified(&ChainParams::mainnet()).unwrap();
+        block.nonce = 1;
+        let second = block.verified(&ChainParams::mainnet()).unwrap();
+        block_forest.add_block(first).unwrap();
+        block_forest.add_block(second).unwrap();
+
+        metrics.observe_forest(&block_forest);
+        let session = metrics.open_session();
+        session.message_received("block");
+        session.message_sent("request");
+        metrics.hash_rate(1).set(100.);
+
+        let registry = metrics.registry();
+        assert_eq!(registry.get("babencoin_head_index", &[]), Some(1.));
+        assert_eq!(registry.get("babencoin_fork_count", &[]), Some(1.));
+        assert_eq!(registry.get("babencoin_unknown_blocks", &[]), Some(0.));
+        assert_eq!(registry.get("babencoin_mempool_size", &[]), Some(0.));
+        assert_eq!(registry.get("babencoin_sessions", &[]), Some(1.));
+        assert_eq!(
+            registry.get("babencoin_messages_received_total", &[("kind", "block")]),
+            Some(1.)
+        );
+        assert_eq!(
+            registry.get("babencoin_messages_sent_total", &[("kind", "request")]),
+            Some(1.)
+        );
+        assert_eq!(
+            registry.get("babencoin_hash_rate", &[("thread", "1")]),
+            Some(100.)
+        );
+
+        drop(session);
+        assert_eq!(registry.get("babencoin_sessions", &[]), Some(0.));
+    }
+}
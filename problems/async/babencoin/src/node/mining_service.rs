@@ -6,10 +6,13 @@ use std::{
 };
 
 use crate::{
+    chain_params::ChainParams,
     data::{
         Block, BlockAttributes, BlockHash, Transaction, VerifiedBlock, VerifiedTransaction,
-        WalletId, MAX_REWARD,
+        WalletId,
     },
+    mining::PowKind,
+    node::{clock::Clock, metrics::NodeMetrics, shutdown::Shutdown},
     util::{deserialize_wallet_id, serialize_wallet_id},
 };
 
@@ -34,6 +37,9 @@ pub struct MiningServiceConfig {
         deserialize_with = "deserialize_wallet_id"
     )]
     pub public_key: WalletId,
+
+    #[serde(default)]
+    pub pow: PowKind,
 }
 
 impl Default for MiningServiceConfig {
@@ -42,6 +48,7 @@ impl Default for MiningServiceConfig {
             thread_count: 0,
             max_tx_per_block: 0,
             public_key: WalletId::of_genesis(),
+            pow: PowKind::Sha3,
         }
     }
 }
@@ -58,26 +65,33 @@ pub struct MiningInfo {
 
 pub struct MiningService {
     config: MiningServiceConfig,
+    chain: ChainParams,
     info_receiver: Receiver<MiningInfo>,
     block_sender: Sender<VerifiedBlock>,
+    metrics: NodeMetrics,
+    clock: Clock,
+    shutdown: Shutdown,
     // TODO: your code goes here.
 }
 
 impl MiningService {
     pub fn new(
         config: MiningServiceConfig,
+        chain: ChainParams,
         info_receiver: Receiver<MiningInfo>,
         block_sender: Sender<VerifiedBlock>,
+        metrics: NodeMetrics,
+        clock: Clock,
+        shutdown: Shutdown,
     ) -> Self {
         // TODO: your code goes here.
         unimplemented!()
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
     }
 
     // TODO: your code goes here.
 }
-
@@ -1,6 +1,17 @@
 #![forbid(unsafe_code)]
 
-use crate::data::{PeerMessage, VerifiedPeerMessage};
+use crate::{
+    chain_params::ChainParams,
+    codec::{Codec, Hello, MessageDecoder, MessageEncoder},
+    data::{PeerMessage, VerifiedPeerMessage},
+    node::{
+        ban_list::{Bans, Misbehaviour},
+        clock::Clock,
+        metrics::NodeMetrics,
+        shutdown::Shutdown,
+        transport::{Connection, Listener, Transport},
+    },
+};
 
 use anyhow::{bail, Context, Result};
 use crossbeam::channel::{self, Receiver, Sender};
@@ -11,7 +22,6 @@ use std::{
     collections::HashMap,
     fmt::{self, Display},
     io::{self, BufReader, ErrorKind, Read, Write},
-    net::{TcpListener, TcpStream},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc, Mutex,
@@ -28,12 +38,47 @@ pub type SessionId = u64;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PeerServiceConfig {
     #[serde(with = "humantime_serde")]
     pub dial_cooldown: Duration,
     pub dial_addresses: Vec<String>,
     pub listen_address: Option<String>,
+    #[serde(default = "default_ban_score_threshold")]
+    pub ban_score_threshold: u32,
+    #[serde(with = "humantime_serde", default = "default_ban_duration")]
+    pub ban_duration: Duration,
+    /// Empty by default, so that the sessions use JSON like older nodes do.
+    #[serde(default)]
+    pub codecs: Vec<Codec>,
+    #[serde(with = "humantime_serde", default = "default_handshake_timeout")]
+    pub handshake_timeout: Duration,
+}
+
+impl Default for PeerServiceConfig {
+    fn default() -> Self {
+        Self {
+            dial_cooldown: Duration::ZERO,
+            dial_addresses: vec![],
+            listen_address: None,
+            ban_score_threshold: default_ban_score_threshold(),
+            ban_duration: default_ban_duration(),
+            codecs: vec![],
+            handshake_timeout: default_handshake_timeout(),
+        }
+    }
+}
+
+fn default_ban_score_threshold() -> u32 {
+    100
+}
+
+fn default_ban_duration() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_handshake_timeout() -> Duration {
+    Duration::from_secs(5)
 }
 
 #[derive(Debug, Clone)]
@@ -59,32 +104,51 @@ pub struct PeerCommand {
 pub enum PeerCommandKind {
     SendMessage(VerifiedPeerMessage),
     Drop,
+    Misbehaved(Misbehaviour),
+}
+
+/// The channels between the peer service and the gossip service.
+pub struct PeerChannels {
+    pub peer_event_sender: Sender<PeerEvent>,
+    pub command_receiver: Receiver<PeerCommand>,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct PeerService {
     config: PeerServiceConfig,
+    chain: ChainParams,
+    transport: Arc<dyn Transport>,
     peer_event_sender: Sender<PeerEvent>,
     command_receiver: Receiver<PeerCommand>,
+    bans: Bans,
+    metrics: NodeMetrics,
+    clock: Clock,
+    shutdown: Shutdown,
     // TODO: your code goes here.
 }
 
 impl PeerService {
+    /// `bans` is created from `ban_score_threshold` and `ban_duration` of the config.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: PeerServiceConfig,
-        peer_event_sender: Sender<PeerEvent>,
-        command_receiver: Receiver<PeerCommand>,
+        chain: ChainParams,
+        transport: Arc<dyn Transport>,
+        channels: PeerChannels,
+        bans: Bans,
+        metrics: NodeMetrics,
+        clock: Clock,
+        shutdown: Shutdown,
     ) -> Result<Self> {
         // TODO: your code goes here.
         unimplemented!()
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
     }
 
     // TODO: your code goes here.
 }
-
@@ -0,0 +1,131 @@
+#![forbid(unsafe_code)]
+
+use crossbeam::channel::{self, Receiver, Sender};
+
+use std::{
+    fmt::{self, Display},
+    sync::{Arc, Mutex},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// `NodeHandle::shutdown()` was called.
+    Requested,
+    /// The service returned although no shutdown was requested.
+    ServiceExited(&'static str),
+    /// The service returned an error or panicked.
+    ServiceFailed {
+        service: &'static str,
+        error: String,
+    },
+}
+
+impl Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Requested => write!(f, "shutdown requested"),
+            Self::ServiceExited(service) => write!(f, "{} service terminated", service),
+            Self::ServiceFailed { service, error } => {
+                write!(f, "{} service failed: {}", service, error)
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct State {
+    sender: Option<Sender<()>>,
+    reason: Option<ShutdownReason>,
+}
+
+/// Tells the services that the node is stopping. Nothing is ever sent to the
+/// receiver, it gets disconnected instead, so that the services can wait for
+/// it in `select!` together with their other channels.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: Receiver<()>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel::bounded(0);
+        Self {
+            receiver,
+            state: Arc::new(Mutex::new(State {
+                sender: Some(sender),
+                reason: None,
+            })),
+        }
+    }
+
+    pub fn receiver(&self) -> &Receiver<()> {
+        &self.receiver
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.state.lock().unwrap().sender.is_none()
+    }
+
+    /// Only the first reason is kept.
+    pub fn trigger(&self, reason: ShutdownReason) {
+        let mut state = self.state.lock().unwrap();
+        if state.reason.is_none() {
+            state.reason = Some(reason);
+        }
+        state.sender = None;
+    }
+
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        self.state.lock().unwrap().reason.clone()
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crossbeam::select;
+
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_shutdown() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_triggered());
+        assert_eq!(shutdown.reason(), None);
+
+        let waiter = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                let (_sender, receiver) = channel::unbounded::<()>();
+                select! {
+                    recv(receiver) -> _ => false,
+                    recv(shutdown.receiver()) -> msg => msg.is_err(),
+                }
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+
+        shutdown.trigger(ShutdownReason::ServiceExited("mining"));
+        shutdown.trigger(ShutdownReason::Requested);
+        assert!(waiter.join().unwrap());
+        assert!(shutdown.is_triggered());
+        assert_eq!(
+            shutdown.reason(),
+            Some(ShutdownReason::ServiceExited("mining"))
+        );
+        assert!(shutdown.receiver().recv().is_err());
+    }
+}
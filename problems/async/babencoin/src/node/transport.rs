@@ -0,0 +1,554 @@
+#![forbid(unsafe_code)]
+
+use super::clock::Clock;
+
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait Connection: Read + Write + Send {
+    fn try_clone(&self) -> io::Result<Box<dyn Connection>>;
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    /// Closes the connection in both directions, including all its clones.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+pub trait Listener: Send {
+    fn accept(&self) -> io::Result<Box<dyn Connection>>;
+}
+
+/// The way `PeerService` establishes connections.
+pub trait Transport: Send + Sync {
+    fn listen(&self, address: &str) -> io::Result<Box<dyn Listener>>;
+    fn dial(&self, address: &str) -> io::Result<Box<dyn Connection>>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        let (stream, _) = TcpListener::accept(self)?;
+        Ok(Box::new(stream))
+    }
+}
+
+#[derive(Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn listen(&self, address: &str) -> io::Result<Box<dyn Listener>> {
+        Ok(Box::new(TcpListener::bind(address)?))
+    }
+
+    fn dial(&self, address: &str) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpStream::connect(address)?))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug)]
+pub struct LinkConfig {
+    /// Delay of every write.
+    pub latency: Duration,
+    /// Upper bound of a uniformly distributed delay added to `latency`.
+    pub jitter: Duration,
+    /// Probability that a message is lost.
+    pub loss: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.,
+        }
+    }
+}
+
+impl LinkConfig {
+    pub(crate) fn sample_delay<R: Rng>(&self, rng: &mut R) -> Duration {
+        self.latency + self.jitter.mul_f64(rng.gen::<f64>())
+    }
+}
+
+struct NetworkState {
+    link: LinkConfig,
+    rng: StdRng,
+    listeners: HashMap<SocketAddr, Sender<MemoryConnection>>,
+    groups: Option<HashMap<IpAddr, usize>>,
+    next_port: u16,
+}
+
+impl NetworkState {
+    fn is_partitioned(&self, first: &IpAddr, second: &IpAddr) -> bool {
+        match &self.groups {
+            Some(groups) => groups.get(first) != groups.get(second),
+            None => false,
+        }
+    }
+}
+
+/// In-memory network of the nodes, each node identified by its IP address.
+///
+/// Writes are delivered after the link latency in order, measured by the clock of
+/// the network, which may be virtual. A byte stream can't lose
+/// bytes, so a lost write resets the connection, as a TCP connection over a lossy
+/// link eventually would. Connections between different partitions are reset and
+/// can't be established until the partition heals.
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<NetworkState>>,
+    clock: Clock,
+}
+
+impl MemoryNetwork {
+    pub fn new(link: LinkConfig, seed: u64) -> Self {
+        Self::with_clock(link, seed, Clock::System)
+    }
+
+    pub fn with_clock(link: LinkConfig, seed: u64, clock: Clock) -> Self {
+        Self {
+            clock,
+            state: Arc::new(Mutex::new(NetworkState {
+                link,
+                rng: StdRng::seed_from_u64(seed),
+                listeners: HashMap::new(),
+                groups: None,
+                next_port: 1,
+            })),
+        }
+    }
+
+    /// Transport of the node with the given address.
+    pub fn transport(&self, ip: IpAddr) -> MemoryTransport {
+        MemoryTransport {
+            network: self.clone(),
+            ip,
+        }
+    }
+
+    pub fn set_link(&self, link: LinkConfig) {
+        self.state.lock().unwrap().link = link;
+    }
+
+    /// Nodes from different groups can't reach each other. Nodes not listed in any
+    /// group are isolated.
+    pub fn partition(&self, groups: &[&[IpAddr]]) {
+        let mut group_ids = HashMap::new();
+        for (id, group) in groups.iter().enumerate() {
+            for ip in group.iter() {
+                group_ids.insert(*ip, id);
+            }
+        }
+        self.state.lock().unwrap().groups = Some(group_ids);
+    }
+
+    pub fn heal(&self) {
+        self.state.lock().unwrap().groups = None;
+    }
+
+    fn is_partitioned(&self, first: &IpAddr, second: &IpAddr) -> bool {
+        self.state.lock().unwrap().is_partitioned(first, second)
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    ip: IpAddr,
+}
+
+impl Transport for MemoryTransport {
+    fn listen(&self, address: &str) -> io::Result<Box<dyn Listener>> {
+        let addr = resolve(address)?;
+        if addr.ip() != self.ip {
+            return Err(io::Error::new(
+                ErrorKind::AddrNotAvailable,
+                "address doesn't belong to the node",
+            ));
+        }
+
+        let mut state = self.network.state.lock().unwrap();
+        if state.listeners.contains_key(&addr) {
+            return Err(io::Error::from(ErrorKind::AddrInUse));
+        }
+        let (sender, receiver) = channel::unbounded();
+        state.listeners.insert(addr, sender);
+        Ok(Box::new(MemoryListener {
+            network: self.network.clone(),
+            addr,
+            receiver,
+        }))
+    }
+
+    fn dial(&self, address: &str) -> io::Result<Box<dyn Connection>> {
+        let remote = resolve(address)?;
+
+        let mut state = self.network.state.lock().unwrap();
+        if state.is_partitioned(&self.ip, &remote.ip()) {
+            return Err(io::Error::from(ErrorKind::TimedOut));
+        }
+        let listener = state
+            .listeners
+            .get(&remote)
+            .ok_or_else(|| io::Error::from(ErrorKind::ConnectionRefused))?
+            .clone();
+
+        let local = SocketAddr::new(self.ip, state.next_port);
+        state.next_port = state.next_port.wrapping_add(1).max(1);
+        drop(state);
+
+        let (local_sender, remote_receiver) = channel::unbounded();
+        let (remote_sender, local_receiver) = channel::unbounded();
+        let closed = Arc::new(AtomicBool::new(false));
+        let remote_conn = MemoryConnection::new(
+            &self.network,
+            remote,
+            local,
+            closed.clone(),
+            remote_sender,
+            remote_receiver,
+        );
+        listener
+            .send(remote_conn)
+            .map_err(|_| io::Error::from(ErrorKind::ConnectionRefused))?;
+
+        Ok(Box::new(MemoryConnection::new(
+            &self.network,
+            local,
+            remote,
+            closed,
+            local_sender,
+            local_receiver,
+        )))
+    }
+}
+
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+    address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::from(ErrorKind::AddrNotAvailable))
+}
+
+struct MemoryListener {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    receiver: Receiver<MemoryConnection>,
+}
+
+impl Listener for MemoryListener {
+    fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self.receiver.recv() {
+            Ok(conn) => Ok(Box::new(conn)),
+            Err(_) => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.network
+            .state
+            .lock()
+            .unwrap()
+            .listeners
+            .remove(&self.addr);
+    }
+}
+
+struct Packet {
+    deliver_at: Instant,
+    data: Vec<u8>,
+}
+
+struct MemoryConnection {
+    network: MemoryNetwork,
+    local: SocketAddr,
+    peer: SocketAddr,
+    closed: Arc<AtomicBool>,
+    sender: Sender<Packet>,
+    receiver: Receiver<Packet>,
+    read_buffer: Vec<u8>,
+    last_deliver_at: Instant,
+}
+
+impl MemoryConnection {
+    fn new(
+        network: &MemoryNetwork,
+        local: SocketAddr,
+        peer: SocketAddr,
+        closed: Arc<AtomicBool>,
+        sender: Sender<Packet>,
+        receiver: Receiver<Packet>,
+    ) -> Self {
+        Self {
+            network: network.clone(),
+            local,
+            peer,
+            closed,
+            sender,
+            receiver,
+            read_buffer: vec![],
+            last_deliver_at: network.clock.now(),
+        }
+    }
+
+    fn reset(&self) -> io::Error {
+        self.closed.store(true, Ordering::SeqCst);
+        io::Error::from(ErrorKind::ConnectionReset)
+    }
+
+    fn is_partitioned(&self) -> bool {
+        self.network
+            .is_partitioned(&self.local.ip(), &self.peer.ip())
+    }
+}
+
+impl Read for MemoryConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_buffer.is_empty() {
+            if self.closed.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            if self.is_partitioned() {
+                return Err(self.reset());
+            }
+
+            match self.receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(packet) => {
+                    while !self
+                        .network
+                        .clock
+                        .wait_until(packet.deliver_at, POLL_INTERVAL)
+                    {
+                        if self.closed.load(Ordering::SeqCst) {
+                            return Ok(0);
+                        }
+                    }
+                    self.read_buffer = packet.data;
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.read_buffer.len());
+        buf[..len].copy_from_slice(&self.read_buffer[..len]);
+        self.read_buffer.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for MemoryConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(io::Error::from(ErrorKind::BrokenPipe));
+        }
+
+        let delay = {
+            let mut state = self.network.state.lock().unwrap();
+            if state.is_partitioned(&self.local.ip(), &self.peer.ip()) {
+                drop(state);
+                return Err(self.reset());
+            }
+            let loss = state.link.loss;
+            if loss > 0. && state.rng.gen_bool(loss.min(1.)) {
+                drop(state);
+                return Err(self.reset());
+            }
+            let link = state.link.clone();
+            link.sample_delay(&mut state.rng)
+        };
+
+        // Keep the stream in order even if the delay of this write is shorter.
+        let deliver_at = (self.network.clock.now() + delay).max(self.last_deliver_at);
+        self.last_deliver_at = deliver_at;
+        self.sender
+            .send(Packet {
+                deliver_at,
+                data: buf.to_vec(),
+            })
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for MemoryConnection {
+    fn try_clone(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(MemoryConnection {
+            network: self.network.clone(),
+            local: self.local,
+            peer: self.peer,
+            closed: self.closed.clone(),
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            read_buffer: vec![],
+            last_deliver_at: self.last_deliver_at,
+        }))
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.closed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::clock::VirtualClock;
+
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    struct Pair {
+        _listener: Box<dyn Listener>,
+        client: Box<dyn Connection>,
+        server: Box<dyn Connection>,
+    }
+
+    fn connect(network: &MemoryNetwork) -> Pair {
+        let listener = network.transport(ip(1)).listen("10.0.0.1:9090").unwrap();
+        let client = network.transport(ip(2)).dial("10.0.0.1:9090").unwrap();
+        let server = listener.accept().unwrap();
+        Pair {
+            _listener: listener,
+            client,
+            server,
+        }
+    }
+
+    #[test]
+    fn test_latency() {
+        let network = MemoryNetwork::new(
+            LinkConfig {
+                latency: Duration::from_millis(100),
+                ..LinkConfig::default()
+            },
+            0,
+        );
+        let Pair {
+            mut client,
+            mut server,
+            ..
+        } = connect(&network);
+        assert_eq!(server.peer_addr().unwrap().ip(), ip(2));
+
+        let start = Instant::now();
+        client.write_all(b"hello").unwrap();
+        client.write_all(b" world").unwrap();
+        let mut buf = [0u8; 11];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello world");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        client.shutdown().unwrap();
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_virtual_latency() {
+        let clock = VirtualClock::new();
+        let network = MemoryNetwork::with_clock(
+            LinkConfig {
+                latency: Duration::from_secs(3600),
+                ..LinkConfig::default()
+            },
+            0,
+            Clock::Virtual(clock.clone()),
+        );
+        let Pair {
+            mut client,
+            mut server,
+            ..
+        } = connect(&network);
+
+        let start = Instant::now();
+        client.write_all(b"hello").unwrap();
+        clock.advance(Duration::from_secs(3600));
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_partition() {
+        let network = MemoryNetwork::new(LinkConfig::default(), 0);
+        let mut pair = connect(&network);
+
+        network.partition(&[&[ip(1)], &[ip(2)]]);
+        assert!(pair.client.write_all(b"ping").is_err());
+        assert!(network.transport(ip(2)).dial("10.0.0.1:9090").is_err());
+
+        network.heal();
+        assert!(network.transport(ip(2)).dial("10.0.0.1:9090").is_ok());
+    }
+
+    #[test]
+    fn test_loss() {
+        let network = MemoryNetwork::new(
+            LinkConfig {
+                loss: 1.,
+                ..LinkConfig::default()
+            },
+            0,
+        );
+        let Pair {
+            mut client,
+            mut server,
+            ..
+        } = connect(&network);
+        assert_eq!(
+            client.write_all(b"ping").unwrap_err().kind(),
+            ErrorKind::ConnectionReset
+        );
+        assert_eq!(server.read(&mut [0u8; 4]).unwrap(), 0);
+    }
+}
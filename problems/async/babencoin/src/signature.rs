@@ -0,0 +1,237 @@
+use crate::util::parse_pkcs8_public;
+
+use anyhow::{bail, ensure, Context, Result};
+use ed25519_dalek::{Keypair, Signer, Verifier, PUBLIC_KEY_LENGTH};
+use rsa::{
+    padding::PaddingScheme, PublicKey, PublicKeyEncoding, PublicKeyParts, RSAPrivateKey,
+    RSAPublicKey,
+};
+use sha3::{Digest, Sha3_512};
+
+use std::{
+    convert::TryFrom,
+    hash::{Hash, Hasher},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// PKCS8 is a DER sequence, so the RSA keys need no tag of their own.
+const RSA_TAG: u8 = 0x30;
+const ED25519_TAG: u8 = 0x01;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// PKCS#1 v1.5 signatures of the raw hash.
+    Rsa,
+    Ed25519,
+}
+
+/// Public key of a wallet. Wallets of different schemes can send coins to each other.
+///
+/// The encoding is tagged by its first byte: an RSA key is encoded as PKCS8, which
+/// always starts with `0x30`, and an Ed25519 key is `0x01` followed by its 32 bytes.
+#[derive(Clone, Debug, Eq)]
+pub enum WalletId {
+    Rsa(RSAPublicKey),
+    Ed25519(ed25519_dalek::PublicKey),
+}
+
+impl WalletId {
+    /// The issuer of the mainnet genesis block.
+    pub fn of_genesis() -> Self {
+        parse_pkcs8_public(include_str!("../data/genesis.crt"))
+            .unwrap()
+            .into()
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Rsa(_) => SignatureScheme::Rsa,
+            Self::Ed25519(_) => SignatureScheme::Ed25519,
+        }
+    }
+
+    pub fn verify(&self, hash: &[u8], signature: &[u8]) -> Result<()> {
+        match self {
+            Self::Rsa(key) => key
+                .verify(PaddingScheme::PKCS1v15Sign { hash: None }, hash, signature)
+                .context("invalid RSA signature"),
+            Self::Ed25519(key) => {
+                let signature = ed25519_dalek::Signature::try_from(signature)
+                    .context("malformed Ed25519 signature")?;
+                key.verify(hash, &signature)
+                    .context("invalid Ed25519 signature")
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Rsa(key) => key.to_pkcs8().context("failed to encode key as PKCS8"),
+            Self::Ed25519(key) => {
+                let mut bytes = vec![ED25519_TAG];
+                bytes.extend_from_slice(key.as_bytes());
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.first() {
+            Some(&RSA_TAG) => RSAPublicKey::from_pkcs8(bytes)
+                .map(Self::Rsa)
+                .context("invalid PKCS8"),
+            Some(&ED25519_TAG) => {
+                ensure!(
+                    bytes.len() == 1 + PUBLIC_KEY_LENGTH,
+                    "Ed25519 key must be {} bytes long",
+                    PUBLIC_KEY_LENGTH
+                );
+                ed25519_dalek::PublicKey::from_bytes(&bytes[1..])
+                    .map(Self::Ed25519)
+                    .context("invalid Ed25519 key")
+            }
+            Some(tag) => bail!("unknown wallet tag {:#04x}", tag),
+            None => bail!("empty wallet id"),
+        }
+    }
+
+    /// Feeds the key to the hasher of a block or a transaction. RSA keys are hashed
+    /// as their little-endian modulus and exponent, as they always were.
+    pub fn hash_into(&self, hasher: &mut Sha3_512) {
+        match self {
+            Self::Rsa(key) => {
+                hasher.update(key.n().to_bytes_le());
+                hasher.update(key.e().to_bytes_le());
+            }
+            Self::Ed25519(key) => {
+                hasher.update([ED25519_TAG]);
+                hasher.update(key.as_bytes());
+            }
+        }
+    }
+}
+
+impl Hash for WalletId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Rsa(key) => {
+                key.n().hash(state);
+                key.e().hash(state);
+            }
+            Self::Ed25519(key) => key.as_bytes().hash(state),
+        }
+    }
+}
+
+impl PartialEq for WalletId {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Rsa(first), Self::Rsa(second)) => {
+                first.n() == second.n() && first.e() == second.e()
+            }
+            (Self::Ed25519(first), Self::Ed25519(second)) => first == second,
+            _ => false,
+        }
+    }
+}
+
+impl From<RSAPublicKey> for WalletId {
+    fn from(key: RSAPublicKey) -> Self {
+        Self::Rsa(key)
+    }
+}
+
+impl From<ed25519_dalek::PublicKey> for WalletId {
+    fn from(key: ed25519_dalek::PublicKey) -> Self {
+        Self::Ed25519(key)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Private key of a wallet that signs its transactions.
+pub trait SigningKey {
+    fn wallet_id(&self) -> WalletId;
+    fn sign(&self, hash: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl SigningKey for RSAPrivateKey {
+    fn wallet_id(&self) -> WalletId {
+        self.to_public_key().into()
+    }
+
+    fn sign(&self, hash: &[u8]) -> Result<Vec<u8>> {
+        RSAPrivateKey::sign(self, PaddingScheme::PKCS1v15Sign { hash: None }, hash)
+            .context("failed to sign with RSA")
+    }
+}
+
+impl SigningKey for Keypair {
+    fn wallet_id(&self) -> WalletId {
+        self.public.into()
+    }
+
+    fn sign(&self, hash: &[u8]) -> Result<Vec<u8>> {
+        Ok(Signer::sign(self, hash).to_bytes().to_vec())
+    }
+}
+
+pub fn generate_ed25519_key() -> Keypair {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&rand::random::<[u8; 32]>())
+        .expect("any 32 bytes are a valid secret key");
+    Keypair {
+        public: (&secret).into(),
+        secret,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::parse_pkcs8_private;
+
+    fn rsa_key() -> RSAPrivateKey {
+        parse_pkcs8_private(include_str!("../data/test.pem")).unwrap()
+    }
+
+    #[test]
+    fn test_sign() {
+        let hash = [7u8; 64];
+        let keys: [Box<dyn SigningKey>; 2] =
+            [Box::new(rsa_key()), Box::new(generate_ed25519_key())];
+        for key in keys.iter() {
+            let signature = key.sign(&hash).unwrap();
+            key.wallet_id().verify(&hash, &signature).unwrap();
+            assert!(key.wallet_id().verify(&[8u8; 64], &signature).is_err());
+        }
+
+        let rsa_signature = keys[0].sign(&hash).unwrap();
+        assert!(keys[1].wallet_id().verify(&hash, &rsa_signature).is_err());
+        assert!(keys[1].wallet_id().verify(&hash, &[]).is_err());
+    }
+
+    #[test]
+    fn test_encoding() {
+        let rsa = rsa_key().wallet_id();
+        let ed25519 = generate_ed25519_key().wallet_id();
+        assert_eq!(rsa.scheme(), SignatureScheme::Rsa);
+        assert_eq!(ed25519.scheme(), SignatureScheme::Ed25519);
+
+        for wallet in [&rsa, &ed25519] {
+            let bytes = wallet.to_bytes().unwrap();
+            assert_eq!(WalletId::from_bytes(&bytes).unwrap(), *wallet);
+        }
+        assert_eq!(ed25519.to_bytes().unwrap().len(), 33);
+        assert_eq!(rsa.to_bytes().unwrap()[0], RSA_TAG);
+        assert_ne!(rsa, ed25519);
+
+        assert!(WalletId::from_bytes(&[]).is_err());
+        assert!(WalletId::from_bytes(&[ED25519_TAG; 5]).is_err());
+        assert!(WalletId::from_bytes(&[2u8; 33]).is_err());
+    }
+}
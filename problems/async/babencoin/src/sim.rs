@@ -0,0 +1,543 @@
+use crate::{
+    block_forest::BlockForest,
+    chain_params::ChainParams,
+    data::{Block, BlockAttributes, BlockHash, VerifiedBlock, BLOCK_VERSION, HASH_LEN},
+    mempool::MempoolConfig,
+    merkle::EMPTY_MERKLE_ROOT,
+    metrics::Registry,
+    node::{
+        self,
+        clock::{Clock, VirtualClock},
+        transport::{LinkConfig, MemoryNetwork},
+        Config, NodeHandle, ShutdownReason,
+    },
+};
+
+use anyhow::{Context, Result};
+use chrono::{TimeZone, Utc};
+use log::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Real time given to the node services to react to every step of virtual time.
+const STEP_PAUSE: Duration = Duration::from_millis(1);
+const NODE_PORT: u16 = 9000;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub node_count: usize,
+    /// Must accept any block hash, i.e. have no retargeting and the largest
+    /// `genesis_max_hash`, like regtest: the simulation doesn't search for nonces.
+    pub chain: ChainParams,
+    /// Link between every pair of nodes.
+    pub link: LinkConfig,
+    /// Mean time between blocks in the whole network. Blocks are found at
+    /// exponentially distributed moments.
+    pub block_interval: Duration,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            node_count: 3,
+            chain: ChainParams::regtest(),
+            link: LinkConfig {
+                latency: Duration::from_millis(100),
+                jitter: Duration::from_millis(50),
+                loss: 0.,
+            },
+            block_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub blocks_mined: u64,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    pub reorgs: u64,
+    /// The largest number of blocks removed from a head chain by a reorg.
+    pub max_reorg_depth: u64,
+}
+
+#[derive(Clone)]
+enum Message {
+    Block(Arc<VerifiedBlock>),
+    Request(BlockHash),
+}
+
+enum Event {
+    Deliver {
+        from: usize,
+        to: usize,
+        message: Message,
+    },
+    Mine {
+        node: usize,
+        generation: u64,
+    },
+}
+
+struct Scheduled {
+    at: Duration,
+    seq: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct SimNode {
+    forest: BlockForest,
+    mining: bool,
+    mining_generation: u64,
+    group: Option<usize>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Deterministic discrete-event model of block gossip in a network of nodes.
+///
+/// The node services are not run, see `NodeSimulation` for that: a simulated node
+/// is just a `BlockForest` that mines on its head, relays the new blocks to the
+/// other nodes and requests the unknown parent of a block from its sender,
+/// following the rules of the gossip service. The links behave like `LinkConfig` describes, all the time is virtual
+/// and all the randomness comes from the seed, so runs with the same config
+/// produce the same chains.
+pub struct Simulation {
+    config: SimConfig,
+    rng: StdRng,
+    now: Duration,
+    next_seq: u64,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    nodes: Vec<SimNode>,
+    stats: SimStats,
+}
+
+impl Simulation {
+    /// Panics if the chain of the config doesn't accept every block hash.
+    pub fn new(config: SimConfig) -> Self {
+        assert!(
+            !config.chain.retarget && config.chain.genesis_max_hash == [255u8; HASH_LEN],
+            "simulated chain must accept any block hash"
+        );
+        let nodes = (0..config.node_count)
+            .map(|_| SimNode {
+                forest: BlockForest::with_params(config.chain.clone(), MempoolConfig::default()),
+                mining: true,
+                mining_generation: 0,
+                group: None,
+            })
+            .collect();
+
+        let mut sim = Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            now: Duration::ZERO,
+            next_seq: 0,
+            queue: BinaryHeap::new(),
+            nodes,
+            stats: SimStats::default(),
+        };
+        for node in 0..sim.nodes.len() {
+            sim.schedule_mining(node);
+        }
+        sim
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn stats(&self) -> &SimStats {
+        &self.stats
+    }
+
+    pub fn forest(&self, node: usize) -> &BlockForest {
+        &self.nodes[node].forest
+    }
+
+    pub fn heads(&self) -> Vec<BlockHash> {
+        self.nodes
+            .iter()
+            .map(|node| *node.forest.head().hash())
+            .collect()
+    }
+
+    pub fn is_converged(&self) -> bool {
+        let heads = self.heads();
+        heads.iter().all(|head| *head == heads[0])
+    }
+
+    pub fn set_mining(&mut self, node: usize, enabled: bool) {
+        if self.nodes[node].mining == enabled {
+            return;
+        }
+        self.nodes[node].mining = enabled;
+        self.nodes[node].mining_generation += 1;
+        if enabled {
+            self.schedule_mining(node);
+        }
+    }
+
+    /// Nodes from different groups can't reach each other, messages in flight
+    /// between them are lost. Nodes not listed in any group are isolated.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            node.group = Some(
+                groups
+                    .iter()
+                    .position(|group| group.contains(&i))
+                    .unwrap_or(groups.len() + i),
+            );
+        }
+    }
+
+    pub fn heal(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.group = None;
+        }
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        self.run_until(duration, |_| false);
+    }
+
+    /// Processes events until `pred` holds or `limit` of virtual time passes.
+    /// Returns whether `pred` holds.
+    pub fn run_until<F: FnMut(&Simulation) -> bool>(
+        &mut self,
+        limit: Duration,
+        mut pred: F,
+    ) -> bool {
+        let deadline = self.now + limit;
+        loop {
+            if pred(self) {
+                return true;
+            }
+            match self.queue.peek() {
+                Some(Reverse(scheduled)) if scheduled.at <= deadline => {}
+                _ => break,
+            }
+            let Reverse(scheduled) = self.queue.pop().unwrap();
+            self.now = scheduled.at;
+            self.handle_event(scheduled.event);
+        }
+        self.now = deadline;
+        pred(self)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+
+    fn schedule(&mut self, delay: Duration, event: Event) {
+        let scheduled = Scheduled {
+            at: self.now + delay,
+            seq: self.next_seq,
+            event,
+        };
+        self.next_seq += 1;
+        self.queue.push(Reverse(scheduled));
+    }
+
+    fn schedule_mining(&mut self, node: usize) {
+        // Every node finds blocks at the same rate, so the network as a whole
+        // finds them once per block_interval on average.
+        let mean = self.config.block_interval.as_secs_f64() * self.nodes.len() as f64;
+        let delay = -(1. - self.rng.gen::<f64>()).ln() * mean;
+        let generation = self.nodes[node].mining_generation;
+        self.schedule(
+            Duration::from_secs_f64(delay),
+            Event::Mine { node, generation },
+        );
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Mine { node, generation } => {
+                if !self.nodes[node].mining || self.nodes[node].mining_generation != generation {
+                    return;
+                }
+                let block = Arc::new(self.mine_block(node));
+                self.stats.blocks_mined += 1;
+                self.add_block(node, block.clone());
+                self.broadcast(node, None, Message::Block(block));
+                self.schedule_mining(node);
+            }
+            Event::Deliver { from, to, message } => {
+                if self.is_partitioned(from, to) {
+                    self.stats.messages_dropped += 1;
+                    return;
+                }
+                self.handle_message(from, to, message);
+            }
+        }
+    }
+
+    fn handle_message(&mut self, from: usize, to: usize, message: Message) {
+        match message {
+            Message::Block(block) => {
+                if self.nodes[to].forest.find_block(block.hash()).is_some() {
+                    return;
+                }
+                if !self.add_block(to, block.clone()) {
+                    return;
+                }
+                if let Some(hash) = self.find_missing_ancestor(to, &block) {
+                    self.send(to, from, Message::Request(hash));
+                }
+                self.broadcast(to, Some(from), Message::Block(block));
+            }
+            Message::Request(hash) => {
+                if let Some(block) = self.nodes[to].forest.find_block(&hash) {
+                    let block = block.clone();
+                    self.send(to, from, Message::Block(block));
+                }
+            }
+        }
+    }
+
+    fn mine_block(&mut self, node: usize) -> VerifiedBlock {
+        let forest = &self.nodes[node].forest;
+        let head = forest.head();
+        let timestamp = (self.config.chain.genesis_timestamp.timestamp()
+            + self.now.as_secs() as i64)
+            .max(head.timestamp.timestamp() + 1);
+
+        let block = Block {
+            attrs: BlockAttributes {
+                version: BLOCK_VERSION,
+                index: head.index + 1,
+                reward: 0,
+                nonce: self.rng.gen(),
+                timestamp: Utc.timestamp_opt(timestamp, 0).unwrap(),
+                issuer: self.config.chain.genesis_issuer.clone(),
+                max_hash: forest.next_max_hash(),
+                prev_hash: *head.hash(),
+                merkle_root: EMPTY_MERKLE_ROOT,
+            },
+            transactions: vec![],
+        };
+        block
+            .verified(&self.config.chain)
+            .expect("mined block must be valid")
+    }
+
+    /// Returns false if the block was rejected.
+    fn add_block(&mut self, node: usize, block: Arc<VerifiedBlock>) -> bool {
+        match self.nodes[node].forest.add_block((*block).clone()) {
+            Ok(Some(change)) if change.is_reorg() => {
+                let depth = change.disconnected.len() as u64;
+                debug!("node {} reorganized {} blocks", node, depth);
+                self.stats.reorgs += 1;
+                self.stats.max_reorg_depth = self.stats.max_reorg_depth.max(depth);
+                true
+            }
+            Ok(_) => true,
+            Err(err) => {
+                warn!("node {} rejected block: {:#}", node, err);
+                false
+            }
+        }
+    }
+
+    /// Returns the first ancestor of the block that the node doesn't know.
+    fn find_missing_ancestor(&self, node: usize, block: &VerifiedBlock) -> Option<BlockHash> {
+        let forest = &self.nodes[node].forest;
+        let mut hash = block.prev_hash;
+        while let Some(ancestor) = forest.find_block(&hash) {
+            if ancestor.index == 0 {
+                return None;
+            }
+            hash = ancestor.prev_hash;
+        }
+        Some(hash)
+    }
+
+    fn broadcast(&mut self, from: usize, except: Option<usize>, message: Message) {
+        for to in 0..self.nodes.len() {
+            if to != from && Some(to) != except {
+                self.send(from, to, message.clone());
+            }
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: Message) {
+        self.stats.messages_sent += 1;
+        if self.is_partitioned(from, to) || self.rng.gen_bool(self.config.link.loss.min(1.)) {
+            self.stats.messages_dropped += 1;
+            return;
+        }
+        let delay = self.config.link.sample_delay(&mut self.rng);
+        self.schedule(delay, Event::Deliver { from, to, message });
+    }
+
+    fn is_partitioned(&self, first: usize, second: usize) -> bool {
+        self.nodes[first].group != self.nodes[second].group
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Runs the real services of several nodes in one process. The nodes talk over a
+/// `MemoryNetwork`, and both the network and the services measure time with a
+/// virtual clock that moves only in `run_for()` and `run_until()`, so minutes of
+/// latency and timeouts take milliseconds of real time.
+///
+/// Unlike `Simulation`, the runs are not reproducible exactly: the services work
+/// in their own threads, which get `STEP_PAUSE` of real time after every `step`
+/// of virtual time.
+pub struct NodeSimulation {
+    clock: Arc<VirtualClock>,
+    network: MemoryNetwork,
+    step: Duration,
+    started_at: Instant,
+    registries: Vec<Arc<Registry>>,
+    nodes: Vec<NodeHandle>,
+}
+
+impl NodeSimulation {
+    /// Starts a node for every config. Node `i` listens on `address(i)`, so the
+    /// configs may list each other in `dial_addresses`.
+    pub fn start(
+        configs: Vec<Config>,
+        link: LinkConfig,
+        seed: u64,
+        step: Duration,
+    ) -> Result<Self> {
+        let clock = VirtualClock::new();
+        let mut sim = Self {
+            network: MemoryNetwork::with_clock(link, seed, Clock::Virtual(clock.clone())),
+            started_at: clock.now(),
+            clock,
+            step,
+            registries: vec![],
+            nodes: vec![],
+        };
+        for (i, mut config) in configs.into_iter().enumerate() {
+            config.peer_service.listen_address = Some(Self::address(i));
+            let registry = Arc::new(Registry::new());
+            let node = node::start_with(
+                config,
+                Arc::new(sim.network.transport(Self::ip(i))),
+                registry.clone(),
+                Clock::Virtual(sim.clock.clone()),
+            )
+            .with_context(|| format!("failed to start node {}", i))?;
+            sim.registries.push(registry);
+            sim.nodes.push(node);
+        }
+        Ok(sim)
+    }
+
+    pub fn ip(node: usize) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(
+            u32::from(Ipv4Addr::new(10, 0, 0, 1)) + node as u32,
+        ))
+    }
+
+    pub fn address(node: usize) -> String {
+        format!("{}:{}", Self::ip(node), NODE_PORT)
+    }
+
+    /// Use it to partition the nodes or to connect to them from the test.
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    pub fn registry(&self, node: usize) -> &Registry {
+        &self.registries[node]
+    }
+
+    /// Virtual time since the start.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now() - self.started_at
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        self.run_until(duration, |_| false);
+    }
+
+    /// Advances the virtual time until `pred` holds or `limit` passes. Returns
+    /// whether `pred` holds.
+    pub fn run_until<F: FnMut(&NodeSimulation) -> bool>(
+        &mut self,
+        limit: Duration,
+        mut pred: F,
+    ) -> bool {
+        let deadline = self.clock.now() + limit;
+        while !pred(self) {
+            let now = self.clock.now();
+            if now >= deadline {
+                return false;
+            }
+            self.clock.advance(self.step.min(deadline - now));
+            thread::sleep(STEP_PAUSE);
+        }
+        true
+    }
+
+    /// Stops the nodes, returning the reason each of them stopped for.
+    pub fn shutdown(mut self) -> Vec<ShutdownReason> {
+        self.stop_nodes()
+    }
+
+    /// The time keeps going while the nodes stop, so that no service sleeps forever.
+    fn stop_nodes(&mut self) -> Vec<ShutdownReason> {
+        let clock = &self.clock;
+        let step = self.step;
+        let nodes = &mut self.nodes;
+        let stopped = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                while !stopped.load(atomic::Ordering::SeqCst) {
+                    clock.advance(step);
+                    thread::sleep(STEP_PAUSE);
+                }
+            });
+            let reasons = nodes.drain(..).map(NodeHandle::shutdown).collect();
+            stopped.store(true, atomic::Ordering::SeqCst);
+            reasons
+        })
+    }
+}
+
+impl Drop for NodeSimulation {
+    fn drop(&mut self) {
+        self.stop_nodes();
+    }
+}
@@ -2,7 +2,7 @@ use crate::data::WalletId;
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, LocalResult, TimeZone, Utc};
-use rsa::{PublicKeyEncoding, RSAPrivateKey, RSAPublicKey};
+use rsa::{RSAPrivateKey, RSAPublicKey};
 use serde::{
     de::{self, Deserializer},
     ser::{self, Serializer},
@@ -72,6 +72,40 @@ where
     Ok(array)
 }
 
+pub fn serialize_base64_vec<T, S>(arrays: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[u8]>,
+    S: Serializer,
+{
+    serializer.collect_seq(arrays.iter().map(|array| base64::encode(array.as_ref())))
+}
+
+pub fn deserialize_base64_fixed_vec<'de, D, const SIZE: usize>(
+    deserializer: D,
+) -> Result<Vec<[u8; SIZE]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let strings = Vec::<String>::deserialize(deserializer)?;
+    let mut arrays = Vec::with_capacity(strings.len());
+    for string in strings {
+        let bytes = base64::decode(&string)
+            .map_err(|err| de::Error::custom(format!("invalid base64: {}", err)))?;
+        if bytes.len() != SIZE {
+            return Err(de::Error::custom(format!(
+                "invalid length: expected {}, got {}",
+                SIZE,
+                bytes.len()
+            )));
+        }
+
+        let mut array = [0u8; SIZE];
+        array.copy_from_slice(&bytes);
+        arrays.push(array);
+    }
+    Ok(arrays)
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub fn serialize_wallet_id<S>(wallet: &WalletId, serializer: S) -> Result<S::Ok, S::Error>
@@ -79,9 +113,8 @@ where
     S: Serializer,
 {
     let bytes = wallet
-        .public_key
-        .to_pkcs8()
-        .map_err(|err| ser::Error::custom(format!("failed to encode key as PKCS8: {}", err)))?;
+        .to_bytes()
+        .map_err(|err| ser::Error::custom(format!("{:#}", err)))?;
     serialize_base64(&bytes, serializer)
 }
 
@@ -90,9 +123,7 @@ where
     D: Deserializer<'de>,
 {
     let bytes = deserialize_base64(deserializer)?;
-    RSAPublicKey::from_pkcs8(&bytes)
-        .map(|public_key| WalletId { public_key })
-        .map_err(|err| de::Error::custom(format!("invalid PKCS8: {}", err)))
+    WalletId::from_bytes(&bytes).map_err(|err| de::Error::custom(format!("{:#}", err)))
 }
 
 ////////////////////////////////////////////////////////////////////////////////
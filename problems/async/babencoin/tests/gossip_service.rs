@@ -9,15 +9,23 @@ use helpers::{
 };
 
 use babencoin::{
-    data::{Block, PeerMessage, Transaction, VerifiedBlock, VerifiedTransaction},
+    chain_params::ChainParams,
+    data::{
+        Block, PeerMessage, Transaction, VerifiedBlock, VerifiedPeerMessage, VerifiedTransaction,
+    },
     node,
 };
 
+use std::time::Instant;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[test]
 fn block_request() {
-    let env = test_env!("test_block_request");
+    // Unknown parents are requested one by one only without fast sync.
+    let mut config = node::Config::default();
+    config.gossip_service.sync_max_in_flight_per_session = 0;
+    let env = test_env!("test_block_request", config);
     let mut conn = env.connect_to_node().unwrap();
 
     wait_for_message(&mut conn, 10, |msg| match msg {
@@ -59,6 +67,60 @@ fn block_request() {
     .unwrap();
 }
 
+#[test]
+fn proof_request() {
+    let env = test_env!("test_proof_request");
+    let mut conn = env.connect_to_node().unwrap();
+
+    let txs: Vec<_> = (0..3)
+        .map(|i| {
+            VerifiedTransaction::sign(
+                &generate_private_key(),
+                generate_public_key().into(),
+                0,
+                0,
+                format!("tx #{}", i),
+            )
+            .unwrap()
+        })
+        .collect();
+
+    let mut block = random_block(1);
+    block.attrs.prev_hash = Block::genesis().compute_hash();
+    block.transactions = txs.iter().map(|tx| tx.clone().into()).collect();
+    block.attrs.merkle_root = block.compute_merkle_root();
+    send_message(&mut conn, PeerMessage::Block(Box::new(block.clone()))).unwrap();
+    sync(&mut conn).unwrap();
+
+    let block_hash = block.compute_hash();
+    let tx_hash = *txs[1].hash();
+    send_message(
+        &mut conn,
+        PeerMessage::GetProof {
+            block_hash,
+            transaction_hash: tx_hash,
+        },
+    )
+    .unwrap();
+
+    let msg = wait_for_message(&mut conn, 10, |msg| {
+        matches!(msg, PeerMessage::Proof { .. })
+    })
+    .unwrap();
+    match msg.verified(&ChainParams::mainnet()).unwrap() {
+        VerifiedPeerMessage::Proof {
+            header,
+            transaction_hash,
+            proof,
+        } => {
+            assert_eq!(*header.hash(), block_hash);
+            assert_eq!(transaction_hash, tx_hash);
+            assert!(header.verify_inclusion(&tx_hash, &proof));
+        }
+        _ => unreachable!(),
+    }
+}
+
 #[test]
 fn tx_send() {
     let env = test_env!("test_tx_send");
@@ -165,10 +227,12 @@ fn head_switch() {
     let mut block_one = random_block(1);
     block_one.attrs.prev_hash = Block::genesis().compute_hash();
     block_one.transactions.push(tx_one.clone().into());
+    block_one.attrs.merkle_root = block_one.compute_merkle_root();
 
     let mut block_two = random_block(1);
     block_two.attrs.prev_hash = Block::genesis().compute_hash();
     block_two.transactions.push(tx_two.clone().into());
+    block_two.attrs.merkle_root = block_two.compute_merkle_root();
 
     let mut conn_one = env.connect_to_node().unwrap();
     send_message(
@@ -231,7 +295,10 @@ fn head_switch() {
 
 #[test]
 fn no_bad_block_memoization() {
-    let env = test_env!("test_no_bad_block_memoization");
+    // Unknown parents are requested one by one only without fast sync.
+    let mut config = node::Config::default();
+    config.gossip_service.sync_max_in_flight_per_session = 0;
+    let env = test_env!("test_no_bad_block_memoization", config);
     let mut conn = env.connect_to_node().unwrap();
 
     let ok_block = random_block(25);
@@ -313,3 +380,51 @@ fn eager_requests() {
     })
     .unwrap();
 }
+
+#[test]
+fn fast_sync() {
+    const CHAIN_LEN: u64 = 1000;
+    const TIME_BUDGET: time::Duration = time::Duration::from_secs(30);
+
+    let target_block_time = ChainParams::mainnet().target_block_time;
+    let mut blocks = vec![Block::genesis()];
+    for i in 1..=CHAIN_LEN {
+        let prev_block = blocks.last().unwrap();
+        let mut block = Block::genesis();
+        block.attrs.index = i;
+        block.attrs.prev_hash = prev_block.compute_hash();
+        block.attrs.timestamp = prev_block
+            .timestamp
+            .checked_add_signed(chrono::Duration::from_std(target_block_time).unwrap())
+            .unwrap();
+        blocks.push(block);
+    }
+
+    let mut config = node::Config::default();
+    config.gossip_service.max_requests_per_second = 0;
+    config.gossip_service.sync_max_in_flight_per_session = 32;
+    config.gossip_service.sync_request_timeout = time::Duration::from_secs(1);
+
+    let env_one = test_env!("test_fast_sync_one", config);
+    let mut conn_one = env_one.connect_to_node().unwrap();
+    for block in blocks.iter().skip(1) {
+        send_message(&mut conn_one, PeerMessage::Block(Box::new(block.clone()))).unwrap();
+    }
+    sync(&mut conn_one).unwrap();
+
+    let mut config = node::Config::default();
+    config.gossip_service.max_requests_per_second = 0;
+    config.gossip_service.sync_max_in_flight_per_session = 32;
+    config.gossip_service.sync_request_timeout = time::Duration::from_secs(1);
+    config.peer_service.dial_addresses = vec![env_one.addr().to_string()];
+
+    let start_ts = Instant::now();
+    let env_two = test_env!("test_fast_sync_two", config);
+    let mut conn_two = env_two.connect_to_node().unwrap();
+    wait_for_message(&mut conn_two, TIME_BUDGET.as_secs(), |msg| match msg {
+        PeerMessage::Block(block) => **block == *blocks.last().unwrap(),
+        _ => false,
+    })
+    .unwrap();
+    assert!(start_ts.elapsed() < TIME_BUDGET);
+}
@@ -8,8 +8,10 @@ use helpers::{
 };
 
 use babencoin::{
-    block_forest::{BlockForest, EPOCH_SIZE, TARGET_BLOCK_MINING_TIME_SECONDS},
-    data::{Block, BlockAttributes, PeerMessage, VerifiedTransaction, HASH_LEN},
+    block_forest::BlockForest,
+    chain_params::ChainParams,
+    data::{Block, BlockAttributes, PeerMessage, VerifiedTransaction, BLOCK_VERSION, HASH_LEN},
+    merkle::EMPTY_MERKLE_ROOT,
     node,
 };
 
@@ -35,7 +37,7 @@ fn genesis_mining() {
     .unwrap();
 
     let mut last_block = match msg {
-        PeerMessage::Block(block) => block.verified().unwrap(),
+        PeerMessage::Block(block) => block.verified(&ChainParams::mainnet()).unwrap(),
         _ => unreachable!(),
     };
     block_forest.add_block(last_block.clone()).unwrap();
@@ -51,7 +53,7 @@ fn genesis_mining() {
 
         wait_for_message(&mut conn, 10, |msg| match msg {
             PeerMessage::Block(block) => {
-                let verified = block.clone().verified().unwrap();
+                let verified = block.clone().verified(&ChainParams::mainnet()).unwrap();
                 if verified.hash() == &last_block.prev_hash {
                     block_forest.add_block(verified.clone()).unwrap();
                     last_block = verified;
@@ -86,17 +88,20 @@ fn no_repeated_parents() {
 
 #[test]
 fn test_mining_difficulty() {
+    let params = ChainParams::mainnet();
+    let target_seconds = params.target_block_time.as_secs();
     let mut blocks = vec![Block::genesis()];
-    for i in 1..3 * EPOCH_SIZE {
+    for i in 1..3 * params.epoch_size {
         let prev_block = blocks.last().unwrap().clone();
-        let time_delta_seconds = if i < 2 * EPOCH_SIZE {
-            TARGET_BLOCK_MINING_TIME_SECONDS
+        let time_delta_seconds = if i < 2 * params.epoch_size {
+            target_seconds
         } else {
-            TARGET_BLOCK_MINING_TIME_SECONDS / 2
+            target_seconds / 2
         };
 
         blocks.push(Block {
             attrs: BlockAttributes {
+                version: BLOCK_VERSION,
                 index: i as u64,
                 reward: 0,
                 nonce: 0,
@@ -107,6 +112,7 @@ fn test_mining_difficulty() {
                 issuer: generate_public_key().into(),
                 max_hash: [255; HASH_LEN],
                 prev_hash: prev_block.compute_hash(),
+                merkle_root: EMPTY_MERKLE_ROOT,
             },
             transactions: vec![],
         });
@@ -132,7 +138,7 @@ fn test_mining_difficulty() {
             if block.prev_hash == expected_prev_hash {
                 assert_eq!(block.index, blocks.len() as u64);
                 assert_eq!(block.max_hash, expected_max_hash);
-                block.clone().verified().unwrap();
+                block.clone().verified(&ChainParams::mainnet()).unwrap();
                 true
             } else {
                 false
@@ -177,7 +183,7 @@ fn max_tx_per_block() {
     let mut received_transactions = vec![];
     wait_for_message(&mut conn, 15, |msg| match msg {
         PeerMessage::Block(block) => {
-            let verified = block.clone().verified().unwrap();
+            let verified = block.clone().verified(&ChainParams::mainnet()).unwrap();
             assert!(verified.transactions().len() <= 2);
             received_transactions.extend(verified.transactions().iter().cloned());
             received_transactions.len() >= transactions.len()
@@ -194,3 +200,28 @@ fn max_tx_per_block() {
         assert_eq!(expected_tx, got_tx);
     }
 }
+
+#[test]
+fn regtest_mining() {
+    let params = ChainParams::regtest();
+    let mut config = node::Config::default();
+    config.chain = params.clone();
+    config.mining_service.thread_count = 1;
+    config.mining_service.public_key = generate_public_key().into();
+
+    let env = test_env!("regtest_mining", config);
+    let mut conn = env.connect_to_node().unwrap();
+
+    let msg = wait_for_message(&mut conn, 10, |msg| match msg {
+        PeerMessage::Block(block) => block.index >= 3,
+        _ => false,
+    })
+    .unwrap();
+    let block = match msg {
+        PeerMessage::Block(block) => block,
+        _ => unreachable!(),
+    };
+    assert_eq!(block.max_hash, params.genesis_max_hash);
+    assert!(block.clone().verified(&ChainParams::mainnet()).is_err());
+    block.verified(&params).unwrap();
+}
@@ -0,0 +1,104 @@
+use babencoin::{
+    block_forest::BlockForest,
+    chain_params::ChainParams,
+    codec::{write_frame, Codec},
+    data::{Block, BlockAttributes, PeerMessage, BLOCK_VERSION},
+    mempool::MempoolConfig,
+    merkle::EMPTY_MERKLE_ROOT,
+    node::{
+        self,
+        transport::{LinkConfig, Transport},
+    },
+    sim::NodeSimulation,
+};
+
+use chrono::{TimeZone, Utc};
+
+use std::time::{Duration, Instant};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn regtest_block() -> Block {
+    let chain = ChainParams::regtest();
+    let forest = BlockForest::with_params(chain.clone(), MempoolConfig::default());
+    let genesis = forest.head();
+    Block {
+        attrs: BlockAttributes {
+            version: BLOCK_VERSION,
+            index: 1,
+            reward: 0,
+            nonce: 0,
+            timestamp: Utc
+                .timestamp_opt(genesis.timestamp.timestamp() + 1, 0)
+                .unwrap(),
+            issuer: chain.genesis_issuer.clone(),
+            max_hash: forest.next_max_hash(),
+            prev_hash: *genesis.hash(),
+            merkle_root: EMPTY_MERKLE_ROOT,
+        },
+        transactions: vec![],
+    }
+}
+
+fn head_indices(sim: &NodeSimulation, node_count: usize) -> Vec<Option<f64>> {
+    (0..node_count)
+        .map(|node| sim.registry(node).get("babencoin_head_index", &[]))
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn block_propagates_on_virtual_time() {
+    // The nodes form a chain: every node dials the previous one.
+    let configs = (0..3)
+        .map(|node| {
+            let mut config = node::Config {
+                chain: ChainParams::regtest(),
+                ..node::Config::default()
+            };
+            if node > 0 {
+                config.peer_service.dial_addresses = vec![NodeSimulation::address(node - 1)];
+            }
+            config
+        })
+        .collect();
+    let latency = Duration::from_secs(10);
+    let link = LinkConfig {
+        latency,
+        ..LinkConfig::default()
+    };
+    let mut sim = NodeSimulation::start(configs, link, 0, Duration::from_millis(100)).unwrap();
+    assert!(sim.run_until(MINUTE, |sim| {
+        let sessions = |node| sim.registry(node).get("babencoin_sessions", &[]);
+        sessions(0) == Some(1.) && sessions(1) == Some(2.) && sessions(2) == Some(1.)
+    }));
+
+    let mut conn = sim
+        .network()
+        .transport(NodeSimulation::ip(100))
+        .dial(&NodeSimulation::address(0))
+        .unwrap();
+    let message = PeerMessage::Block(Box::new(regtest_block()));
+    write_frame(
+        &mut conn,
+        Codec::Json,
+        &serde_json::to_vec(&message).unwrap(),
+    )
+    .unwrap();
+
+    let sent_at = sim.elapsed();
+    let started = Instant::now();
+    assert!(sim.run_until(10 * MINUTE, |sim| {
+        head_indices(sim, 3).iter().all(|index| *index == Some(1.))
+    }));
+    // The block has crossed three links in virtual time, and much faster in real time.
+    assert!(sim.elapsed() - sent_at >= 3 * latency);
+    assert!(started.elapsed() < 3 * latency);
+
+    for reason in sim.shutdown() {
+        assert_eq!(reason, node::ShutdownReason::Requested);
+    }
+}
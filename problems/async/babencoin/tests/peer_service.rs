@@ -1,17 +1,19 @@
 #[macro_use]
 mod helpers;
 
-use helpers::send_message;
+use helpers::{send_message, sync, wait_for_message};
 
 use babencoin::{
-    data::{Block, PeerMessage, Transaction, VerifiedBlock, VerifiedTransaction, MAX_REWARD},
+    chain_params::ChainParams,
+    codec::{read_frame, write_frame, Codec, Hello, MessageDecoder, MessageEncoder},
+    data::{Block, PeerMessage, Transaction, VerifiedBlock, VerifiedTransaction, TX_VERSION_1},
     node,
     util::parse_pkcs8_private,
 };
 
 use std::{
-    io::{ErrorKind, Read, Write},
-    net::TcpListener,
+    io::{BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
     thread::sleep,
     time::Duration,
 };
@@ -52,18 +54,21 @@ fn invalid_messages() {
     let invalid_block = {
         let mut block = Block::genesis();
         block.attrs.index = 10;
-        block.attrs.reward = MAX_REWARD + 1;
+        block.attrs.reward = ChainParams::mainnet().max_reward + 1;
         block
     };
 
     let genesis_key = Block::genesis().attrs.issuer;
 
     let invalid_tx = Transaction {
+        version: TX_VERSION_1,
         amount: 1000,
         fee: 30,
         comment: "foo".into(),
         sender: genesis_key.clone(),
         receiver: genesis_key,
+        extra_outputs: vec![],
+        not_before: None,
         signature: vec![0; 64],
     };
 
@@ -119,3 +124,115 @@ fn dial() {
         listener.accept().unwrap();
     }
 }
+
+#[test]
+fn ban() {
+    let mut config = node::Config::default();
+    config.peer_service.ban_score_threshold = 100;
+    config.peer_service.ban_duration = Duration::from_secs(3);
+    let env = test_env!("test_ban", config);
+
+    let mut conn = env.connect_to_node().unwrap();
+    for nonce in 0..2 {
+        // Block timestamp is not greater than genesis timestamp, so it's rejected by the forest.
+        let mut bad_block = Block::genesis();
+        bad_block.attrs.index = 1;
+        bad_block.attrs.nonce = nonce;
+        bad_block.attrs.prev_hash = *VerifiedBlock::genesis().hash();
+        send_message(&mut conn, PeerMessage::Block(Box::new(bad_block))).unwrap();
+    }
+
+    let mut buf = vec![];
+    if conn.read_to_end(&mut buf).is_err() {
+        panic!("node didn't drop misbehaving connection");
+    }
+
+    let mut conn = env.connect_to_node().unwrap();
+    if conn.read_to_end(&mut buf).is_err() {
+        panic!("node didn't drop connection from banned address");
+    }
+
+    sleep(Duration::from_secs(4));
+
+    let mut conn = env.connect_to_node().unwrap();
+    wait_for_message(&mut conn, 10, |msg| match msg {
+        PeerMessage::Block(block) => **block == Block::genesis(),
+        _ => false,
+    })
+    .unwrap();
+}
+
+#[test]
+fn binary_codec() {
+    let mut config = node::Config::default();
+    config.peer_service.codecs = vec![Codec::Binary];
+    config.peer_service.handshake_timeout = Duration::from_secs(1);
+    let env = test_env!("test_binary_codec", config);
+
+    let mut conn = env.connect_to_node().unwrap();
+    let hello = Hello {
+        codecs: vec![Codec::BinaryDeflate, Codec::Binary],
+    };
+    let frame = serde_json::to_vec(&hello).unwrap();
+    write_frame(&mut conn, Codec::Json, &frame).unwrap();
+
+    let mut reader = BufReader::new(conn.try_clone().unwrap());
+    let frame = read_frame(&mut reader, Codec::Json).unwrap().unwrap();
+    let node_hello: Hello = serde_json::from_slice(&frame).unwrap();
+    assert_eq!(node_hello.codecs, vec![Codec::Binary]);
+    assert_eq!(Hello::negotiate(&hello, &node_hello), Codec::Binary);
+
+    let mut encoder = MessageEncoder::new(Codec::Binary);
+    let frame = encoder
+        .encode(&PeerMessage::Request {
+            block_hash: *VerifiedBlock::genesis().hash(),
+        })
+        .unwrap();
+    write_frame(&mut conn, Codec::Binary, &frame).unwrap();
+
+    let mut decoder = MessageDecoder::new(Codec::Binary);
+    loop {
+        let frame = read_frame(&mut reader, Codec::Binary)
+            .unwrap()
+            .expect("node dropped connection");
+        if let PeerMessage::Block(block) = decoder.decode(&frame).unwrap() {
+            assert_eq!(*block, Block::genesis());
+            break;
+        }
+    }
+}
+
+#[test]
+fn metrics_endpoint() {
+    let metrics_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut config = node::Config::default();
+    config.metrics.listen_address = Some(metrics_addr.to_string());
+    let env = test_env!("test_metrics_endpoint", config);
+
+    let mut conn = env.connect_to_node().unwrap();
+    sync(&mut conn).unwrap();
+
+    let mut http = TcpStream::connect(metrics_addr).unwrap();
+    http.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let value = |series: &str| -> f64 {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {} in response", series))
+            .parse()
+            .unwrap()
+    };
+    assert_eq!(value("babencoin_sessions"), 1.);
+    assert!(value("babencoin_messages_received_total{kind=\"block\"}") >= 1.);
+    assert!(value("babencoin_messages_received_total{kind=\"request\"}") >= 1.);
+    assert!(value("babencoin_messages_sent_total{kind=\"block\"}") >= 1.);
+    assert_eq!(value("babencoin_head_index"), 0.);
+}
@@ -0,0 +1,101 @@
+use babencoin::{
+    node::transport::LinkConfig,
+    sim::{SimConfig, Simulation},
+};
+
+use std::time::Duration;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn stop_mining_but_one(sim: &mut Simulation, node_count: usize) {
+    for node in 1..node_count {
+        sim.set_mining(node, false);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn fork_race() {
+    // Blocks are found faster than they propagate, so forks are frequent.
+    let config = SimConfig {
+        seed: 1,
+        node_count: 4,
+        link: LinkConfig {
+            latency: Duration::from_secs(2),
+            jitter: Duration::from_secs(2),
+            loss: 0.,
+        },
+        block_interval: Duration::from_secs(3),
+        ..SimConfig::default()
+    };
+    let mut sim = Simulation::new(config);
+    sim.run_for(10 * MINUTE);
+    assert!(sim.stats().blocks_mined > 100);
+    assert!(sim.stats().reorgs > 0);
+
+    stop_mining_but_one(&mut sim, 4);
+    assert!(sim.run_until(10 * MINUTE, |sim| sim.is_converged()));
+}
+
+#[test]
+fn partition_heals() {
+    let mut sim = Simulation::new(SimConfig {
+        seed: 2,
+        node_count: 4,
+        ..SimConfig::default()
+    });
+    sim.run_for(MINUTE);
+    assert!(sim.run_until(MINUTE, |sim| sim.is_converged()));
+
+    sim.partition(&[&[0, 1], &[2, 3]]);
+    sim.run_for(10 * MINUTE);
+    assert_eq!(sim.forest(0).head().hash(), sim.forest(1).head().hash());
+    assert_eq!(sim.forest(2).head().hash(), sim.forest(3).head().hash());
+    assert_ne!(sim.forest(0).head().hash(), sim.forest(2).head().hash());
+    let dropped = sim.stats().messages_dropped;
+    assert!(dropped > 0);
+
+    sim.heal();
+    stop_mining_but_one(&mut sim, 4);
+    assert!(sim.run_until(10 * MINUTE, |sim| sim.is_converged()));
+    assert!(sim.stats().max_reorg_depth > 0);
+    assert_eq!(sim.stats().messages_dropped, dropped);
+}
+
+#[test]
+fn lossy_link() {
+    let mut sim = Simulation::new(SimConfig {
+        seed: 3,
+        link: LinkConfig {
+            loss: 0.3,
+            ..SimConfig::default().link
+        },
+        ..SimConfig::default()
+    });
+    sim.run_for(10 * MINUTE);
+    assert!(sim.stats().messages_dropped > 0);
+
+    stop_mining_but_one(&mut sim, 3);
+    assert!(sim.run_until(30 * MINUTE, |sim| sim.is_converged()));
+}
+
+#[test]
+fn deterministic() {
+    let run = |seed| {
+        let mut sim = Simulation::new(SimConfig {
+            seed,
+            ..SimConfig::default()
+        });
+        sim.partition(&[&[0], &[1, 2]]);
+        sim.run_for(5 * MINUTE);
+        sim.heal();
+        sim.run_for(5 * MINUTE);
+        (sim.heads(), sim.stats().clone())
+    };
+
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42).0, run(43).0);
+}
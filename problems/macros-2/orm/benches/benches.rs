@@ -0,0 +1,91 @@
+use orm::{Connection, Object, ObjectId};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+#[derive(Object)]
+struct User {
+    name: String,
+    visits: i64,
+    is_admin: bool,
+}
+
+const OBJECT_COUNT: i64 = 10_000;
+
+fn users() -> impl Iterator<Item = User> {
+    (0..OBJECT_COUNT).map(|i| User {
+        name: format!("user{}", i),
+        visits: i,
+        is_admin: i % 100 == 0,
+    })
+}
+
+/// A connection with `OBJECT_COUNT` users committed.
+fn populated(open: fn() -> Connection) -> (Connection, Vec<ObjectId>) {
+    let mut conn = open();
+    let tx = conn.new_transaction().unwrap();
+    let ids = tx
+        .create_many(users())
+        .unwrap()
+        .iter()
+        .map(|user| user.id())
+        .collect();
+    tx.commit().unwrap();
+    (conn, ids)
+}
+
+fn bench_commit_10k(c: &mut Criterion) {
+    let backends: [(&str, fn() -> Connection); 2] = [
+        ("sqlite", || Connection::open_in_memory().unwrap()),
+        ("memory", || Connection::open_memory_storage().unwrap()),
+    ];
+
+    for (name, open) in backends {
+        let mut group = c.benchmark_group(format!("commit_10k_{}", name));
+        group.sample_size(10);
+
+        group.bench_function("insert", |b| {
+            b.iter_batched(
+                open,
+                |mut conn| {
+                    let tx = conn.new_transaction().unwrap();
+                    tx.create_many(users()).unwrap();
+                    tx.commit().unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+
+        group.bench_function("update", |b| {
+            b.iter_batched(
+                || populated(open),
+                |(mut conn, ids)| {
+                    let tx = conn.new_transaction().unwrap();
+                    for id in ids {
+                        tx.get::<User>(id).unwrap().borrow_mut().visits += 1;
+                    }
+                    tx.commit().unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+
+        group.bench_function("delete", |b| {
+            b.iter_batched(
+                || populated(open),
+                |(mut conn, ids)| {
+                    let tx = conn.new_transaction().unwrap();
+                    for id in ids {
+                        tx.get::<User>(id).unwrap().delete();
+                    }
+                    tx.commit().unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_commit_10k);
+criterion_main!(benches);
@@ -3,10 +3,67 @@ use proc_macro::{Span, TokenStream};
 use quote::quote;
 use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, LitStr};
 
-#[proc_macro_derive(Object, attributes(table_name, column_name))]
+#[proc_macro_derive(Object, attributes(table_name, column_name, orm))]
 pub fn derive_object(input: TokenStream) -> TokenStream {
     // TODO: your code goes here.
     unimplemented!()
 }
 
 // TODO: your code goes here.
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Implements `orm::Field` for a fieldless enum, storing it as the name of
+/// the variant.
+#[proc_macro_derive(Field)]
+pub fn derive_field(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = &input.ident;
+    let variants = match &input.data {
+        Data::Enum(data) => &data.variants,
+        _ => {
+            return syn::Error::new_spanned(ident, "Field can be derived only for enums")
+                .to_compile_error()
+                .into()
+        }
+    };
+    if let Some(variant) = variants
+        .iter()
+        .find(|variant| !matches!(variant.fields, Fields::Unit))
+    {
+        return syn::Error::new_spanned(variant, "variants of a Field enum can't have fields")
+            .to_compile_error()
+            .into();
+    }
+
+    let names = variants
+        .iter()
+        .map(|variant| LitStr::new(&variant.ident.to_string(), variant.ident.span()));
+    let names_2 = names.clone();
+    let variants = variants.iter().map(|variant| &variant.ident);
+    let variants_2 = variants.clone();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::orm::field::Field for #ident #ty_generics #where_clause {
+            const DATA_TYPE: ::orm::data::DataType = ::orm::data::DataType::String;
+
+            fn to_value(&self) -> ::orm::data::Value<'_> {
+                ::orm::data::Value::String(::std::borrow::Cow::Borrowed(match self {
+                    #(Self::#variants => #names,)*
+                }))
+            }
+
+            fn from_value(value: ::orm::data::Value<'_>) -> ::std::option::Option<Self> {
+                match value {
+                    ::orm::data::Value::String(s) => match &*s {
+                        #(#names_2 => ::std::option::Option::Some(Self::#variants_2),)*
+                        _ => ::std::option::Option::None,
+                    },
+                    _ => ::std::option::Option::None,
+                }
+            }
+        }
+    }
+    .into()
+}
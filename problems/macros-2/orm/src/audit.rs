@@ -0,0 +1,234 @@
+#![forbid(unsafe_code)]
+use crate::{
+    data::Value,
+    error::{Error, Result},
+    object::Schema,
+    storage::RowSlice,
+    ObjectId,
+};
+use chrono::{DateTime, Utc};
+use std::fmt::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A field whose value differs from the one in the storage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub attr_name: &'static str,
+    pub column_name: &'static str,
+    pub old: Value<'static>,
+    pub new: Value<'static>,
+}
+
+/// Compares two rows of the schema.
+pub fn diff(schema: &Schema, old: &RowSlice, new: &RowSlice) -> Vec<FieldChange> {
+    schema
+        .columns()
+        .iter()
+        .zip(old.iter().zip(new))
+        .filter(|(_, (old, new))| old != new)
+        .map(|(column, (old, new))| FieldChange {
+            attr_name: column.attr_name,
+            column_name: column.column_name,
+            old: old.clone().into_owned(),
+            new: new.clone().into_owned(),
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "insert" => Some(Self::Insert),
+            "update" => Some(Self::Update),
+            "delete" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A row of the audit table.
+///
+/// `changes` is a JSON object keyed by column names: the values of the
+/// inserted or deleted object, or `[old, new]` pairs for an update.
+/// `committed_at` is the same for all the records of a commit: it is taken
+/// right before they are passed to `append_audit_log()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    pub table_name: String,
+    pub object_id: ObjectId,
+    pub operation: Operation,
+    pub changes: String,
+    pub committed_at: DateTime<Utc>,
+}
+
+/// The table the records are stored in.
+pub const AUDIT_TABLE: &str = "orm_audit_log";
+
+impl AuditRecord {
+    pub fn insert(
+        schema: &Schema,
+        object_id: ObjectId,
+        row: &RowSlice,
+        committed_at: DateTime<Utc>,
+    ) -> Self {
+        Self::new(
+            schema,
+            object_id,
+            Operation::Insert,
+            row_to_json(schema, row),
+            committed_at,
+        )
+    }
+
+    pub fn update(
+        schema: &Schema,
+        object_id: ObjectId,
+        changes: &[FieldChange],
+        committed_at: DateTime<Utc>,
+    ) -> Self {
+        let mut json = String::from("{");
+        for (i, change) in changes.iter().enumerate() {
+            if i > 0 {
+                json.push_str(", ");
+            }
+            write_string(&mut json, change.column_name);
+            json.push_str(": [");
+            write_value(&mut json, &change.old);
+            json.push_str(", ");
+            write_value(&mut json, &change.new);
+            json.push(']');
+        }
+        json.push('}');
+        Self::new(schema, object_id, Operation::Update, json, committed_at)
+    }
+
+    /// `row` holds the values the object had before it was deleted.
+    pub fn delete(
+        schema: &Schema,
+        object_id: ObjectId,
+        row: &RowSlice,
+        committed_at: DateTime<Utc>,
+    ) -> Self {
+        Self::new(
+            schema,
+            object_id,
+            Operation::Delete,
+            row_to_json(schema, row),
+            committed_at,
+        )
+    }
+
+    fn new(
+        schema: &Schema,
+        object_id: ObjectId,
+        operation: Operation,
+        changes: String,
+        committed_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            table_name: schema.table_name().to_string(),
+            object_id,
+            operation,
+            changes,
+            committed_at,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn row_to_json(schema: &Schema, row: &RowSlice) -> String {
+    let mut json = String::from("{");
+    for (i, (column, value)) in schema.columns().iter().zip(row).enumerate() {
+        if i > 0 {
+            json.push_str(", ");
+        }
+        write_string(&mut json, column.column_name);
+        json.push_str(": ");
+        write_value(&mut json, value);
+    }
+    json.push('}');
+    json
+}
+
+/// Bytes are written as a hex string, non-finite floats as `null`.
+fn write_value(json: &mut String, value: &Value) {
+    match value {
+        Value::String(s) => write_string(json, s),
+        Value::Bytes(b) => {
+            json.push('"');
+            for byte in b.iter() {
+                write!(json, "{:02x}", byte).unwrap();
+            }
+            json.push('"');
+        }
+        Value::Int64(x) => write!(json, "{}", x).unwrap(),
+        Value::Float64(x) if x.is_finite() => write!(json, "{:?}", x).unwrap(),
+        Value::Float64(_) | Value::Null => json.push_str("null"),
+        Value::Bool(x) => write!(json, "{}", x).unwrap(),
+        Value::Ref(id) => write!(json, "{}", id.into_i64()).unwrap(),
+    }
+}
+
+fn write_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Builds a record from the columns of the audit table, after `id`.
+pub(crate) fn parse_record(
+    table_name: String,
+    object_id: i64,
+    operation: &str,
+    changes: String,
+    committed_at: &str,
+) -> Result<AuditRecord> {
+    let operation = Operation::parse(operation).ok_or_else(|| {
+        Error::Storage(format!("unknown audit log operation '{}'", operation).into())
+    })?;
+    let committed_at = DateTime::parse_from_rfc3339(committed_at)
+        .map_err(|err| Error::Storage(Box::new(err)))?
+        .with_timezone(&Utc);
+    Ok(AuditRecord {
+        table_name,
+        object_id: object_id.into(),
+        operation,
+        changes,
+        committed_at,
+    })
+}
@@ -1,10 +1,15 @@
 #![forbid(unsafe_code)]
-use crate::{storage::StorageTransaction, Result, Transaction};
-use std::path::Path;
+use crate::{
+    audit::AuditRecord,
+    migration::MigrationPolicy,
+    storage::{memory::MemoryStorage, StorageTransaction},
+    Result, Transaction,
+};
+use std::{path::Path, thread, time::Duration};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-trait StorageConnection {
+pub(crate) trait StorageConnection {
     fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>>;
 }
 
@@ -16,24 +21,129 @@ impl StorageConnection for rusqlite::Connection {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// How many times `Connection::transact` runs a transaction that fails with
+/// a conflict.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled before every next one.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub struct Connection {
     inner: Box<dyn StorageConnection>,
+    migration_policy: MigrationPolicy,
+    retry_policy: RetryPolicy,
+    audit_log: bool,
 }
 
 impl Connection {
     pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open(path)?),
-        })
+        Self::new_sqlite(rusqlite::Connection::open(path)?)
     }
 
     pub fn open_in_memory() -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open_in_memory()?),
-        })
+        Self::new_sqlite(rusqlite::Connection::open_in_memory()?)
+    }
+
+    /// Keeps the data in memory without SQLite. Every connection has its own
+    /// storage, nothing is saved.
+    pub fn open_memory_storage() -> Result<Self> {
+        Ok(Self::new(Box::<MemoryStorage>::default()))
+    }
+
+    /// `params` are described in `postgres::Config`, e.g.
+    /// `host=localhost user=postgres`.
+    #[cfg(feature = "postgres")]
+    pub fn open_postgres(params: &str) -> Result<Self> {
+        let client = ::postgres::Client::connect(params, ::postgres::NoTls)
+            .map_err(|err| crate::Error::Storage(Box::new(err)))?;
+        Ok(Self::new(Box::new(client)))
+    }
+
+    fn new(inner: Box<dyn StorageConnection>) -> Self {
+        Self {
+            inner,
+            migration_policy: MigrationPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            audit_log: false,
+        }
+    }
+
+    fn new_sqlite(conn: rusqlite::Connection) -> Result<Self> {
+        // Foreign keys are off by default and can't be enabled inside a transaction.
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        Ok(Self::new(Box::new(conn)))
+    }
+
+    /// Applies to the transactions created afterwards.
+    pub fn set_migration_policy(&mut self, policy: MigrationPolicy) {
+        self.migration_policy = policy;
+    }
+
+    /// Applies to the transactions created afterwards.
+    pub fn set_audit_log(&mut self, enabled: bool) {
+        self.audit_log = enabled;
+    }
+
+    /// The records appended by the transactions with the audit log enabled.
+    pub fn audit_log(&mut self) -> Result<Vec<AuditRecord>> {
+        let tx = self.inner.new_transaction()?;
+        let records = tx.audit_log()?;
+        tx.rollback()?;
+        Ok(records)
     }
 
     pub fn new_transaction(&mut self) -> Result<Transaction<'_>> {
-        Ok(Transaction::new(self.inner.new_transaction()?))
+        Ok(Transaction::new(
+            self.inner.new_transaction()?,
+            self.migration_policy,
+            self.audit_log,
+        ))
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Runs `f` in a new transaction and commits it. If either fails with
+    /// a conflict (see `Error::is_conflict`), the transaction is rolled back
+    /// and `f` is run again in another one, as allowed by the retry policy.
+    pub fn transact<R>(&mut self, mut f: impl FnMut(&Transaction<'_>) -> Result<R>) -> Result<R> {
+        let RetryPolicy {
+            max_retries,
+            mut backoff,
+        } = self.retry_policy;
+        let mut retries = 0;
+        loop {
+            let tx = self.new_transaction()?;
+            let res = match f(&tx) {
+                Ok(value) => tx.commit().map(|()| value),
+                Err(err) => {
+                    // The error of `f` is more interesting than the one of rollback.
+                    let _ = tx.rollback();
+                    Err(err)
+                }
+            };
+            match res {
+                Err(err) if err.is_conflict() && retries < max_retries => {
+                    retries += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                res => return res,
+            }
+        }
     }
 }
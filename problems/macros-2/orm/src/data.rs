@@ -3,7 +3,7 @@ use std::{borrow::Cow, fmt};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct ObjectId(i64);
 
 // TODO: your code goes here.
@@ -17,16 +17,36 @@ pub enum DataType {
     Int64,
     Float64,
     Bool,
+    /// `relation::Ref<T>`, stored as the id of the referenced object.
+    Ref,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value<'a> {
     String(Cow<'a, str>),
     Bytes(Cow<'a, [u8]>),
     Int64(i64),
     Float64(f64),
     Bool(bool),
+    Ref(ObjectId),
+    /// Only in the columns of `Option<T>` fields.
+    Null,
+}
+
+impl Value<'_> {
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Self::String(s) => Value::String(Cow::Owned(s.into_owned())),
+            Self::Bytes(b) => Value::Bytes(Cow::Owned(b.into_owned())),
+            Self::Int64(x) => Value::Int64(x),
+            Self::Float64(x) => Value::Float64(x),
+            Self::Bool(x) => Value::Bool(x),
+            Self::Ref(id) => Value::Ref(id),
+            Self::Null => Value::Null,
+        }
+    }
 }
 
 // TODO: your code goes here.
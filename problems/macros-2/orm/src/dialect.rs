@@ -0,0 +1,103 @@
+#![forbid(unsafe_code)]
+use crate::data::DataType;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The parts of SQL that differ between the databases.
+pub trait Dialect {
+    fn quote_identifier(&self, name: &str) -> String {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+
+    /// The type of the column holding values of the given type.
+    fn sql_type(&self, data_type: DataType) -> &'static str;
+
+    /// The literal of `migration::default_value`.
+    fn default_value(&self, data_type: DataType) -> &'static str;
+
+    /// The definition of the `id` column in `CREATE TABLE`.
+    fn id_column(&self) -> &'static str;
+
+    /// The placeholder of the `index`-th parameter of a statement, starting from 1.
+    fn placeholder(&self, index: usize) -> String;
+
+    /// `LIMIT` clause argument meaning no limit, for queries with `OFFSET`.
+    fn no_limit(&self) -> &'static str;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Sqlite;
+
+impl Dialect for Sqlite {
+    fn sql_type(&self, data_type: DataType) -> &'static str {
+        match data_type {
+            DataType::String => "TEXT",
+            DataType::Bytes => "BLOB",
+            DataType::Int64 => "BIGINT",
+            DataType::Float64 => "REAL",
+            DataType::Bool => "TINYINT",
+            DataType::Ref => "INTEGER",
+        }
+    }
+
+    fn default_value(&self, data_type: DataType) -> &'static str {
+        match data_type {
+            DataType::String => "''",
+            DataType::Bytes => "x''",
+            DataType::Int64 | DataType::Bool => "0",
+            DataType::Float64 => "0.0",
+            DataType::Ref => "NULL",
+        }
+    }
+
+    fn id_column(&self) -> &'static str {
+        "id INTEGER PRIMARY KEY AUTOINCREMENT"
+    }
+
+    fn placeholder(&self, _index: usize) -> String {
+        "?".to_string()
+    }
+
+    fn no_limit(&self) -> &'static str {
+        "-1"
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Postgres;
+
+impl Dialect for Postgres {
+    fn sql_type(&self, data_type: DataType) -> &'static str {
+        match data_type {
+            DataType::String => "text",
+            DataType::Bytes => "bytea",
+            DataType::Int64 | DataType::Ref => "bigint",
+            DataType::Float64 => "double precision",
+            DataType::Bool => "boolean",
+        }
+    }
+
+    fn default_value(&self, data_type: DataType) -> &'static str {
+        match data_type {
+            DataType::String | DataType::Bytes => "''",
+            DataType::Int64 => "0",
+            DataType::Float64 => "0.0",
+            DataType::Bool => "FALSE",
+            DataType::Ref => "NULL",
+        }
+    }
+
+    fn id_column(&self) -> &'static str {
+        "id BIGSERIAL PRIMARY KEY"
+    }
+
+    fn placeholder(&self, index: usize) -> String {
+        format!("${}", index)
+    }
+
+    fn no_limit(&self) -> &'static str {
+        "ALL"
+    }
+}
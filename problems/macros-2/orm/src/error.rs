@@ -1,5 +1,5 @@
 #![forbid(unsafe_code)]
-use crate::{data::DataType, object::Schema, ObjectId};
+use crate::{data::DataType, migration::MigrationStep, object::Schema, ObjectId};
 use thiserror::Error;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -12,12 +12,32 @@ pub enum Error {
     UnexpectedType(Box<UnexpectedTypeError>),
     #[error(transparent)]
     MissingColumn(Box<MissingColumnError>),
+    #[error(transparent)]
+    ReferentialIntegrity(Box<ReferentialIntegrityError>),
+    #[error(transparent)]
+    Migration(Box<MigrationError>),
+    #[error(transparent)]
+    StaleObject(Box<StaleObjectError>),
+    #[error(transparent)]
+    UniqueViolation(Box<UniqueViolationError>),
+    #[error(transparent)]
+    NotUnique(Box<NotUniqueError>),
+    #[error("hook failed: {0}")]
+    Hook(#[source] Box<dyn std::error::Error>),
     #[error("database is locked")]
     LockConflict,
     #[error("storage error: {0}")]
     Storage(#[source] Box<dyn std::error::Error>),
 }
 
+impl Error {
+    /// Whether the transaction may succeed if it is run again, see
+    /// `Connection::transact`.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Self::LockConflict | Self::StaleObject(_))
+    }
+}
+
 impl<'a> From<ErrorWithCtx<'a, rusqlite::Error>> for Error {
     fn from(err: ErrorWithCtx<rusqlite::Error>) -> Self {
         // TODO: your code goes here.
@@ -44,12 +64,14 @@ pub struct NotFoundError {
 
 #[derive(Error, Debug)]
 #[error(
-    "invalid type for {type_name}::{attr_name}: expected equivalent of {expected_type:?}, \
-    got {got_type} (table: {table_name}, column: {column_name})"
+    "invalid type for {type_name}::{attr_name} of type {rust_type}: expected equivalent of \
+    {expected_type:?}, got {got_type} (table: {table_name}, column: {column_name})"
 )]
 pub struct UnexpectedTypeError {
     pub type_name: &'static str,
     pub attr_name: &'static str,
+    /// `std::any::type_name` of the field.
+    pub rust_type: &'static str,
     pub table_name: &'static str,
     pub column_name: &'static str,
     pub expected_type: DataType,
@@ -72,4 +94,81 @@ pub struct MissingColumnError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Error, Debug)]
+#[error(
+    "object {object_id} in table {table_name} references a missing object \
+    in table {referenced_table_name}"
+)]
+pub struct ReferentialIntegrityError {
+    pub object_id: ObjectId,
+    pub table_name: String,
+    pub referenced_table_name: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error(
+    "object was changed by another transaction: type '{type_name}', id {object_id}, \
+    version {version}"
+)]
+pub struct StaleObjectError {
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+    /// The version the transaction has loaded.
+    pub version: i64,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("duplicate value in unique columns {column_names:?} of table {table_name}")]
+pub struct UniqueViolationError {
+    pub table_name: &'static str,
+    pub column_names: &'static [&'static str],
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error(
+    "{type_name}::{attr_name} has no unique index of its own \
+    (table: {table_name}, column: {column_name})"
+)]
+pub struct NotUniqueError {
+    pub type_name: &'static str,
+    pub attr_name: &'static str,
+    pub table_name: &'static str,
+    pub column_name: &'static str,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("cannot migrate table {table_name}: {kind} step '{step}'")]
+pub struct MigrationError {
+    pub table_name: &'static str,
+    pub kind: MigrationErrorKind,
+    pub step: MigrationStep,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationErrorKind {
+    /// The step loses data and `MigrationPolicy::AllowDestructive` is not set.
+    Destructive,
+    /// The step can't be applied to a table that already has rows.
+    Unsupported,
+}
+
+impl std::fmt::Display for MigrationErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Destructive => write!(f, "destructive"),
+            Self::Unsupported => write!(f, "unsupported"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub type Result<T> = std::result::Result<T, Error>;
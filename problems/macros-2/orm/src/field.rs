@@ -0,0 +1,192 @@
+#![forbid(unsafe_code)]
+use crate::{data::DataType, data::Value, relation::Ref};
+use chrono::{DateTime, TimeZone, Utc};
+use std::borrow::Cow;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A type that can be a field of an object.
+///
+/// Fieldless enums implement it with `#[derive(orm::Field)]`: they are stored
+/// as the names of their variants.
+pub trait Field: Sized {
+    const DATA_TYPE: DataType;
+    /// Whether the column may contain NULL.
+    const NULLABLE: bool = false;
+
+    fn to_value(&self) -> Value<'_>;
+
+    /// Returns `None` if the value has another type or doesn't fit into `Self`.
+    fn from_value(value: Value<'_>) -> Option<Self>;
+}
+
+impl Field for String {
+    const DATA_TYPE: DataType = DataType::String;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::String(Cow::Borrowed(self))
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(s.into_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl Field for Vec<u8> {
+    const DATA_TYPE: DataType = DataType::Bytes;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::Bytes(Cow::Borrowed(self))
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Bytes(b) => Some(b.into_owned()),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_int_field {
+    ($($ty:ty),*) => {
+        $(
+            impl Field for $ty {
+                const DATA_TYPE: DataType = DataType::Int64;
+
+                fn to_value(&self) -> Value<'_> {
+                    Value::Int64(i64::from(*self))
+                }
+
+                fn from_value(value: Value<'_>) -> Option<Self> {
+                    match value {
+                        Value::Int64(x) => x.try_into().ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_int_field!(i64, i32, u32);
+
+impl Field for f64 {
+    const DATA_TYPE: DataType = DataType::Float64;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::Float64(*self)
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Float64(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl Field for f32 {
+    const DATA_TYPE: DataType = DataType::Float64;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::Float64(f64::from(*self))
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Float64(x) => Some(x as f32),
+            _ => None,
+        }
+    }
+}
+
+impl Field for bool {
+    const DATA_TYPE: DataType = DataType::Bool;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::Bool(*self)
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Bool(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl<T> Field for Ref<T> {
+    const DATA_TYPE: DataType = DataType::Ref;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::Ref(self.id())
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Ref(id) => Some(Ref::new(id)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Field> Field for Option<T> {
+    const DATA_TYPE: DataType = T::DATA_TYPE;
+    const NULLABLE: bool = true;
+
+    fn to_value(&self) -> Value<'_> {
+        match self {
+            Some(x) => x.to_value(),
+            None => Value::Null,
+        }
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Stored as an RFC 3339 string, e.g. `2022-04-01T12:00:00+00:00`.
+impl Field for DateTime<Utc> {
+    const DATA_TYPE: DataType = DataType::String;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::String(Cow::Owned(self.to_rfc3339()))
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::String(s) => DateTime::parse_from_rfc3339(&s)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            _ => None,
+        }
+    }
+}
+
+/// A point in time stored as the number of seconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnixTimestamp(pub DateTime<Utc>);
+
+impl Field for UnixTimestamp {
+    const DATA_TYPE: DataType = DataType::Int64;
+
+    fn to_value(&self) -> Value<'_> {
+        Value::Int64(self.0.timestamp())
+    }
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        match value {
+            Value::Int64(secs) => Utc.timestamp_opt(secs, 0).single().map(Self),
+            _ => None,
+        }
+    }
+}
@@ -3,14 +3,24 @@ mod connection;
 mod error;
 mod transaction;
 
+pub mod audit;
 pub mod data;
+pub mod dialect;
+pub mod field;
+pub mod migration;
 pub mod object;
+pub mod query;
+pub mod relation;
 pub mod storage;
 
-pub use connection::Connection;
+pub use connection::{Connection, RetryPolicy};
 pub use data::ObjectId;
-pub use error::{Error, Result};
-pub use object::Object;
+pub use error::{Error, MigrationErrorKind, Result};
+pub use field::Field;
+pub use migration::MigrationPolicy;
+pub use object::{Hooks, Object};
+pub use query::{Column, Predicate, Select};
+pub use relation::{HasMany, OnDelete, Ref};
 pub use transaction::{ObjectState, Transaction, Tx};
 
-pub use orm_derive::Object;
+pub use orm_derive::{Field, Object};
@@ -0,0 +1,269 @@
+#![forbid(unsafe_code)]
+use crate::{
+    data::{DataType, Value},
+    dialect::Dialect,
+    error::{Error, MigrationError, MigrationErrorKind, Result},
+    relation::ForeignKey,
+};
+use std::borrow::Cow;
+use std::fmt::{self, Display};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// How `ensure_table` treats an existing table that doesn't match the schema.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MigrationPolicy {
+    /// Leave the table as is: accessing the objects fails with
+    /// `MissingColumn` or `UnexpectedType`.
+    #[default]
+    Disabled,
+    /// Only add and rename columns, fail on everything else.
+    Safe,
+    /// Also drop the columns that are no longer used and recreate the
+    /// columns whose type has changed.
+    AllowDestructive,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A column as it is expected by the object schema.
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnDef {
+    pub attr_name: &'static str,
+    pub column_name: &'static str,
+    pub data_type: DataType,
+    pub nullable: bool,
+    /// Set for the `Ref<T>` fields.
+    pub references: Option<ForeignKey>,
+    /// Former names of the column, from `#[orm(renamed_from(...))]`.
+    pub renamed_from: &'static [&'static str],
+}
+
+/// An index from `#[orm(index)]` or `#[orm(unique)]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexDef {
+    /// Column names, in the order of the index.
+    pub columns: &'static [&'static str],
+    pub unique: bool,
+}
+
+impl IndexDef {
+    /// E.g. `User_name_key` for a unique index and `User_name_idx` otherwise.
+    pub fn name(&self, table_name: &str) -> String {
+        let suffix = if self.unique { "key" } else { "idx" };
+        format!("{}_{}_{}", table_name, self.columns.join("_"), suffix)
+    }
+
+    pub fn to_sql(&self, table_name: &str, dialect: &dyn Dialect) -> String {
+        let columns: Vec<_> = self
+            .columns
+            .iter()
+            .map(|column| dialect.quote_identifier(column))
+            .collect();
+        format!(
+            "CREATE {}INDEX {} ON {} ({})",
+            if self.unique { "UNIQUE " } else { "" },
+            dialect.quote_identifier(&self.name(table_name)),
+            dialect.quote_identifier(table_name),
+            columns.join(", "),
+        )
+    }
+}
+
+/// A column as it exists in the table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub sql_type: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MigrationStep {
+    AddColumn {
+        column_name: &'static str,
+        data_type: DataType,
+        nullable: bool,
+        references: Option<ForeignKey>,
+    },
+    RenameColumn {
+        from: String,
+        to: &'static str,
+    },
+    DropColumn {
+        column_name: String,
+    },
+}
+
+impl MigrationStep {
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, Self::DropColumn { .. })
+    }
+
+    pub fn to_sql(&self, table_name: &str, dialect: &dyn Dialect) -> String {
+        let table = dialect.quote_identifier(table_name);
+        match self {
+            Self::AddColumn {
+                column_name,
+                data_type,
+                nullable,
+                references,
+            } => {
+                let mut sql = format!(
+                    "ALTER TABLE {} ADD COLUMN {} {} DEFAULT {}",
+                    table,
+                    dialect.quote_identifier(column_name),
+                    dialect.sql_type(*data_type),
+                    if *nullable {
+                        "NULL"
+                    } else {
+                        dialect.default_value(*data_type)
+                    },
+                );
+                if let Some(fk) = references {
+                    sql.push(' ');
+                    sql.push_str(&fk.to_sql(dialect));
+                }
+                sql
+            }
+            Self::RenameColumn { from, to } => format!(
+                "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                table,
+                dialect.quote_identifier(from),
+                dialect.quote_identifier(to),
+            ),
+            Self::DropColumn { column_name } => format!(
+                "ALTER TABLE {} DROP COLUMN {}",
+                table,
+                dialect.quote_identifier(column_name),
+            ),
+        }
+    }
+}
+
+impl Display for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AddColumn {
+                column_name,
+                data_type,
+                ..
+            } => write!(f, "add column {} of type {:?}", column_name, data_type),
+            Self::RenameColumn { from, to } => write!(f, "rename column {} to {}", from, to),
+            Self::DropColumn { column_name } => write!(f, "drop column {}", column_name),
+        }
+    }
+}
+
+/// The value that the rows existing before the migration get in a new column
+/// of a non-`Option` field.
+pub fn default_value(data_type: DataType) -> Value<'static> {
+    match data_type {
+        DataType::String => Value::String(Cow::Borrowed("")),
+        DataType::Bytes => Value::Bytes(Cow::Borrowed(&[])),
+        DataType::Int64 => Value::Int64(0),
+        DataType::Float64 => Value::Float64(0.),
+        DataType::Bool => Value::Bool(false),
+        DataType::Ref => Value::Null,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Computes the steps turning the `existing` columns of the table into the
+/// `expected` ones. The `id` column is never touched.
+///
+/// Fails if some step is destructive and the policy doesn't allow it, or if a
+/// non-nullable `Ref` column must be added: there is no object the existing
+/// rows could reference.
+pub fn plan(
+    table_name: &'static str,
+    expected: &[ColumnDef],
+    existing: &[ColumnInfo],
+    policy: MigrationPolicy,
+    dialect: &dyn Dialect,
+) -> Result<Vec<MigrationStep>> {
+    let mut unused: Vec<&ColumnInfo> = existing.iter().filter(|c| c.name != "id").collect();
+    let mut take_unused = |name: &str| {
+        let pos = unused.iter().position(|c| c.name == name)?;
+        Some(unused.remove(pos))
+    };
+
+    let mut renames = vec![];
+    let mut adds = vec![];
+    let mut drops = vec![];
+
+    let matched: Vec<_> = expected
+        .iter()
+        .map(|def| (def, take_unused(def.column_name)))
+        .collect();
+    for (def, column) in matched {
+        let column = match column {
+            Some(column) => Some(column),
+            None => def.renamed_from.iter().find_map(|name| {
+                let column = take_unused(name)?;
+                renames.push(MigrationStep::RenameColumn {
+                    from: column.name.clone(),
+                    to: def.column_name,
+                });
+                Some(column)
+            }),
+        };
+        let add = MigrationStep::AddColumn {
+            column_name: def.column_name,
+            data_type: def.data_type,
+            nullable: def.nullable,
+            references: def.references,
+        };
+        match column {
+            Some(column)
+                if column
+                    .sql_type
+                    .eq_ignore_ascii_case(dialect.sql_type(def.data_type)) => {}
+            _ if def.data_type == DataType::Ref && !def.nullable => {
+                return Err(migration_error(
+                    table_name,
+                    MigrationErrorKind::Unsupported,
+                    add,
+                ));
+            }
+            Some(_) => {
+                drops.push(MigrationStep::DropColumn {
+                    column_name: def.column_name.to_string(),
+                });
+                adds.push(add);
+            }
+            None => adds.push(add),
+        }
+    }
+    drops.extend(unused.into_iter().map(|column| MigrationStep::DropColumn {
+        column_name: column.name.clone(),
+    }));
+
+    // Renames go first, so that a type change of a renamed column drops it by
+    // its new name.
+    let steps: Vec<_> = renames.into_iter().chain(drops).chain(adds).collect();
+    if policy != MigrationPolicy::AllowDestructive {
+        if let Some(step) = steps.iter().find(|step| step.is_destructive()) {
+            return Err(migration_error(
+                table_name,
+                MigrationErrorKind::Destructive,
+                step.clone(),
+            ));
+        }
+    }
+    Ok(steps)
+}
+
+fn migration_error(
+    table_name: &'static str,
+    kind: MigrationErrorKind,
+    step: MigrationStep,
+) -> Error {
+    Error::Migration(Box::new(MigrationError {
+        table_name,
+        kind,
+        step,
+    }))
+}
@@ -1,10 +1,37 @@
 #![forbid(unsafe_code)]
-use crate::{data::DataType, storage::Row};
+use crate::{
+    data::DataType,
+    migration::{ColumnDef, IndexDef},
+    storage::Row,
+    Result,
+};
 use std::any::Any;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub trait Object: Any + Sized {
+/// Callbacks invoked by the transaction. `#[derive(Object)]` implements them
+/// as no-ops, unless the struct is marked with `#[orm(hooks)]` and has its
+/// own implementation.
+///
+/// An error returned by a hook fails the operation, e.g. with `Error::Hook`.
+pub trait Hooks {
+    /// Called by `Transaction::create` before the object is inserted.
+    fn before_insert(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the changes of the object are written to the storage.
+    fn after_update(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the removed object is deleted from the storage.
+    fn before_delete(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub trait Object: Any + Sized + Hooks {
     // TODO: your code goes here.
 }
 
@@ -14,4 +41,35 @@ pub struct Schema {
     // TODO: your code goes here.
 }
 
+/// Used by the storages that build SQL themselves.
+impl Schema {
+    pub fn type_name(&self) -> &'static str {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
+    pub fn table_name(&self) -> &'static str {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
+    /// All columns but `id`, in the order of the values in a row.
+    pub fn columns(&self) -> &[ColumnDef] {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
+    /// The indexes to create together with the table.
+    pub fn indexes(&self) -> &[IndexDef] {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
+    /// Position of the `#[orm(version)]` column in `columns()`.
+    pub fn version_column(&self) -> Option<usize> {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+}
+
 // TODO: your code goes here.
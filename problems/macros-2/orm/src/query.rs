@@ -0,0 +1,485 @@
+#![forbid(unsafe_code)]
+use crate::{
+    data::Value, dialect::Dialect, field::Field, object::Object, storage::Row,
+    transaction::Transaction, ObjectId, Result, Tx,
+};
+use std::{cmp::Ordering, fmt::Write, marker::PhantomData};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A column of the table of `T` holding values of type `V`.
+///
+/// `#[derive(Object)]` generates an associated function returning the column
+/// for every field, e.g. `User::name()` for `User::name`.
+pub struct Column<T, V> {
+    attr_name: &'static str,
+    column_name: &'static str,
+    _marker: PhantomData<fn() -> (T, V)>,
+}
+
+impl<T, V> Clone for Column<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Column<T, V> {}
+
+impl<T: Object, V: Field> Column<T, V> {
+    pub const fn new(attr_name: &'static str, column_name: &'static str) -> Self {
+        Self {
+            attr_name,
+            column_name,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn attr_name(&self) -> &'static str {
+        self.attr_name
+    }
+
+    pub fn column_name(&self) -> &'static str {
+        self.column_name
+    }
+
+    pub fn eq(self, value: impl Into<V>) -> Predicate<T> {
+        self.compare(CompareOp::Eq, value)
+    }
+
+    pub fn ne(self, value: impl Into<V>) -> Predicate<T> {
+        self.compare(CompareOp::Ne, value)
+    }
+
+    pub fn lt(self, value: impl Into<V>) -> Predicate<T> {
+        self.compare(CompareOp::Lt, value)
+    }
+
+    pub fn le(self, value: impl Into<V>) -> Predicate<T> {
+        self.compare(CompareOp::Le, value)
+    }
+
+    pub fn gt(self, value: impl Into<V>) -> Predicate<T> {
+        self.compare(CompareOp::Gt, value)
+    }
+
+    pub fn ge(self, value: impl Into<V>) -> Predicate<T> {
+        self.compare(CompareOp::Ge, value)
+    }
+
+    pub fn is_in<I: Into<V>>(self, values: impl IntoIterator<Item = I>) -> Predicate<T> {
+        Predicate::new(Expr::In {
+            column_name: self.column_name,
+            values: values
+                .into_iter()
+                .map(|v| v.into().to_value().into_owned())
+                .collect(),
+        })
+    }
+
+    fn compare(self, op: CompareOp, value: impl Into<V>) -> Predicate<T> {
+        Predicate::new(Expr::Compare {
+            column_name: self.column_name,
+            op,
+            value: value.into().to_value().into_owned(),
+        })
+    }
+}
+
+impl<T: Object, V: Field> Column<T, Option<V>> {
+    pub fn is_null(self) -> Predicate<T> {
+        self.eq(None::<V>)
+    }
+
+    pub fn is_not_null(self) -> Predicate<T> {
+        self.ne(None::<V>)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn holds(&self, ord: Ordering) -> bool {
+        match self {
+            Self::Eq => ord.is_eq(),
+            Self::Ne => ord.is_ne(),
+            Self::Lt => ord.is_lt(),
+            Self::Le => ord.is_le(),
+            Self::Gt => ord.is_gt(),
+            Self::Ge => ord.is_ge(),
+        }
+    }
+
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Eq => "=",
+            Self::Ne => "<>",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+enum Expr {
+    Compare {
+        column_name: &'static str,
+        op: CompareOp,
+        value: Value<'static>,
+    },
+    In {
+        column_name: &'static str,
+        values: Vec<Value<'static>>,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    fn write_sql<'a>(
+        &'a self,
+        dialect: &dyn Dialect,
+        sql: &mut String,
+        params: &mut Vec<&'a Value<'static>>,
+    ) {
+        match self {
+            // `= NULL` is never true, SQL has `IS NULL` instead.
+            Self::Compare {
+                column_name,
+                op: op @ (CompareOp::Eq | CompareOp::Ne),
+                value: Value::Null,
+            } => {
+                let not = if *op == CompareOp::Ne { " NOT" } else { "" };
+                let column = dialect.quote_identifier(column_name);
+                write!(sql, "{} IS{} NULL", column, not).unwrap();
+            }
+            Self::Compare {
+                column_name,
+                op,
+                value,
+            } => {
+                params.push(value);
+                write!(
+                    sql,
+                    "{} {} {}",
+                    dialect.quote_identifier(column_name),
+                    op.as_sql(),
+                    dialect.placeholder(params.len()),
+                )
+                .unwrap();
+            }
+            Self::In { values, .. } if values.is_empty() => {
+                // Nothing is in an empty list, but `IN ()` is not valid SQL.
+                sql.push_str("1 = 0");
+            }
+            Self::In {
+                column_name,
+                values,
+            } => {
+                write!(sql, "{} IN (", dialect.quote_identifier(column_name)).unwrap();
+                for (i, value) in values.iter().enumerate() {
+                    params.push(value);
+                    if i > 0 {
+                        sql.push_str(", ");
+                    }
+                    sql.push_str(&dialect.placeholder(params.len()));
+                }
+                sql.push(')');
+            }
+            Self::And(left, right) => Self::write_binary(dialect, sql, params, left, "AND", right),
+            Self::Or(left, right) => Self::write_binary(dialect, sql, params, left, "OR", right),
+            Self::Not(inner) => {
+                sql.push_str("NOT (");
+                inner.write_sql(dialect, sql, params);
+                sql.push(')');
+            }
+        }
+    }
+
+    fn write_binary<'a>(
+        dialect: &dyn Dialect,
+        sql: &mut String,
+        params: &mut Vec<&'a Value<'static>>,
+        left: &'a Expr,
+        op: &str,
+        right: &'a Expr,
+    ) {
+        sql.push('(');
+        left.write_sql(dialect, sql, params);
+        write!(sql, ") {} (", op).unwrap();
+        right.write_sql(dialect, sql, params);
+        sql.push(')');
+    }
+
+    /// Evaluates the expression like SQL does: `None` stands for NULL, i.e.
+    /// an unknown result of a comparison with NULL.
+    fn eval<'v>(&self, column: &impl Fn(&str) -> &'v Value<'v>) -> Option<bool> {
+        match self {
+            Self::Compare {
+                column_name,
+                op,
+                value: Value::Null,
+            } => {
+                let is_null = matches!(column(column_name), Value::Null);
+                Some(if *op == CompareOp::Ne {
+                    !is_null
+                } else {
+                    is_null
+                })
+            }
+            Self::Compare {
+                column_name,
+                op,
+                value,
+            } => compare_values(column(column_name), value).map(|ord| op.holds(ord)),
+            Self::In {
+                column_name,
+                values,
+            } => {
+                let lhs = column(column_name);
+                let mut result = Some(false);
+                for value in values {
+                    match compare_values(lhs, value) {
+                        Some(Ordering::Equal) => return Some(true),
+                        Some(_) => {}
+                        None => result = None,
+                    }
+                }
+                result
+            }
+            Self::And(left, right) => match (left.eval(column), right.eval(column)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Self::Or(left, right) => match (left.eval(column), right.eval(column)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Self::Not(inner) => inner.eval(column).map(|x| !x),
+        }
+    }
+}
+
+/// Compares two values of the same type; NULL is not comparable to anything.
+fn compare_values(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bytes(a), Value::Bytes(b)) => Some(a.cmp(b)),
+        (Value::Int64(a), Value::Int64(b)) => Some(a.cmp(b)),
+        (Value::Float64(a), Value::Float64(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Ref(a), Value::Ref(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// The order of `ORDER BY`: NULLs go first, as in SQLite.
+fn order_values(lhs: &Value, rhs: &Value) -> Ordering {
+    match (lhs, rhs) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        _ => compare_values(lhs, rhs).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// A condition on the objects of type `T`.
+pub struct Predicate<T> {
+    expr: Expr,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Predicate<T> {
+    fn new(expr: Expr) -> Self {
+        Self {
+            expr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn and(self, other: Predicate<T>) -> Self {
+        Self::new(Expr::And(Box::new(self.expr), Box::new(other.expr)))
+    }
+
+    pub fn or(self, other: Predicate<T>) -> Self {
+        Self::new(Expr::Or(Box::new(self.expr), Box::new(other.expr)))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Self::new(Expr::Not(Box::new(self.expr)))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+/// Untyped part of `Select` that is passed to the storage.
+#[derive(Default)]
+pub struct Query {
+    filter: Option<Expr>,
+    order_by: Vec<(&'static str, Direction)>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+}
+
+impl Query {
+    /// Returns the clauses that follow `SELECT ... FROM table` and the values of
+    /// their placeholders. Values are never inlined into the SQL.
+    ///
+    /// The rows are ordered by `id` after the `order_by` columns, so that the
+    /// order is always deterministic.
+    pub fn to_sql(&self, dialect: &dyn Dialect) -> (String, Vec<&Value<'static>>) {
+        let mut sql = String::new();
+        let mut params = vec![];
+
+        if let Some(filter) = &self.filter {
+            sql.push_str(" WHERE ");
+            filter.write_sql(dialect, &mut sql, &mut params);
+        }
+
+        sql.push_str(" ORDER BY ");
+        for (column_name, direction) in self.order_by.iter() {
+            write!(
+                sql,
+                "{} {}, ",
+                dialect.quote_identifier(column_name),
+                match direction {
+                    Direction::Asc => "ASC",
+                    Direction::Desc => "DESC",
+                }
+            )
+            .unwrap();
+        }
+        sql.push_str("id ASC");
+
+        // OFFSET is allowed only after LIMIT.
+        match (self.limit, self.offset) {
+            (Some(limit), _) => write!(sql, " LIMIT {}", limit).unwrap(),
+            (None, Some(_)) => write!(sql, " LIMIT {}", dialect.no_limit()).unwrap(),
+            (None, None) => {}
+        }
+        if let Some(offset) = self.offset {
+            write!(sql, " OFFSET {}", offset).unwrap();
+        }
+
+        (sql, params)
+    }
+
+    /// Runs the query over the rows of a table for the storages without SQL.
+    /// `columns` are the names of the row values, in the same order.
+    pub fn apply<'r>(
+        &self,
+        columns: &[&str],
+        rows: impl IntoIterator<Item = (ObjectId, Row<'r>)>,
+    ) -> Vec<(ObjectId, Row<'r>)> {
+        let index = |name: &str| {
+            columns
+                .iter()
+                .position(|column| *column == name)
+                .unwrap_or_else(|| panic!("no such column: {}", name))
+        };
+
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .filter(|(_, row)| match &self.filter {
+                Some(filter) => filter.eval(&|name| &row[index(name)]) == Some(true),
+                None => true,
+            })
+            .collect();
+
+        rows.sort_by(|(lhs_id, lhs), (rhs_id, rhs)| {
+            self.order_by
+                .iter()
+                .map(|(column_name, direction)| {
+                    let i = index(column_name);
+                    let ord = order_values(&lhs[i], &rhs[i]);
+                    match direction {
+                        Direction::Asc => ord,
+                        Direction::Desc => ord.reverse(),
+                    }
+                })
+                .find(|ord| ord.is_ne())
+                .unwrap_or_else(|| lhs_id.cmp(rhs_id))
+        });
+
+        rows.into_iter()
+            .skip(self.offset.unwrap_or(0) as usize)
+            .take(self.limit.map_or(usize::MAX, |limit| limit as usize))
+            .collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Query builder returned by `Transaction::select`.
+pub struct Select<'t, 'a, T> {
+    tx: &'t Transaction<'a>,
+    query: Query,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'t, 'a, T: Object> Select<'t, 'a, T> {
+    pub(crate) fn new(tx: &'t Transaction<'a>) -> Self {
+        Self {
+            tx,
+            query: Query::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Several filters must hold together.
+    pub fn filter(mut self, predicate: Predicate<T>) -> Self {
+        self.query.filter = Some(match self.query.filter.take() {
+            Some(filter) => Expr::And(Box::new(filter), Box::new(predicate.expr)),
+            None => predicate.expr,
+        });
+        self
+    }
+
+    pub fn order_by<V: Field>(mut self, column: Column<T, V>) -> Self {
+        self.query
+            .order_by
+            .push((column.column_name(), Direction::Asc));
+        self
+    }
+
+    pub fn order_by_desc<V: Field>(mut self, column: Column<T, V>) -> Self {
+        self.query
+            .order_by
+            .push((column.column_name(), Direction::Desc));
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.query.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.query.offset = Some(offset);
+        self
+    }
+
+    pub fn fetch(self) -> Result<Vec<Tx<'t, T>>> {
+        self.tx.fetch(&self.query)
+    }
+}
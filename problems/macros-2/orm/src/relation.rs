@@ -0,0 +1,125 @@
+#![forbid(unsafe_code)]
+use crate::{
+    dialect::Dialect,
+    object::Object,
+    query::Column,
+    transaction::{Transaction, Tx},
+    ObjectId, Result,
+};
+use std::{any::Any, fmt, marker::PhantomData};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A reference to an object of type `T`, stored as a foreign key column.
+///
+/// The referenced object is not loaded until `Ref::get` is called.
+pub struct Ref<T> {
+    id: ObjectId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Ref<T> {
+    pub fn new(id: ObjectId) -> Self {
+        Self {
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.id
+    }
+}
+
+impl<T: Object> Ref<T> {
+    /// Loads the referenced object through the transaction cache.
+    pub fn get<'t>(&self, tx: &'t Transaction<'_>) -> Result<Tx<'t, T>> {
+        tx.get(self.id)
+    }
+}
+
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ref<T> {}
+
+impl<T> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Ref<T> {}
+
+impl<T> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ref({:?})", self.id)
+    }
+}
+
+impl<T> From<ObjectId> for Ref<T> {
+    fn from(id: ObjectId) -> Self {
+        Self::new(id)
+    }
+}
+
+impl<T: Any> From<&Tx<'_, T>> for Ref<T> {
+    fn from(obj: &Tx<'_, T>) -> Self {
+        Self::new(obj.id())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What happens to the referencing objects when the referenced one is deleted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnDelete {
+    /// The transaction fails to commit while the references exist.
+    #[default]
+    Restrict,
+    /// The referencing objects are deleted as well.
+    Cascade,
+}
+
+/// The target of a `Ref<T>` column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForeignKey {
+    pub table_name: &'static str,
+    pub on_delete: OnDelete,
+}
+
+impl ForeignKey {
+    /// The constraint to append to the column definition, e.g.
+    /// `REFERENCES "Author"(id) ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED`.
+    pub fn to_sql(&self, dialect: &dyn Dialect) -> String {
+        format!(
+            "REFERENCES {}(id) ON DELETE {} DEFERRABLE INITIALLY DEFERRED",
+            dialect.quote_identifier(self.table_name),
+            match self.on_delete {
+                OnDelete::Restrict => "NO ACTION",
+                OnDelete::Cascade => "CASCADE",
+            },
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Objects of type `U` referencing an object of type `T` through one of their
+/// `Ref<T>` fields.
+pub struct HasMany<T, U> {
+    column: Column<U, Ref<T>>,
+}
+
+impl<T: Object, U: Object> HasMany<T, U> {
+    pub fn new(column: Column<U, Ref<T>>) -> Self {
+        Self { column }
+    }
+
+    pub fn fetch<'t>(&self, tx: &'t Transaction<'_>, owner: &Tx<'_, T>) -> Result<Vec<Tx<'t, U>>> {
+        tx.select::<U>().filter(self.column.eq(owner)).fetch()
+    }
+}
@@ -1,34 +1,90 @@
 #![forbid(unsafe_code)]
 use crate::{
+    audit::{self, AuditRecord, AUDIT_TABLE},
     data::{DataType, Value},
-    error::{Error, ErrorCtx, ErrorWithCtx, Result, UnexpectedTypeError},
+    dialect::{Dialect, Sqlite},
+    error::{
+        Error, ErrorCtx, ErrorWithCtx, ReferentialIntegrityError, Result, UnexpectedTypeError,
+    },
+    migration::{ColumnInfo, MigrationStep},
     object::Schema,
+    query::Query,
     ObjectId,
 };
 use rusqlite::{types::FromSqlError, ToSql};
 use std::{borrow::Cow, fmt::Write};
 
+pub(crate) mod memory;
+#[cfg(feature = "postgres")]
+pub(crate) mod pg;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub type Row<'a> = Vec<Value<'a>>;
 pub type RowSlice<'a> = [Value<'a>];
 
+/// The number of bound parameters a statement may have. SQLite3 before 3.32
+/// doesn't allow more.
+pub(crate) const MAX_PARAMS: usize = 999;
+
+/// How many rows of `columns` values fit into one statement.
+pub(crate) fn batch_len(columns: usize) -> usize {
+    MAX_PARAMS / columns.max(1)
+}
+
+/// The position of the version column and the version of the object in `row`,
+/// if the schema has a version column.
+pub(crate) fn row_version(schema: &Schema, row: &RowSlice) -> Option<(usize, i64)> {
+    schema.version_column().map(|index| match row[index] {
+        Value::Int64(version) => (index, version),
+        ref value => panic!("version must be an integer, got {:?}", value),
+    })
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub(crate) trait StorageTransaction {
+    fn dialect(&self) -> &dyn Dialect;
+
     fn table_exists(&self, table: &str) -> Result<bool>;
     fn create_table(&self, schema: &Schema) -> Result<()>;
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>>;
+    fn migrate_table(&self, table: &str, steps: &[MigrationStep]) -> Result<()>;
 
-    fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId>;
-    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
+    /// Inserts the rows with as few statements as possible (see `batch_len`)
+    /// and returns their ids in the same order.
+    fn insert_rows(&self, schema: &Schema, rows: &[&RowSlice]) -> Result<Vec<ObjectId>>;
+    /// Updates the rows with a prepared statement. The rows that don't exist
+    /// are skipped.
+    ///
+    /// If the schema has a version column, a row is updated only if it
+    /// still has the version from `row`, and the version is incremented.
+    /// Otherwise fails with `Error::StaleObject`.
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, &RowSlice)]) -> Result<()>;
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>>;
-    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    /// Deletes the rows with as few statements as possible, e.g. with
+    /// `WHERE id IN (...)`.
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()>;
+
+    /// Fails with `Error::ReferentialIntegrity` if some row references
+    /// a missing one. Must be called right before `commit`.
+    fn check_foreign_keys(&self) -> Result<()>;
+
+    /// Appends the records to `audit::AUDIT_TABLE`, creating it if needed.
+    fn append_audit_log(&self, records: &[AuditRecord]) -> Result<()>;
+    /// All the records in the order they were appended.
+    fn audit_log(&self) -> Result<Vec<AuditRecord>>;
 
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
 }
 
 impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
+    fn dialect(&self) -> &dyn Dialect {
+        &Sqlite
+    }
+
     fn table_exists(&self, table: &str) -> Result<bool> {
         // TODO: your code goes here.
         unimplemented!()
@@ -39,12 +95,30 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
         unimplemented!()
     }
 
-    fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let mut stmt = self.prepare("SELECT name, type FROM pragma_table_info(?)")?;
+        let columns = stmt.query_map([table], |row| {
+            Ok(ColumnInfo {
+                name: row.get(0)?,
+                sql_type: row.get(1)?,
+            })
+        })?;
+        Ok(columns.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn migrate_table(&self, table: &str, steps: &[MigrationStep]) -> Result<()> {
+        for step in steps {
+            self.execute(&step.to_sql(table, &Sqlite), [])?;
+        }
+        Ok(())
+    }
+
+    fn insert_rows(&self, schema: &Schema, rows: &[&RowSlice]) -> Result<Vec<ObjectId>> {
         // TODO: your code goes here.
         unimplemented!()
     }
 
-    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, &RowSlice)]) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
     }
@@ -54,11 +128,78 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
         unimplemented!()
     }
 
-    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
     }
 
+    fn check_foreign_keys(&self) -> Result<()> {
+        let mut stmt = self.prepare("PRAGMA foreign_key_check")?;
+        let mut rows = stmt.query([])?;
+        match rows.next()? {
+            Some(row) => Err(Error::ReferentialIntegrity(Box::new(
+                ReferentialIntegrityError {
+                    object_id: row.get::<_, i64>(1)?.into(),
+                    table_name: row.get(0)?,
+                    referenced_table_name: row.get(2)?,
+                },
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn append_audit_log(&self, records: &[AuditRecord]) -> Result<()> {
+        self.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+                id INTEGER PRIMARY KEY AUTOINCREMENT, table_name TEXT, object_id BIGINT, \
+                operation TEXT, changes TEXT, committed_at TEXT)",
+            AUDIT_TABLE
+        ))?;
+        let mut stmt = self.prepare_cached(&format!(
+            "INSERT INTO {} (table_name, object_id, operation, changes, committed_at) \
+            VALUES (?, ?, ?, ?, ?)",
+            AUDIT_TABLE
+        ))?;
+        for record in records {
+            stmt.execute(rusqlite::params![
+                record.table_name,
+                record.object_id.into_i64(),
+                record.operation.as_str(),
+                record.changes,
+                record.committed_at.to_rfc3339(),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditRecord>> {
+        let mut stmt = self.prepare("SELECT 1 FROM sqlite_master WHERE name = ?")?;
+        if !stmt.exists([AUDIT_TABLE])? {
+            return Ok(vec![]);
+        }
+        let mut stmt = self.prepare(&format!(
+            "SELECT table_name, object_id, operation, changes, committed_at FROM {} ORDER BY id",
+            AUDIT_TABLE
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut records = vec![];
+        while let Some(row) = rows.next()? {
+            records.push(audit::parse_record(
+                row.get(0)?,
+                row.get(1)?,
+                &row.get::<_, String>(2)?,
+                row.get(3)?,
+                &row.get::<_, String>(4)?,
+            )?);
+        }
+        Ok(records)
+    }
+
     fn commit(&self) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
@@ -0,0 +1,399 @@
+#![forbid(unsafe_code)]
+use crate::{
+    audit::AuditRecord,
+    connection::StorageConnection,
+    data::{DataType, Value},
+    dialect::{Dialect, Sqlite},
+    error::{
+        Error, MissingColumnError, NotFoundError, ReferentialIntegrityError, Result,
+        StaleObjectError, UniqueViolationError,
+    },
+    migration::{self, ColumnDef, ColumnInfo, IndexDef, MigrationStep},
+    object::Schema,
+    query::Query,
+    relation::OnDelete,
+    storage::{row_version, Row, RowSlice, StorageTransaction},
+    ObjectId,
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+struct Table {
+    columns: Vec<ColumnDef>,
+    indexes: Vec<IndexDef>,
+    rows: BTreeMap<ObjectId, Row<'static>>,
+    next_id: i64,
+}
+
+impl Table {
+    /// Positions of the schema columns in the rows of the table.
+    fn positions(&self, schema: &Schema) -> Result<Vec<usize>> {
+        schema
+            .columns()
+            .iter()
+            .map(|column| {
+                self.columns
+                    .iter()
+                    .position(|c| c.column_name == column.column_name)
+                    .ok_or_else(|| {
+                        Error::MissingColumn(Box::new(MissingColumnError {
+                            type_name: schema.type_name(),
+                            attr_name: column.attr_name,
+                            table_name: schema.table_name(),
+                            column_name: column.column_name,
+                        }))
+                    })
+            })
+            .collect()
+    }
+
+    fn position(&self, column_name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|c| c.column_name == column_name)
+    }
+
+    fn write_row(&mut self, id: ObjectId, schema: &Schema, values: &RowSlice) -> Result<()> {
+        let positions = self.positions(schema)?;
+        let mut row = match self.rows.get(&id) {
+            Some(row) => row.clone(),
+            None => self
+                .columns
+                .iter()
+                .map(|column| match column.nullable {
+                    true => Value::Null,
+                    false => migration::default_value(column.data_type),
+                })
+                .collect(),
+        };
+        for (&pos, value) in positions.iter().zip(values) {
+            row[pos] = value.clone().into_owned();
+        }
+        self.check_unique(id, schema, &row)?;
+        self.rows.insert(id, row);
+        Ok(())
+    }
+
+    /// Fails if another row has the same values in the columns of a unique
+    /// index. As in SQL, NULLs are never equal.
+    fn check_unique(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+        for index in self.indexes.iter().filter(|index| index.unique) {
+            let positions: Option<Vec<_>> = index
+                .columns
+                .iter()
+                .map(|name| self.position(name))
+                .collect();
+            let positions = match positions {
+                Some(positions) => positions,
+                // Some of the columns were dropped by a migration.
+                None => continue,
+            };
+            if positions.iter().any(|&pos| row[pos] == Value::Null) {
+                continue;
+            }
+            let duplicate = self.rows.iter().any(|(&other_id, other)| {
+                other_id != id && positions.iter().all(|&pos| other[pos] == row[pos])
+            });
+            if duplicate {
+                return Err(Error::UniqueViolation(Box::new(UniqueViolationError {
+                    table_name: schema.table_name(),
+                    column_names: index.columns,
+                })));
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the missing rows, checks the version if there is one.
+    fn update_row(&mut self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+        let stored = match self.rows.get(&id) {
+            Some(stored) => stored,
+            None => return Ok(()),
+        };
+        let (index, version) = match row_version(schema, row) {
+            Some(version) => version,
+            None => return self.write_row(id, schema, row),
+        };
+        if stored[self.positions(schema)?[index]] != Value::Int64(version) {
+            return Err(Error::StaleObject(Box::new(StaleObjectError {
+                object_id: id,
+                type_name: schema.type_name(),
+                version,
+            })));
+        }
+        let mut row = row.to_vec();
+        row[index] = Value::Int64(version + 1);
+        self.write_row(id, schema, &row)
+    }
+
+    fn read_row(&self, row: &RowSlice<'static>, positions: &[usize]) -> Row<'static> {
+        positions.iter().map(|&pos| row[pos].clone()).collect()
+    }
+}
+
+type Tables = HashMap<String, Table>;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Keeps the tables in memory, without any SQL. Meant for tests.
+///
+/// A transaction works on a copy of all tables, which replaces the tables
+/// on commit.
+#[derive(Default)]
+pub(crate) struct MemoryStorage {
+    tables: Tables,
+    audit_log: Vec<AuditRecord>,
+}
+
+impl StorageConnection for MemoryStorage {
+    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
+        let tables = RefCell::new(self.tables.clone());
+        Ok(Box::new(MemoryTransaction {
+            storage: RefCell::new(self),
+            tables,
+            audit_log: RefCell::default(),
+        }))
+    }
+}
+
+struct MemoryTransaction<'a> {
+    storage: RefCell<&'a mut MemoryStorage>,
+    tables: RefCell<Tables>,
+    /// The records appended by this transaction.
+    audit_log: RefCell<Vec<AuditRecord>>,
+}
+
+impl MemoryTransaction<'_> {
+    fn with_table<R>(&self, schema: &Schema, f: impl FnOnce(&mut Table) -> Result<R>) -> Result<R> {
+        let mut tables = self.tables.borrow_mut();
+        let table = tables
+            .get_mut(schema.table_name())
+            .unwrap_or_else(|| panic!("table {} doesn't exist", schema.table_name()));
+        f(table)
+    }
+}
+
+fn not_found(id: ObjectId, schema: &Schema) -> Error {
+    Error::NotFound(Box::new(NotFoundError {
+        object_id: id,
+        type_name: schema.type_name(),
+    }))
+}
+
+/// Deletes the row together with the rows referencing it with `OnDelete::Cascade`.
+fn delete_cascade(tables: &mut Tables, table_name: &str, id: ObjectId) {
+    let mut referencing = vec![];
+    for (name, table) in tables.iter() {
+        for (pos, column) in table.columns.iter().enumerate() {
+            match column.references {
+                Some(fk) if fk.table_name == table_name && fk.on_delete == OnDelete::Cascade => {
+                    referencing.extend(
+                        table
+                            .rows
+                            .iter()
+                            .filter(|(_, row)| row[pos] == Value::Ref(id))
+                            .map(|(&child_id, _)| (name.clone(), child_id)),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(table) = tables.get_mut(table_name) {
+        table.rows.remove(&id);
+    }
+    for (name, child_id) in referencing {
+        delete_cascade(tables, &name, child_id);
+    }
+}
+
+impl StorageTransaction for MemoryTransaction<'_> {
+    fn dialect(&self) -> &dyn Dialect {
+        &Sqlite
+    }
+
+    fn table_exists(&self, table: &str) -> Result<bool> {
+        Ok(self.tables.borrow().contains_key(table))
+    }
+
+    fn create_table(&self, schema: &Schema) -> Result<()> {
+        self.tables.borrow_mut().insert(
+            schema.table_name().to_string(),
+            Table {
+                columns: schema.columns().to_vec(),
+                indexes: schema.indexes().to_vec(),
+                rows: BTreeMap::new(),
+                next_id: 1,
+            },
+        );
+        Ok(())
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let tables = self.tables.borrow();
+        let id = ColumnInfo {
+            name: "id".to_string(),
+            sql_type: Sqlite.sql_type(DataType::Ref).to_string(),
+        };
+        let columns = tables[table].columns.iter().map(|column| ColumnInfo {
+            name: column.column_name.to_string(),
+            sql_type: Sqlite.sql_type(column.data_type).to_string(),
+        });
+        Ok(std::iter::once(id).chain(columns).collect())
+    }
+
+    fn migrate_table(&self, table: &str, steps: &[MigrationStep]) -> Result<()> {
+        let mut tables = self.tables.borrow_mut();
+        let table = tables.get_mut(table).unwrap();
+        for step in steps {
+            match step {
+                &MigrationStep::AddColumn {
+                    column_name,
+                    data_type,
+                    nullable,
+                    references,
+                } => {
+                    table.columns.push(ColumnDef {
+                        attr_name: column_name,
+                        column_name,
+                        data_type,
+                        nullable,
+                        references,
+                        renamed_from: &[],
+                    });
+                    let value = match nullable {
+                        true => Value::Null,
+                        false => migration::default_value(data_type),
+                    };
+                    for row in table.rows.values_mut() {
+                        row.push(value.clone());
+                    }
+                }
+                MigrationStep::RenameColumn { from, to } => {
+                    let pos = table.position(from).unwrap();
+                    table.columns[pos].column_name = *to;
+                }
+                MigrationStep::DropColumn { column_name } => {
+                    let pos = table.position(column_name).unwrap();
+                    table.columns.remove(pos);
+                    for row in table.rows.values_mut() {
+                        row.remove(pos);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_rows(&self, schema: &Schema, rows: &[&RowSlice]) -> Result<Vec<ObjectId>> {
+        self.with_table(schema, |table| {
+            rows.iter()
+                .map(|row| {
+                    let id = ObjectId::from(table.next_id);
+                    table.write_row(id, schema, row)?;
+                    table.next_id += 1;
+                    Ok(id)
+                })
+                .collect()
+        })
+    }
+
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, &RowSlice)]) -> Result<()> {
+        self.with_table(schema, |table| {
+            for &(id, row) in rows {
+                table.update_row(id, schema, row)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
+        self.with_table(schema, |table| {
+            let positions = table.positions(schema)?;
+            let row = table.rows.get(&id).ok_or_else(|| not_found(id, schema))?;
+            Ok(table.read_row(row, &positions))
+        })
+    }
+
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        self.with_table(schema, |table| {
+            let positions = table.positions(schema)?;
+            let columns: Vec<_> = schema.columns().iter().map(|c| c.column_name).collect();
+            let rows = table
+                .rows
+                .iter()
+                .map(|(&id, row)| (id, table.read_row(row, &positions)));
+            Ok(query.apply(&columns, rows))
+        })
+    }
+
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()> {
+        let mut tables = self.tables.borrow_mut();
+        for &id in ids {
+            delete_cascade(&mut tables, schema.table_name(), id);
+        }
+        Ok(())
+    }
+
+    fn check_foreign_keys(&self) -> Result<()> {
+        let tables = self.tables.borrow();
+        let mut names: Vec<_> = tables.keys().collect();
+        names.sort();
+        for name in names {
+            let table = &tables[name];
+            for (pos, column) in table.columns.iter().enumerate() {
+                let fk = match column.references {
+                    Some(fk) => fk,
+                    None => continue,
+                };
+                let referenced = tables.get(fk.table_name);
+                for (&id, row) in table.rows.iter() {
+                    let target = match row[pos] {
+                        Value::Ref(target) => target,
+                        _ => continue,
+                    };
+                    if !referenced.is_some_and(|t| t.rows.contains_key(&target)) {
+                        return Err(Error::ReferentialIntegrity(Box::new(
+                            ReferentialIntegrityError {
+                                object_id: id,
+                                table_name: name.clone(),
+                                referenced_table_name: fk.table_name.to_string(),
+                            },
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn append_audit_log(&self, records: &[AuditRecord]) -> Result<()> {
+        self.audit_log.borrow_mut().extend_from_slice(records);
+        Ok(())
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditRecord>> {
+        let mut records = self.storage.borrow().audit_log.clone();
+        records.extend_from_slice(&self.audit_log.borrow());
+        Ok(records)
+    }
+
+    fn commit(&self) -> Result<()> {
+        let mut storage = self.storage.borrow_mut();
+        storage.tables = self.tables.borrow().clone();
+        storage.audit_log.append(&mut self.audit_log.borrow_mut());
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        *self.tables.borrow_mut() = self.storage.borrow().tables.clone();
+        self.audit_log.borrow_mut().clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,529 @@
+#![forbid(unsafe_code)]
+use crate::{
+    audit::{self, AuditRecord, AUDIT_TABLE},
+    connection::StorageConnection,
+    data::{DataType, Value},
+    dialect::{Dialect, Postgres},
+    error::{
+        Error, MissingColumnError, NotFoundError, ReferentialIntegrityError, Result,
+        StaleObjectError, UniqueViolationError,
+    },
+    migration::{ColumnInfo, MigrationStep},
+    object::Schema,
+    query::Query,
+    storage::{batch_len, row_version, Row, RowSlice, StorageTransaction},
+    ObjectId,
+};
+use ::postgres::{error::SqlState, types::ToSql, Client, Statement};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Write,
+    iter,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl StorageConnection for Client {
+    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
+        self.batch_execute("BEGIN").map_err(storage_error)?;
+        Ok(Box::new(PgTransaction {
+            client: RefCell::new(self),
+            statements: RefCell::default(),
+            finished: Cell::new(false),
+        }))
+    }
+}
+
+/// Transaction is controlled with plain `BEGIN`, `COMMIT` and `ROLLBACK`,
+/// since `postgres::Transaction::commit` consumes the transaction.
+struct PgTransaction<'a> {
+    client: RefCell<&'a mut Client>,
+    /// Prepared statements by their SQL.
+    statements: RefCell<HashMap<String, Statement>>,
+    finished: Cell<bool>,
+}
+
+impl Drop for PgTransaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished.get() {
+            let _ = self.client.get_mut().batch_execute("ROLLBACK");
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn storage_error(err: ::postgres::Error) -> Error {
+    match err.code() {
+        Some(&SqlState::T_R_SERIALIZATION_FAILURE)
+        | Some(&SqlState::T_R_DEADLOCK_DETECTED)
+        | Some(&SqlState::LOCK_NOT_AVAILABLE) => Error::LockConflict,
+        _ => Error::Storage(Box::new(err)),
+    }
+}
+
+/// Like `storage_error`, but knows which object the statement was about.
+fn row_error(err: ::postgres::Error, schema: &Schema) -> Error {
+    if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        let constraint = err.as_db_error().and_then(|e| e.constraint());
+        let index = schema
+            .indexes()
+            .iter()
+            .find(|index| Some(index.name(schema.table_name()).as_str()) == constraint);
+        if let Some(index) = index {
+            return Error::UniqueViolation(Box::new(UniqueViolationError {
+                table_name: schema.table_name(),
+                column_names: index.columns,
+            }));
+        }
+    }
+    if err.code() == Some(&SqlState::UNDEFINED_COLUMN) {
+        let message = err.as_db_error().map(|e| e.message()).unwrap_or_default();
+        let column = schema
+            .columns()
+            .iter()
+            .find(|c| message.contains(&format!("\"{}\"", c.column_name)));
+        if let Some(column) = column {
+            return Error::MissingColumn(Box::new(MissingColumnError {
+                type_name: schema.type_name(),
+                attr_name: column.attr_name,
+                table_name: schema.table_name(),
+                column_name: column.column_name,
+            }));
+        }
+    }
+    storage_error(err)
+}
+
+fn to_sql(value: &Value, data_type: DataType) -> Box<dyn ToSql + Sync> {
+    match value {
+        Value::String(s) => Box::new(s.to_string()),
+        Value::Bytes(b) => Box::new(b.to_vec()),
+        Value::Int64(x) => Box::new(*x),
+        Value::Float64(x) => Box::new(*x),
+        Value::Bool(x) => Box::new(*x),
+        Value::Ref(id) => Box::new(id.into_i64()),
+        // NULL still must have the type of the column.
+        Value::Null => match data_type {
+            DataType::String => Box::new(None::<String>),
+            DataType::Bytes => Box::new(None::<Vec<u8>>),
+            DataType::Int64 | DataType::Ref => Box::new(None::<i64>),
+            DataType::Float64 => Box::new(None::<f64>),
+            DataType::Bool => Box::new(None::<bool>),
+        },
+    }
+}
+
+fn from_sql(row: &::postgres::Row, idx: usize, data_type: DataType) -> Result<Value<'static>> {
+    fn get<'r, T: ::postgres::types::FromSql<'r>>(
+        row: &'r ::postgres::Row,
+        idx: usize,
+    ) -> Result<Option<T>> {
+        row.try_get(idx).map_err(storage_error)
+    }
+
+    let value = match data_type {
+        DataType::String => get::<String>(row, idx)?.map(|s| Value::String(Cow::Owned(s))),
+        DataType::Bytes => get::<Vec<u8>>(row, idx)?.map(|b| Value::Bytes(Cow::Owned(b))),
+        DataType::Int64 => get::<i64>(row, idx)?.map(Value::Int64),
+        DataType::Float64 => get::<f64>(row, idx)?.map(Value::Float64),
+        DataType::Bool => get::<bool>(row, idx)?.map(Value::Bool),
+        DataType::Ref => get::<i64>(row, idx)?.map(|id| Value::Ref(id.into())),
+    };
+    Ok(value.unwrap_or(Value::Null))
+}
+
+impl PgTransaction<'_> {
+    fn prepare(&self, sql: &str) -> std::result::Result<Statement, ::postgres::Error> {
+        if let Some(statement) = self.statements.borrow().get(sql) {
+            return Ok(statement.clone());
+        }
+        let statement = self.client.borrow_mut().prepare(sql)?;
+        self.statements
+            .borrow_mut()
+            .insert(sql.to_string(), statement.clone());
+        Ok(statement)
+    }
+
+    fn execute(
+        &self,
+        sql: &str,
+        params: &[Box<dyn ToSql + Sync>],
+    ) -> std::result::Result<u64, ::postgres::Error> {
+        let statement = self.prepare(sql)?;
+        let params: Vec<_> = params.iter().map(|p| p.as_ref()).collect();
+        self.client.borrow_mut().execute(&statement, &params)
+    }
+
+    fn query(
+        &self,
+        sql: &str,
+        params: &[Box<dyn ToSql + Sync>],
+    ) -> std::result::Result<Vec<::postgres::Row>, ::postgres::Error> {
+        let statement = self.prepare(sql)?;
+        let params: Vec<_> = params.iter().map(|p| p.as_ref()).collect();
+        self.client.borrow_mut().query(&statement, &params)
+    }
+
+    fn row_params(schema: &Schema, row: &RowSlice) -> Vec<Box<dyn ToSql + Sync>> {
+        schema
+            .columns()
+            .iter()
+            .zip(row)
+            .map(|(column, value)| to_sql(value, column.data_type))
+            .collect()
+    }
+
+    /// `SELECT id, col1, col2 FROM table`.
+    fn select_sql(schema: &Schema) -> String {
+        let mut sql = "SELECT id".to_string();
+        for column in schema.columns() {
+            sql.push_str(", ");
+            sql.push_str(&Postgres.quote_identifier(column.column_name));
+        }
+        sql.push_str(" FROM ");
+        sql.push_str(&Postgres.quote_identifier(schema.table_name()));
+        sql
+    }
+
+    fn read_row(schema: &Schema, row: &::postgres::Row) -> Result<(ObjectId, Row<'static>)> {
+        let id: i64 = row.try_get(0).map_err(storage_error)?;
+        let values = schema
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(i, column)| from_sql(row, i + 1, column.data_type))
+            .collect::<Result<_>>()?;
+        Ok((id.into(), values))
+    }
+
+    fn finish(&self, sql: &str) -> Result<()> {
+        self.finished.set(true);
+        self.client
+            .borrow_mut()
+            .batch_execute(sql)
+            .map_err(storage_error)
+    }
+}
+
+impl StorageTransaction for PgTransaction<'_> {
+    fn dialect(&self) -> &dyn Dialect {
+        &Postgres
+    }
+
+    fn table_exists(&self, table: &str) -> Result<bool> {
+        let sql = "SELECT 1 FROM information_schema.tables \
+            WHERE table_schema = current_schema() AND table_name = $1";
+        let rows = self
+            .query(sql, &[Box::new(table.to_string())])
+            .map_err(storage_error)?;
+        Ok(!rows.is_empty())
+    }
+
+    fn create_table(&self, schema: &Schema) -> Result<()> {
+        let mut sql = format!(
+            "CREATE TABLE {} ({}",
+            Postgres.quote_identifier(schema.table_name()),
+            Postgres.id_column(),
+        );
+        for column in schema.columns() {
+            sql.push_str(&format!(
+                ", {} {}",
+                Postgres.quote_identifier(column.column_name),
+                Postgres.sql_type(column.data_type),
+            ));
+            if let Some(fk) = column.references {
+                sql.push(' ');
+                sql.push_str(&fk.to_sql(&Postgres));
+            }
+        }
+        sql.push(')');
+        self.execute(&sql, &[]).map_err(storage_error)?;
+        for index in schema.indexes() {
+            let sql = index.to_sql(schema.table_name(), &Postgres);
+            self.execute(&sql, &[]).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let sql = "SELECT column_name, data_type FROM information_schema.columns \
+            WHERE table_schema = current_schema() AND table_name = $1 \
+            ORDER BY ordinal_position";
+        let rows = self
+            .query(sql, &[Box::new(table.to_string())])
+            .map_err(storage_error)?;
+        rows.iter()
+            .map(|row| {
+                Ok(ColumnInfo {
+                    name: row.try_get(0).map_err(storage_error)?,
+                    sql_type: row.try_get(1).map_err(storage_error)?,
+                })
+            })
+            .collect()
+    }
+
+    fn migrate_table(&self, table: &str, steps: &[MigrationStep]) -> Result<()> {
+        for step in steps {
+            self.execute(&step.to_sql(table, &Postgres), &[])
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn insert_rows(&self, schema: &Schema, rows: &[&RowSlice]) -> Result<Vec<ObjectId>> {
+        if rows.is_empty() {
+            return Ok(vec![]);
+        }
+        let table = Postgres.quote_identifier(schema.table_name());
+
+        // The ids are taken from the sequence first and inserted explicitly, since
+        // `RETURNING` doesn't guarantee the order of the returned rows.
+        let params: Vec<Box<dyn ToSql + Sync>> =
+            vec![Box::new(table.clone()), Box::new(rows.len() as i64)];
+        let ids = self
+            .query(
+                "SELECT nextval(pg_get_serial_sequence($1, 'id')) FROM generate_series(1, $2)",
+                &params,
+            )
+            .map_err(storage_error)?
+            .iter()
+            .map(|row| row.try_get::<_, i64>(0))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(storage_error)?;
+
+        let columns: Vec<_> = iter::once("id".to_string())
+            .chain(
+                schema
+                    .columns()
+                    .iter()
+                    .map(|c| Postgres.quote_identifier(c.column_name)),
+            )
+            .collect();
+        let rows: Vec<_> = ids.iter().zip(rows).collect();
+        for batch in rows.chunks(batch_len(columns.len())) {
+            let values: Vec<_> = (0..batch.len())
+                .map(|i| {
+                    let placeholders: Vec<_> = (1..=columns.len())
+                        .map(|j| Postgres.placeholder(i * columns.len() + j))
+                        .collect();
+                    format!("({})", placeholders.join(", "))
+                })
+                .collect();
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES {}",
+                table,
+                columns.join(", "),
+                values.join(", "),
+            );
+            let params: Vec<_> = batch
+                .iter()
+                .flat_map(|&(&id, row)| {
+                    iter::once(Box::new(id) as Box<dyn ToSql + Sync>)
+                        .chain(Self::row_params(schema, row))
+                })
+                .collect();
+            self.query(&sql, &params)
+                .map_err(|err| row_error(err, schema))?;
+        }
+        Ok(ids.into_iter().map(ObjectId::from).collect())
+    }
+
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, &RowSlice)]) -> Result<()> {
+        if schema.columns().is_empty() {
+            return Ok(());
+        }
+        let mut assignments: Vec<_> = schema
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                format!(
+                    "{} = {}",
+                    Postgres.quote_identifier(c.column_name),
+                    Postgres.placeholder(i + 1)
+                )
+            })
+            .collect();
+        let id_placeholder = Postgres.placeholder(schema.columns().len() + 1);
+        let mut condition = format!("id = {}", id_placeholder);
+        if let Some(index) = schema.version_column() {
+            // The parameter holds the loaded version.
+            let column = Postgres.quote_identifier(schema.columns()[index].column_name);
+            let placeholder = Postgres.placeholder(index + 1);
+            assignments[index] = format!("{} = {} + 1", column, placeholder);
+            write!(condition, " AND {} = {}", column, placeholder).unwrap();
+        }
+        let table = Postgres.quote_identifier(schema.table_name());
+        let sql = format!(
+            "UPDATE {} SET {} WHERE {}",
+            table,
+            assignments.join(", "),
+            condition,
+        );
+
+        for &(id, row) in rows {
+            let mut params = Self::row_params(schema, row);
+            params.push(Box::new(id.into_i64()));
+            let updated = self
+                .execute(&sql, &params)
+                .map_err(|err| row_error(err, schema))?;
+
+            if let (0, Some((_, version))) = (updated, row_version(schema, row)) {
+                // Either the row has another version, or it is deleted.
+                let sql = format!("SELECT 1 FROM {} WHERE id = $1", table);
+                let rows = self
+                    .query(&sql, &[Box::new(id.into_i64())])
+                    .map_err(storage_error)?;
+                if !rows.is_empty() {
+                    return Err(Error::StaleObject(Box::new(StaleObjectError {
+                        object_id: id,
+                        type_name: schema.type_name(),
+                        version,
+                    })));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
+        let sql = format!("{} WHERE id = $1", Self::select_sql(schema));
+        let rows = self
+            .query(&sql, &[Box::new(id.into_i64())])
+            .map_err(|err| row_error(err, schema))?;
+        match rows.first() {
+            Some(row) => Ok(Self::read_row(schema, row)?.1),
+            None => Err(Error::NotFound(Box::new(NotFoundError {
+                object_id: id,
+                type_name: schema.type_name(),
+            }))),
+        }
+    }
+
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let (clauses, values) = query.to_sql(&Postgres);
+        let sql = format!("{}{}", Self::select_sql(schema), clauses);
+        // Queries never bind NULL, so the type passed to `to_sql` is not used.
+        let params: Vec<Box<dyn ToSql + Sync>> = values
+            .into_iter()
+            .map(|value| to_sql(value, DataType::Int64))
+            .collect();
+        let rows = self
+            .query(&sql, &params)
+            .map_err(|err| row_error(err, schema))?;
+        rows.iter().map(|row| Self::read_row(schema, row)).collect()
+    }
+
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()> {
+        let sql = format!(
+            "DELETE FROM {} WHERE id = ANY($1)",
+            Postgres.quote_identifier(schema.table_name())
+        );
+        let ids: Vec<_> = ids.iter().map(|id| id.into_i64()).collect();
+        self.execute(&sql, &[Box::new(ids)])
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn check_foreign_keys(&self) -> Result<()> {
+        // The constraints are deferred until COMMIT, and the error it would
+        // fail with doesn't tell which object is broken. So look for it first.
+        let sql = "SELECT kcu.table_name, kcu.column_name, ccu.table_name \
+            FROM information_schema.referential_constraints rc \
+            JOIN information_schema.key_column_usage kcu \
+                ON kcu.constraint_schema = rc.constraint_schema \
+                AND kcu.constraint_name = rc.constraint_name \
+            JOIN information_schema.constraint_column_usage ccu \
+                ON ccu.constraint_schema = rc.unique_constraint_schema \
+                AND ccu.constraint_name = rc.unique_constraint_name \
+            WHERE rc.constraint_schema = current_schema() \
+            ORDER BY kcu.table_name, kcu.column_name";
+        let keys = self.query(sql, &[]).map_err(storage_error)?;
+        for key in keys.iter() {
+            let table_name: String = key.try_get(0).map_err(storage_error)?;
+            let column_name: String = key.try_get(1).map_err(storage_error)?;
+            let referenced_table_name: String = key.try_get(2).map_err(storage_error)?;
+
+            let sql = format!(
+                "SELECT c.id FROM {table} c LEFT JOIN {referenced} p ON c.{column} = p.id \
+                WHERE c.{column} IS NOT NULL AND p.id IS NULL ORDER BY c.id LIMIT 1",
+                table = Postgres.quote_identifier(&table_name),
+                referenced = Postgres.quote_identifier(&referenced_table_name),
+                column = Postgres.quote_identifier(&column_name),
+            );
+            let broken = self.query(&sql, &[]).map_err(storage_error)?;
+            if let Some(row) = broken.first() {
+                let id: i64 = row.try_get(0).map_err(storage_error)?;
+                return Err(Error::ReferentialIntegrity(Box::new(
+                    ReferentialIntegrityError {
+                        object_id: id.into(),
+                        table_name,
+                        referenced_table_name,
+                    },
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn append_audit_log(&self, records: &[AuditRecord]) -> Result<()> {
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+                id BIGSERIAL PRIMARY KEY, table_name text, object_id bigint, \
+                operation text, changes text, committed_at text)",
+            AUDIT_TABLE
+        );
+        self.execute(&sql, &[]).map_err(storage_error)?;
+        let sql = format!(
+            "INSERT INTO {} (table_name, object_id, operation, changes, committed_at) \
+            VALUES ($1, $2, $3, $4, $5)",
+            AUDIT_TABLE
+        );
+        for record in records {
+            let params: [Box<dyn ToSql + Sync>; 5] = [
+                Box::new(record.table_name.clone()),
+                Box::new(record.object_id.into_i64()),
+                Box::new(record.operation.as_str()),
+                Box::new(record.changes.clone()),
+                Box::new(record.committed_at.to_rfc3339()),
+            ];
+            self.execute(&sql, &params).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditRecord>> {
+        if !self.table_exists(AUDIT_TABLE)? {
+            return Ok(vec![]);
+        }
+        let sql = format!(
+            "SELECT table_name, object_id, operation, changes, committed_at FROM {} ORDER BY id",
+            AUDIT_TABLE
+        );
+        let rows = self.query(&sql, &[]).map_err(storage_error)?;
+        rows.iter()
+            .map(|row| {
+                let operation: String = row.try_get(2).map_err(storage_error)?;
+                let committed_at: String = row.try_get(4).map_err(storage_error)?;
+                audit::parse_record(
+                    row.try_get(0).map_err(storage_error)?,
+                    row.try_get(1).map_err(storage_error)?,
+                    &operation,
+                    row.try_get(3).map_err(storage_error)?,
+                    &committed_at,
+                )
+            })
+            .collect()
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.finish("COMMIT")
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.finish("ROLLBACK")
+    }
+}
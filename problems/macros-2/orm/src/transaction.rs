@@ -1,8 +1,12 @@
 #![forbid(unsafe_code)]
 use crate::{
+    audit::{self, AuditRecord, FieldChange},
     data::ObjectId,
-    error::{Error, NotFoundError, Result},
+    error::{Error, NotFoundError, NotUniqueError, Result},
+    field::Field,
+    migration::{self, MigrationPolicy},
     object::{Object, Schema, Store},
+    query::{Column, Query, Select},
     storage::StorageTransaction,
 };
 use std::{
@@ -21,7 +25,17 @@ pub struct Transaction<'a> {
 }
 
 impl<'a> Transaction<'a> {
-    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>) -> Self {
+    pub(crate) fn new(
+        inner: Box<dyn StorageTransaction + 'a>,
+        migration_policy: MigrationPolicy,
+        audit_log: bool,
+    ) -> Self {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
+    /// The schema generated by `#[derive(Object)]` for `T`.
+    fn schema<T: Object>(&self) -> &'static Schema {
         // TODO: your code goes here.
         unimplemented!()
     }
@@ -36,11 +50,60 @@ impl<'a> Transaction<'a> {
         unimplemented!()
     }
 
+    /// Inserts all the objects at once.
+    pub fn create_many<T: Object>(
+        &self,
+        src_objs: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
     pub fn get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
         // TODO: your code goes here.
         unimplemented!()
     }
 
+    pub fn select<T: Object>(&self) -> Select<'_, 'a, T> {
+        Select::new(self)
+    }
+
+    /// Finds the object by the value of a column with a unique index. Like
+    /// `select()`, returns the cached object if there is one.
+    ///
+    /// Fails with `Error::NotUnique` if the column has no unique index of its
+    /// own, e.g. if it is only a part of a unique index over several columns.
+    pub fn get_by_unique<T: Object, V: Field>(
+        &self,
+        column: Column<T, V>,
+        value: impl Into<V>,
+    ) -> Result<Option<Tx<'_, T>>> {
+        let schema = self.schema::<T>();
+        let is_unique = schema
+            .indexes()
+            .iter()
+            .any(|index| index.unique && index.columns == [column.column_name()]);
+        if !is_unique {
+            return Err(Error::NotUnique(Box::new(NotUniqueError {
+                type_name: schema.type_name(),
+                attr_name: column.attr_name(),
+                table_name: schema.table_name(),
+                column_name: column.column_name(),
+            })));
+        }
+        let mut found = self
+            .select::<T>()
+            .filter(column.eq(value))
+            .limit(1)
+            .fetch()?;
+        Ok(found.pop())
+    }
+
+    pub(crate) fn fetch<T: Object>(&self, query: &Query) -> Result<Vec<Tx<'_, T>>> {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+
     fn try_apply(&self) -> Result<()> {
         // TODO: your code goes here.
         unimplemented!()
@@ -97,3 +160,12 @@ impl<'a, T: Any> Tx<'a, T> {
         unimplemented!()
     }
 }
+
+impl<'a, T: Object> Tx<'a, T> {
+    /// The fields that differ from the values in the storage, as the
+    /// transaction has last read or written them.
+    pub fn changes(&self) -> Vec<FieldChange> {
+        // TODO: your code goes here.
+        unimplemented!()
+    }
+}
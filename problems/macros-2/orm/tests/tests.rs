@@ -1,6 +1,14 @@
-use orm::{data::DataType, Connection, Object, ObjectId, ObjectState, Result, Tx};
-
-use rusqlite::params;
+use orm::{
+    audit::{FieldChange, Operation},
+    data::DataType,
+    Connection, Hooks, MigrationPolicy, Object, ObjectId, ObjectState, Ref, Result, RetryPolicy,
+    Transaction, Tx,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use orm::field::UnixTimestamp;
+use rusqlite::{params, types::Value};
+use std::{cell::RefCell, time::Duration};
 use tempfile::NamedTempFile;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -38,12 +46,55 @@ fn fmt_res<T>(res: &Result<T>) -> String {
     }
 }
 
-////////////////////////////////////////////////////////////////////////////////
+/// Runs the test `$name(conn: Connection)` against every storage backend.
+macro_rules! backend_test {
+    ($(#[$attr:meta])* $name:ident) => {
+        mod $name {
+            use super::*;
 
-#[test]
-fn create() {
-    let mut conn = Connection::open_in_memory().unwrap();
+            #[test]
+            $(#[$attr])*
+            fn sqlite() {
+                super::$name(Connection::open_in_memory().unwrap());
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn memory() {
+                super::$name(Connection::open_memory_storage().unwrap());
+            }
+
+            #[cfg(feature = "postgres")]
+            #[test]
+            $(#[$attr])*
+            fn postgres() {
+                super::$name(open_postgres(stringify!($name)));
+            }
+        }
+    };
+}
+
+/// Connects to the database from `ORM_POSTGRES` with a fresh schema, so that
+/// the tests running in parallel don't see each other's tables. The schema name
+/// is prefixed, since test names like `create` or `select` are reserved words.
+#[cfg(feature = "postgres")]
+fn open_postgres(test_name: &str) -> Connection {
+    let schema = format!("orm_test_{}", test_name);
+    let params = std::env::var("ORM_POSTGRES").expect("ORM_POSTGRES is not set");
+    let mut client = postgres::Client::connect(&params, postgres::NoTls).unwrap();
+    client
+        .batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}",
+            schema
+        ))
+        .unwrap();
+    Connection::open_postgres(&format!("{} options='-c search_path={}'", params, schema)).unwrap()
+}
+
+////////////////////////////////////////////////////////////////////////////////
 
+backend_test!(create);
+fn create(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let user = User {
         name: "John".into(),
@@ -63,10 +114,8 @@ fn create() {
     assert_eq!(*tx_user.borrow(), user);
 }
 
-#[test]
-fn update() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(update);
+fn update(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -92,10 +141,8 @@ fn update() {
     assert_eq!(tx_user.borrow().balance, 400.);
 }
 
-#[test]
-fn delete() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(delete);
+fn delete(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -126,10 +173,8 @@ fn delete() {
     assert_not_found(res, user_id, "User");
 }
 
-#[test]
-fn create_delete() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(create_delete);
+fn create_delete(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -156,11 +201,11 @@ fn create_delete() {
     assert_not_found(res, user_id, "User");
 }
 
-#[test]
-#[should_panic(expected = "already borrowed")]
-fn double_borrow() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(
+    #[should_panic(expected = "already borrowed")]
+    double_borrow
+);
+fn double_borrow(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -177,11 +222,11 @@ fn double_borrow() {
     let _r2 = tx_user_2.borrow_mut();
 }
 
-#[test]
-#[should_panic(expected = "cannot borrow a removed object")]
-fn borrow_created_deleted() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(
+    #[should_panic(expected = "cannot borrow a removed object")]
+    borrow_created_deleted
+);
+fn borrow_created_deleted(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -198,11 +243,11 @@ fn borrow_created_deleted() {
     tx_user_2.borrow();
 }
 
-#[test]
-#[should_panic(expected = "cannot borrow a removed object")]
-fn borrow_deleted() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(
+    #[should_panic(expected = "cannot borrow a removed object")]
+    borrow_deleted
+);
+fn borrow_deleted(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -224,11 +269,11 @@ fn borrow_deleted() {
     tx_user_2.borrow();
 }
 
-#[test]
-#[should_panic(expected = "cannot delete a borrowed object")]
-fn delete_borrowed() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(
+    #[should_panic(expected = "cannot delete a borrowed object")]
+    delete_borrowed
+);
+fn delete_borrowed(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -334,6 +379,7 @@ fn unexpected_type() {
             assert_eq!(err.type_name, "User");
             assert_eq!(err.table_name, "User");
             assert_eq!(err.attr_name, "is_admin");
+            assert_eq!(err.rust_type, "bool");
             assert_eq!(err.column_name, "is_admin");
             assert_eq!(err.expected_type, DataType::Bool);
             assert_eq!(err.got_type, "Text");
@@ -386,6 +432,7 @@ fn null_value() {
         Err(orm::Error::UnexpectedType(err)) => {
             assert_eq!(err.type_name, "User");
             assert_eq!(err.attr_name, "picture");
+            assert_eq!(err.rust_type, "alloc::vec::Vec<u8>");
             assert_eq!(err.column_name, "picture");
             assert_eq!(err.expected_type, DataType::Bytes);
             assert_eq!(err.got_type, "Null");
@@ -430,16 +477,14 @@ fn conflict() {
     }
 }
 
-#[test]
-fn empty_struct() {
+backend_test!(empty_struct);
+fn empty_struct(mut conn: Connection) {
     #[derive(Object)]
     struct Empty {}
 
     #[derive(Object)]
     struct Void;
 
-    let mut conn = Connection::open_in_memory().unwrap();
-
     let tx = conn.new_transaction().unwrap();
     let empty_id = tx.create::<Empty>(Empty {}).unwrap().id();
     let void_id = tx.create::<Void>(Void).unwrap().id();
@@ -465,12 +510,10 @@ fn empty_struct() {
     ));
 }
 
-#[test]
-fn sql_injection() {
+backend_test!(sql_injection);
+fn sql_injection(mut conn: Connection) {
     let names = ["\"; DROP TABLE user --", "'; DROP TABLE user --"];
 
-    let mut conn = Connection::open_in_memory().unwrap();
-
     for &name in names.iter() {
         let tx = conn.new_transaction().unwrap();
 
@@ -497,6 +540,173 @@ fn sql_injection() {
     }
 }
 
+backend_test!(select);
+fn select(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    for (name, visits, is_admin) in [
+        ("Carol", 30, false),
+        ("Alice", 10, true),
+        ("Dave", 5, false),
+        ("Bob", 20, false),
+        ("Eve", 20, true),
+    ] {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits,
+            balance: 0.,
+            is_admin,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let names = |users: Vec<Tx<User>>| -> Vec<String> {
+        users.iter().map(|u| u.borrow().name.clone()).collect()
+    };
+
+    let tx = conn.new_transaction().unwrap();
+    let users = tx
+        .select::<User>()
+        .filter(User::visits().ge(10))
+        .order_by(User::name())
+        .fetch()
+        .unwrap();
+    assert_eq!(names(users), ["Alice", "Bob", "Carol", "Eve"]);
+
+    let users = tx
+        .select::<User>()
+        .filter(User::visits().gt(5))
+        .filter(User::is_admin().eq(false))
+        .order_by_desc(User::visits())
+        .fetch()
+        .unwrap();
+    assert_eq!(names(users), ["Carol", "Bob"]);
+
+    let users = tx
+        .select::<User>()
+        .filter(
+            User::name()
+                .eq("Dave")
+                .or(User::visits().eq(20).and(User::is_admin().eq(true).not())),
+        )
+        .order_by(User::name())
+        .fetch()
+        .unwrap();
+    assert_eq!(names(users), ["Bob", "Dave"]);
+
+    let users = tx
+        .select::<User>()
+        .filter(User::name().is_in(["Eve", "Alice", "Mallory"]))
+        .order_by(User::name())
+        .fetch()
+        .unwrap();
+    assert_eq!(names(users), ["Alice", "Eve"]);
+
+    let users = tx
+        .select::<User>()
+        .filter(User::name().is_in(Vec::<String>::new()))
+        .fetch()
+        .unwrap();
+    assert!(users.is_empty());
+
+    let users = tx
+        .select::<User>()
+        .order_by(User::visits())
+        .order_by(User::name())
+        .offset(1)
+        .limit(3)
+        .fetch()
+        .unwrap();
+    assert_eq!(names(users), ["Alice", "Bob", "Eve"]);
+}
+
+backend_test!(select_identity_map);
+fn select_identity_map(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let users = ["Sam", "Kate", "Max"].map(|name| {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits: 1,
+            balance: 10.,
+            is_admin: false,
+        })
+        .unwrap()
+        .id()
+    });
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let sam = tx.get::<User>(users[0]).unwrap();
+    sam.borrow_mut().visits = 100;
+    tx.get::<User>(users[2]).unwrap().delete();
+
+    let selected = tx
+        .select::<User>()
+        .filter(User::visits().gt(1).or(User::name().eq("Max")))
+        .fetch()
+        .unwrap();
+    assert_eq!(selected.len(), 1);
+    assert_eq!(selected[0].id(), users[0]);
+
+    selected[0].borrow_mut().balance = 42.;
+    assert_eq!(sam.borrow().balance, 42.);
+
+    let selected = tx
+        .select::<User>()
+        .filter(User::name().eq("Kate"))
+        .fetch()
+        .unwrap();
+    let kate = tx.get::<User>(users[1]).unwrap();
+    kate.borrow_mut().visits = 7;
+    assert_eq!(selected[0].borrow().visits, 7);
+    assert!(selected[0].state() == ObjectState::Modified);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let selected = tx.select::<User>().order_by(User::name()).fetch().unwrap();
+    assert_eq!(selected.len(), 2);
+    assert_eq!(selected[0].borrow().name, "Kate");
+    assert_eq!(selected[0].borrow().visits, 7);
+    assert_eq!(selected[1].borrow().balance, 42.);
+}
+
+backend_test!(select_sql_injection);
+fn select_sql_injection(mut conn: Connection) {
+    let names = [
+        "\"; DROP TABLE user --",
+        "'; DROP TABLE user --",
+        "' OR 1 = 1 --",
+    ];
+
+    let tx = conn.new_transaction().unwrap();
+    for &name in names[..2].iter() {
+        tx.create(User {
+            name: name.into(),
+            picture: name.into(),
+            visits: 0,
+            balance: 0.,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for &name in names.iter() {
+        let users = tx
+            .select::<User>()
+            .filter(User::name().eq(name))
+            .fetch()
+            .unwrap();
+        let expected: &[&str] = if name == names[2] { &[] } else { &[name] };
+        let got: Vec<_> = users.iter().map(|u| u.borrow().name.clone()).collect();
+        assert_eq!(got, expected);
+    }
+    assert_eq!(tx.select::<User>().fetch().unwrap().len(), 2);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Object)]
@@ -526,10 +736,8 @@ fn table_column_names() {
         .unwrap();
 }
 
-#[test]
-fn not_found() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(not_found);
+fn not_found(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     match tx.get::<Order>(3523.into()) {
         Err(orm::Error::NotFound(err)) => {
@@ -632,10 +840,8 @@ fn missing_column_renamed() {
     }
 }
 
-#[test]
-fn rollback() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+backend_test!(rollback);
+fn rollback(mut conn: Connection) {
     let user = User {
         name: "DanilaBorisov".into(),
         picture: b"myVKpasswordIS:oigwy8sdvo"[..].into(),
@@ -667,6 +873,1137 @@ fn rollback() {
     assert_eq!(tx_user.borrow().balance, 220.);
 }
 
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+#[orm(has_many(books = "Book::author"))]
+struct Author {
+    name: String,
+}
+
+#[derive(Object, Debug)]
+#[orm(has_many(reviews = "Review::book"))]
+struct Book {
+    title: String,
+    #[orm(on_delete(cascade))]
+    author: Ref<Author>,
+}
+
+#[derive(Object, Debug)]
+struct Review {
+    text: String,
+    book: Ref<Book>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+backend_test!(relations);
+fn relations(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let author = tx
+        .create(Author {
+            name: "Tolstoy".into(),
+        })
+        .unwrap();
+    let other = tx
+        .create(Author {
+            name: "Pushkin".into(),
+        })
+        .unwrap();
+    for title in ["War and Peace", "Anna Karenina"] {
+        tx.create(Book {
+            title: title.into(),
+            author: Ref::from(&author),
+        })
+        .unwrap();
+    }
+    let author_id = author.id();
+    let other_id = other.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let author = tx.get::<Author>(author_id).unwrap();
+    let books = Author::books().fetch(&tx, &author).unwrap();
+    let titles: Vec<_> = books.iter().map(|b| b.borrow().title.clone()).collect();
+    assert_eq!(titles, ["War and Peace", "Anna Karenina"]);
+
+    let book_author = books[0].borrow().author.get(&tx).unwrap();
+    assert_eq!(book_author.id(), author_id);
+    book_author.borrow_mut().name = "Leo Tolstoy".into();
+    assert_eq!(author.borrow().name, "Leo Tolstoy");
+
+    let other = tx.get::<Author>(other_id).unwrap();
+    assert!(Author::books().fetch(&tx, &other).unwrap().is_empty());
+    books[1].borrow_mut().author = Ref::from(&other);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let other = tx.get::<Author>(other_id).unwrap();
+    let books = Author::books().fetch(&tx, &other).unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0].borrow().title, "Anna Karenina");
+    assert_eq!(books[0].borrow().author, Ref::new(other_id));
+}
+
+backend_test!(on_delete_cascade);
+fn on_delete_cascade(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let author = tx
+        .create(Author {
+            name: "Gogol".into(),
+        })
+        .unwrap();
+    let book_id = tx
+        .create(Book {
+            title: "Dead Souls".into(),
+            author: Ref::from(&author),
+        })
+        .unwrap()
+        .id();
+    let author_id = author.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Author>(author_id).unwrap().delete();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<Book>(book_id),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+
+backend_test!(on_delete_restrict);
+fn on_delete_restrict(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let author = tx
+        .create(Author {
+            name: "Chekhov".into(),
+        })
+        .unwrap();
+    let book = tx
+        .create(Book {
+            title: "The Seagull".into(),
+            author: Ref::from(&author),
+        })
+        .unwrap();
+    let review_id = tx
+        .create(Review {
+            text: "Nice".into(),
+            book: Ref::from(&book),
+        })
+        .unwrap()
+        .id();
+    let book_id = book.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Book>(book_id).unwrap().delete();
+    match tx.commit() {
+        Err(orm::Error::ReferentialIntegrity(err)) => {
+            assert_eq!(err.object_id, review_id);
+            assert_eq!(err.table_name, "Review");
+            assert_eq!(err.referenced_table_name, "Book");
+        }
+        res => panic!("expected ReferentialIntegrity error, got {}", fmt_res(&res)),
+    }
+
+    let tx = conn.new_transaction().unwrap();
+    let book = tx.get::<Book>(book_id).unwrap();
+    let reviews = Book::reviews().fetch(&tx, &book).unwrap();
+    assert_eq!(reviews.len(), 1);
+    reviews[0].clone().delete();
+    book.delete();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<Review>(review_id),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+mod v1 {
+    use orm::Object;
+
+    #[derive(Object)]
+    #[table_name("account")]
+    pub struct Account {
+        pub login: String,
+        pub karma: f64,
+    }
+}
+
+mod v2 {
+    use orm::Object;
+
+    #[derive(Object)]
+    #[table_name("account")]
+    pub struct Account {
+        #[orm(renamed_from("login"))]
+        pub name: String,
+        pub karma: f64,
+        pub visits: i64,
+        pub bio: String,
+    }
+}
+
+mod v3 {
+    use orm::Object;
+
+    #[derive(Object)]
+    #[table_name("account")]
+    pub struct Account {
+        pub name: String,
+        pub visits: String,
+    }
+}
+
+#[test]
+fn migrations() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let account_id = tx
+        .create(v1::Account {
+            login: "neo".into(),
+            karma: 1.5,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+    drop(conn);
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<v2::Account>(account_id),
+        Err(orm::Error::MissingColumn(_))
+    ));
+    tx.rollback().unwrap();
+
+    conn.set_migration_policy(MigrationPolicy::Safe);
+    let tx = conn.new_transaction().unwrap();
+    let account = tx.get::<v2::Account>(account_id).unwrap();
+    assert_eq!(account.borrow().name, "neo");
+    assert_eq!(account.borrow().karma, 1.5);
+    assert_eq!(account.borrow().visits, 0);
+    assert_eq!(account.borrow().bio, "");
+    account.borrow_mut().visits = 3;
+    tx.commit().unwrap();
+    drop(conn);
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    conn.set_migration_policy(MigrationPolicy::Safe);
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<v3::Account>(account_id) {
+        Err(orm::Error::Migration(err)) => {
+            assert_eq!(err.table_name, "account");
+            assert_eq!(err.kind, orm::MigrationErrorKind::Destructive);
+        }
+        res => panic!("expected Migration error, got {}", fmt_res(&res)),
+    }
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<v2::Account>(account_id).unwrap().borrow().visits,
+        3
+    );
+    tx.rollback().unwrap();
+
+    conn.set_migration_policy(MigrationPolicy::AllowDestructive);
+    let tx = conn.new_transaction().unwrap();
+    let account = tx.get::<v3::Account>(account_id).unwrap();
+    assert_eq!(account.borrow().name, "neo");
+    assert_eq!(account.borrow().visits, "");
+    tx.commit().unwrap();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let columns: Vec<String> = sqlite_conn
+        .prepare("SELECT name FROM pragma_table_info('account') ORDER BY cid")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(columns, ["id", "name", "visits"]);
+}
+
+mod v4 {
+    use orm::Object;
+
+    #[derive(Object)]
+    #[table_name("account")]
+    pub struct Account {
+        pub name: String,
+        pub visits: String,
+        #[orm(on_delete(cascade))]
+        pub favourite: Option<orm::Ref<super::Author>>,
+    }
+}
+
+#[test]
+fn migration_adds_reference() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let account_id = tx
+        .create(v3::Account {
+            name: "neo".into(),
+            visits: "".into(),
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+    drop(conn);
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    conn.set_migration_policy(MigrationPolicy::Safe);
+    let tx = conn.new_transaction().unwrap();
+    let account = tx.get::<v4::Account>(account_id).unwrap();
+    assert!(account.borrow().favourite.is_none());
+    let author = tx
+        .create(Author {
+            name: "Gibson".into(),
+        })
+        .unwrap();
+    account.borrow_mut().favourite = Some(Ref::from(&author));
+    let author_id = author.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Author>(author_id).unwrap().delete();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<v4::Account>(account_id),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn make_users(count: usize) -> Vec<User> {
+    (0..count)
+        .map(|i| User {
+            name: format!("user{}", i),
+            picture: vec![i as u8],
+            visits: i as i64,
+            balance: i as f64 / 2.,
+            is_admin: i % 10 == 0,
+        })
+        .collect()
+}
+
+backend_test!(create_many);
+fn create_many(mut conn: Connection) {
+    // More rows than fit into a single statement.
+    let users = make_users(1500);
+
+    let tx = conn.new_transaction().unwrap();
+    let created = tx.create_many(users.clone()).unwrap();
+    assert_eq!(created.len(), users.len());
+    for (tx_user, user) in created.iter().zip(&users) {
+        assert!(tx_user.state() == ObjectState::Clean);
+        assert_eq!(*tx_user.borrow(), *user);
+    }
+    let ids: Vec<_> = created.iter().map(|user| user.id()).collect();
+    let mut unique_ids = ids.clone();
+    unique_ids.sort();
+    unique_ids.dedup();
+    assert_eq!(unique_ids.len(), users.len());
+
+    // The created objects are in the cache.
+    let tx_user = tx.get::<User>(ids[42]).unwrap();
+    tx_user.borrow_mut().visits = -1;
+    assert_eq!(created[42].borrow().visits, -1);
+    assert!(created[42].state() == ObjectState::Modified);
+
+    assert!(tx.create_many(Vec::<User>::new()).unwrap().is_empty());
+    drop((created, tx_user));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for (i, (&id, user)) in ids.iter().zip(&users).enumerate() {
+        let tx_user = tx.get::<User>(id).unwrap();
+        match i {
+            42 => assert_eq!(tx_user.borrow().visits, -1),
+            _ => assert_eq!(*tx_user.borrow(), *user),
+        }
+    }
+}
+
+backend_test!(batched_commit);
+fn batched_commit(mut conn: Connection) {
+    let users = make_users(1500);
+
+    let tx = conn.new_transaction().unwrap();
+    let ids: Vec<_> = tx
+        .create_many(users.clone())
+        .unwrap()
+        .iter()
+        .map(|user| user.id())
+        .collect();
+    tx.commit().unwrap();
+
+    // Update every third object, delete every fifth, some of them are both.
+    let tx = conn.new_transaction().unwrap();
+    let tx_users: Vec<_> = ids.iter().map(|&id| tx.get::<User>(id).unwrap()).collect();
+    for (i, tx_user) in tx_users.iter().enumerate() {
+        if i % 3 == 0 {
+            tx_user.borrow_mut().visits += 1000;
+            assert!(tx_user.state() == ObjectState::Modified);
+        }
+    }
+    for (i, tx_user) in tx_users.iter().enumerate() {
+        if i % 5 == 0 {
+            tx_user.clone().delete();
+            assert!(tx_user.state() == ObjectState::Removed);
+        }
+    }
+    drop(tx_users);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for (i, (&id, user)) in ids.iter().zip(&users).enumerate() {
+        let res = tx.get::<User>(id);
+        if i % 5 == 0 {
+            assert_not_found(res, id, "User");
+            continue;
+        }
+        let tx_user = res.unwrap();
+        assert!(tx_user.state() == ObjectState::Clean);
+        let expected_visits = user.visits + if i % 3 == 0 { 1000 } else { 0 };
+        assert_eq!(tx_user.borrow().visits, expected_visits);
+        assert_eq!(tx_user.borrow().name, user.name);
+    }
+    assert_eq!(tx.select::<User>().fetch().unwrap().len(), 1200);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, PartialEq, Debug)]
+struct Wallet {
+    balance: i64,
+    #[orm(version)]
+    version: i64,
+}
+
+backend_test!(versions);
+fn versions(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Wallet {
+            balance: 10,
+            version: 0,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let wallet = tx.get::<Wallet>(id).unwrap();
+    wallet.borrow_mut().balance += 5;
+    tx.select::<Wallet>().fetch().unwrap();
+    assert_eq!(wallet.borrow().version, 1);
+    wallet.borrow_mut().balance += 5;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let wallet = tx.get::<Wallet>(id).unwrap();
+    assert_eq!(
+        *wallet.borrow(),
+        Wallet {
+            balance: 20,
+            version: 2
+        }
+    );
+}
+
+backend_test!(stale_object);
+fn stale_object(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Wallet {
+            balance: 10,
+            version: 0,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let wallet = tx.get::<Wallet>(id).unwrap();
+    wallet.borrow_mut().version = 5;
+    match tx.commit() {
+        Err(orm::Error::StaleObject(err)) => {
+            assert_eq!(err.object_id, id);
+            assert_eq!(err.type_name, "Wallet");
+            assert_eq!(err.version, 5);
+        }
+        res => panic!("expected Error::StaleObject, got {}", fmt_res(&res)),
+    }
+
+    let tx = conn.new_transaction().unwrap();
+    let wallet = tx.get::<Wallet>(id).unwrap();
+    assert_eq!(wallet.borrow().balance, 10);
+    assert_eq!(wallet.borrow().version, 0);
+}
+
+backend_test!(transact);
+fn transact(mut conn: Connection) {
+    conn.set_retry_policy(RetryPolicy {
+        max_retries: 2,
+        backoff: Duration::from_millis(1),
+    });
+
+    let id = conn
+        .transact(|tx| {
+            let wallet = tx.create(Wallet {
+                balance: 10,
+                version: 0,
+            })?;
+            Ok(wallet.id())
+        })
+        .unwrap();
+
+    // The first attempt writes a stale object, the second one succeeds.
+    let mut attempts = 0;
+    conn.transact(|tx| {
+        attempts += 1;
+        let wallet = tx.get::<Wallet>(id)?;
+        let mut wallet = wallet.borrow_mut();
+        wallet.balance += 1;
+        if attempts == 1 {
+            wallet.version += 10;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(attempts, 2);
+
+    let mut attempts = 0;
+    let res = conn.transact(|tx| {
+        attempts += 1;
+        tx.get::<Wallet>(id)?.borrow_mut().version += 10;
+        Ok(())
+    });
+    assert!(
+        matches!(res, Err(orm::Error::StaleObject(_))),
+        "expected Error::StaleObject, got {}",
+        fmt_res(&res)
+    );
+    assert_eq!(attempts, 3);
+
+    // Other errors are not retried.
+    let mut attempts = 0;
+    let missing_id = ObjectId::from(id.into_i64() + 1);
+    let res = conn.transact(|tx| {
+        attempts += 1;
+        tx.get::<Wallet>(missing_id).map(|_| ())
+    });
+    assert!(matches!(res, Err(orm::Error::NotFound(_))));
+    assert_eq!(attempts, 1);
+
+    let wallet = conn
+        .transact(|tx| {
+            let wallet = tx.get::<Wallet>(id)?;
+            let wallet = wallet.borrow();
+            Ok((wallet.balance, wallet.version))
+        })
+        .unwrap();
+    assert_eq!(wallet, (11, 1));
+}
+
+#[test]
+fn transact_lock_conflict() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn_one = Connection::open_sqlite_file(&path).unwrap();
+    let tx_one = conn_one.new_transaction().unwrap();
+    tx_one
+        .create(Wallet {
+            balance: 10,
+            version: 0,
+        })
+        .unwrap();
+
+    let mut conn_two = Connection::open_sqlite_file(&path).unwrap();
+    conn_two.set_retry_policy(RetryPolicy {
+        max_retries: 2,
+        backoff: Duration::from_millis(1),
+    });
+    let mut attempts = 0;
+    let mut create = |tx: &Transaction<'_>| -> Result<ObjectId> {
+        attempts += 1;
+        let wallet = tx.create(Wallet {
+            balance: 20,
+            version: 0,
+        })?;
+        Ok(wallet.id())
+    };
+
+    let res = conn_two.transact(&mut create);
+    assert!(
+        matches!(res, Err(orm::Error::LockConflict)),
+        "expected Error::LockConflict, got {}",
+        fmt_res(&res)
+    );
+    assert_eq!(attempts, 3);
+
+    tx_one.commit().unwrap();
+    conn_two.transact(&mut create).unwrap();
+    assert_eq!(attempts, 4);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Clone, PartialEq, Debug)]
+#[orm(index(last_name, first_name))]
+#[orm(unique(passport_series, passport_number))]
+struct Person {
+    #[orm(unique)]
+    email: String,
+    first_name: String,
+    last_name: String,
+    #[orm(index)]
+    passport_series: i64,
+    passport_number: i64,
+}
+
+fn person(email: &str, passport_series: i64, passport_number: i64) -> Person {
+    Person {
+        email: email.into(),
+        first_name: "John".into(),
+        last_name: "Smith".into(),
+        passport_series,
+        passport_number,
+    }
+}
+
+fn assert_unique_violation<T>(res: Result<T>, expected_column_names: &[&str]) {
+    match res {
+        Err(orm::Error::UniqueViolation(err)) => {
+            assert_eq!(err.table_name, "Person");
+            assert_eq!(err.column_names, expected_column_names);
+        }
+        res => panic!("expected Error::UniqueViolation, got {}", fmt_res(&res)),
+    }
+}
+
+backend_test!(unique_index);
+fn unique_index(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(person("alice@example.com", 1234, 567890))
+        .unwrap();
+    let bob_id = tx
+        .create(person("bob@example.com", 1234, 567891))
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    // A failed statement may abort the whole transaction, so every check
+    // has its own one.
+    let tx = conn.new_transaction().unwrap();
+    let res = tx.create(person("alice@example.com", 4321, 1));
+    assert_unique_violation(res, &["email"]);
+    drop(tx);
+
+    let tx = conn.new_transaction().unwrap();
+    let res = tx.create(person("carol@example.com", 1234, 567890));
+    assert_unique_violation(res, &["passport_series", "passport_number"]);
+    drop(tx);
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Person>(bob_id).unwrap().borrow_mut().email = "alice@example.com".into();
+    assert_unique_violation(tx.commit(), &["email"]);
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Person>(bob_id).unwrap().borrow().email,
+        "bob@example.com"
+    );
+    tx.create(person("carol@example.com", 1235, 567890))
+        .unwrap();
+    tx.commit().unwrap();
+}
+
+backend_test!(get_by_unique);
+fn get_by_unique(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let alice_id = tx
+        .create(person("alice@example.com", 1234, 567890))
+        .unwrap()
+        .id();
+    tx.create(person("bob@example.com", 1234, 567891)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let alice = tx
+        .get_by_unique(Person::email(), "alice@example.com")
+        .unwrap()
+        .unwrap();
+    assert_eq!(alice.id(), alice_id);
+    assert_eq!(*alice.borrow(), person("alice@example.com", 1234, 567890));
+    assert!(tx
+        .get_by_unique(Person::email(), "carol@example.com")
+        .unwrap()
+        .is_none());
+
+    // The cached object is returned, with its pending changes.
+    let cached = tx.get::<Person>(alice_id).unwrap();
+    cached.borrow_mut().email = "alice@example.org".into();
+    assert!(tx
+        .get_by_unique(Person::email(), "alice@example.com")
+        .unwrap()
+        .is_none());
+    let found = tx
+        .get_by_unique(Person::email(), "alice@example.org")
+        .unwrap()
+        .unwrap();
+    found.borrow_mut().first_name = "Alice".into();
+    assert_eq!(cached.borrow().first_name, "Alice");
+
+    found.delete();
+    assert!(tx
+        .get_by_unique(Person::email(), "alice@example.org")
+        .unwrap()
+        .is_none());
+}
+
+backend_test!(get_by_unique_not_unique);
+fn get_by_unique_not_unique(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(person("alice@example.com", 1234, 567890))
+        .unwrap();
+    tx.create(person("bob@example.com", 1234, 567891)).unwrap();
+    // Only a part of the unique index over the passport series and number.
+    match tx.get_by_unique(Person::passport_series(), 1234) {
+        Err(orm::Error::NotUnique(err)) => {
+            assert_eq!(err.type_name, "Person");
+            assert_eq!(err.attr_name, "passport_series");
+            assert_eq!(err.table_name, "Person");
+            assert_eq!(err.column_name, "passport_series");
+        }
+        res => panic!("expected NotUnique error, got {}", fmt_res(&res)),
+    }
+}
+
+#[test]
+fn index_names() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut orm_conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = orm_conn.new_transaction().unwrap();
+    tx.create(person("alice@example.com", 1234, 567890))
+        .unwrap();
+    tx.commit().unwrap();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let mut stmt = sqlite_conn
+        .prepare("SELECT name, sql FROM sqlite_master WHERE type = 'index' ORDER BY name")
+        .unwrap();
+    let indexes: Vec<(String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    let names: Vec<_> = indexes.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "Person_email_key",
+            "Person_last_name_first_name_idx",
+            "Person_passport_series_idx",
+            "Person_passport_series_passport_number_key",
+        ]
+    );
+    assert!(indexes[0].1.starts_with("CREATE UNIQUE INDEX"));
+    assert!(indexes[1].1.starts_with("CREATE INDEX"));
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Clone, PartialEq, Debug)]
+#[orm(hooks)]
+struct Note {
+    title: String,
+    locked: bool,
+}
+
+thread_local! {
+    static NOTE_EVENTS: RefCell<Vec<String>> = RefCell::new(vec![]);
+}
+
+fn note_events() -> Vec<String> {
+    NOTE_EVENTS.with(|events| events.borrow().clone())
+}
+
+fn log_note_event(event: String) {
+    NOTE_EVENTS.with(|events| events.borrow_mut().push(event));
+}
+
+impl Hooks for Note {
+    fn before_insert(&mut self) -> Result<()> {
+        self.title = self.title.trim().to_string();
+        if self.title.is_empty() {
+            return Err(orm::Error::Hook("empty title".into()));
+        }
+        log_note_event(format!("insert {}", self.title));
+        Ok(())
+    }
+
+    fn after_update(&self) -> Result<()> {
+        log_note_event(format!("update {}", self.title));
+        Ok(())
+    }
+
+    fn before_delete(&self) -> Result<()> {
+        if self.locked {
+            return Err(orm::Error::Hook("note is locked".into()));
+        }
+        log_note_event(format!("delete {}", self.title));
+        Ok(())
+    }
+}
+
+backend_test!(hooks);
+fn hooks(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let note = tx
+        .create(Note {
+            title: "  Groceries ".into(),
+            locked: false,
+        })
+        .unwrap();
+    assert_eq!(note.borrow().title, "Groceries");
+    let note_id = note.id();
+    let res = tx.create(Note {
+        title: " ".into(),
+        locked: false,
+    });
+    assert!(
+        matches!(res, Err(orm::Error::Hook(_))),
+        "expected Error::Hook, got {}",
+        fmt_res(&res)
+    );
+    assert_eq!(note_events(), ["insert Groceries"]);
+    drop(note);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let note = tx.get::<Note>(note_id).unwrap();
+    note.borrow_mut().title = "Shopping".into();
+    note.borrow_mut().locked = true;
+    drop(note);
+    tx.commit().unwrap();
+    assert_eq!(note_events(), ["insert Groceries", "update Shopping"]);
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Note>(note_id).unwrap().delete();
+    let res = tx.commit();
+    assert!(
+        matches!(res, Err(orm::Error::Hook(_))),
+        "expected Error::Hook, got {}",
+        fmt_res(&res)
+    );
+
+    let tx = conn.new_transaction().unwrap();
+    let note = tx.get::<Note>(note_id).unwrap();
+    note.borrow_mut().locked = false;
+    note.delete();
+    tx.commit().unwrap();
+    assert_eq!(
+        note_events(),
+        ["insert Groceries", "update Shopping", "delete Shopping"]
+    );
+}
+
+backend_test!(changes);
+fn changes(mut conn: Connection) {
+    let user = User {
+        name: "Alice".into(),
+        picture: vec![1, 2, 3],
+        visits: 10,
+        balance: 0.5,
+        is_admin: false,
+    };
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx.create(user.clone()).unwrap();
+    assert!(tx_user.changes().is_empty());
+    let user_id = tx_user.id();
+    drop(tx_user);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx.get::<User>(user_id).unwrap();
+    assert!(tx_user.changes().is_empty());
+    tx_user.borrow_mut().name = "Bob".into();
+    tx_user.borrow_mut().visits += 1;
+    assert_eq!(
+        tx_user.changes(),
+        [
+            FieldChange {
+                attr_name: "name",
+                column_name: "name",
+                old: orm::data::Value::String("Alice".into()),
+                new: orm::data::Value::String("Bob".into()),
+            },
+            FieldChange {
+                attr_name: "visits",
+                column_name: "visits",
+                old: orm::data::Value::Int64(10),
+                new: orm::data::Value::Int64(11),
+            },
+        ]
+    );
+
+    tx_user.borrow_mut().name = "Alice".into();
+    let changes = tx_user.changes();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].attr_name, "visits");
+
+    // Flushed changes are in the storage.
+    tx.select::<User>().fetch().unwrap();
+    assert!(tx_user.changes().is_empty());
+}
+
+#[derive(Object, Clone, PartialEq, Debug)]
+struct Setting {
+    key: String,
+    value: Option<i64>,
+    enabled: bool,
+}
+
+backend_test!(audit_log);
+fn audit_log(mut conn: Connection) {
+    let setting = Setting {
+        key: "theme \"dark\"".into(),
+        value: Some(1),
+        enabled: true,
+    };
+
+    // Nothing is recorded by default.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(setting.clone()).unwrap();
+    tx.commit().unwrap();
+    assert!(conn.audit_log().unwrap().is_empty());
+
+    conn.set_audit_log(true);
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(setting.clone()).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Setting>(id).unwrap().borrow_mut().value = None;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Setting>(id).unwrap().borrow_mut().enabled = false;
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Setting>(id).unwrap().delete();
+    tx.commit().unwrap();
+
+    let records = conn.audit_log().unwrap();
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| {
+            (
+                r.table_name.as_str(),
+                r.object_id,
+                r.operation,
+                r.changes.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                "Setting",
+                id,
+                Operation::Insert,
+                r#"{"key": "theme \"dark\"", "value": 1, "enabled": true}"#
+            ),
+            ("Setting", id, Operation::Update, r#"{"value": [1, null]}"#),
+            (
+                "Setting",
+                id,
+                Operation::Delete,
+                r#"{"key": "theme \"dark\"", "value": null, "enabled": true}"#
+            ),
+        ]
+    );
+    assert!(records
+        .windows(2)
+        .all(|w| w[0].committed_at <= w[1].committed_at));
+
+    // The objects are flushed at different moments, but committed at once.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(setting.clone()).unwrap();
+    tx.select::<Setting>().fetch().unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    tx.create(setting).unwrap();
+    tx.commit().unwrap();
+
+    let records = conn.audit_log().unwrap();
+    assert_eq!(records.len(), 5);
+    assert_eq!(records[3].committed_at, records[4].committed_at);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(orm::Field, Clone, Copy, PartialEq, Debug)]
+enum Role {
+    Guest,
+    Member,
+    Admin,
+}
+
+#[derive(Object, PartialEq, Clone, Debug)]
+struct Profile {
+    nickname: Option<String>,
+    avatar: Option<Vec<u8>>,
+    age: Option<u32>,
+    level: i32,
+    followers: u32,
+    rating: f32,
+    role: Role,
+    created_at: DateTime<Utc>,
+    last_seen: UnixTimestamp,
+}
+
+#[test]
+fn field_types() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let time = Utc.with_ymd_and_hms(2022, 4, 1, 12, 30, 15).unwrap();
+    let profiles = [
+        Profile {
+            nickname: Some("trinity".into()),
+            avatar: Some(b"\x00\x01"[..].into()),
+            age: Some(u32::MAX),
+            level: i32::MIN,
+            followers: u32::MAX,
+            rating: 4.5,
+            role: Role::Admin,
+            created_at: time,
+            last_seen: UnixTimestamp(time),
+        },
+        Profile {
+            nickname: None,
+            avatar: None,
+            age: None,
+            level: 3,
+            followers: 0,
+            rating: -0.25,
+            role: Role::Guest,
+            created_at: time,
+            last_seen: UnixTimestamp(Utc.timestamp_opt(0, 0).unwrap()),
+        },
+    ];
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let ids: Vec<_> = profiles
+        .iter()
+        .map(|p| tx.create(p.clone()).unwrap().id())
+        .collect();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for (id, profile) in ids.iter().zip(profiles.iter()) {
+        assert_eq!(*tx.get::<Profile>(*id).unwrap().borrow(), *profile);
+    }
+
+    let anonymous = tx
+        .select::<Profile>()
+        .filter(Profile::nickname().is_null())
+        .fetch()
+        .unwrap();
+    assert_eq!(anonymous.len(), 1);
+    assert_eq!(anonymous[0].id(), ids[1]);
+
+    let admins = tx
+        .select::<Profile>()
+        .filter(Profile::role().eq(Role::Admin))
+        .filter(Profile::age().is_not_null())
+        .fetch()
+        .unwrap();
+    assert_eq!(admins.len(), 1);
+    assert_eq!(admins[0].id(), ids[0]);
+
+    admins[0].borrow_mut().nickname = None;
+    admins[0].borrow_mut().role = Role::Member;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let profile = tx.get::<Profile>(ids[0]).unwrap();
+    assert_eq!(profile.borrow().nickname, None);
+    assert_eq!(profile.borrow().role, Role::Member);
+    tx.commit().unwrap();
+    drop(conn);
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let (created_at, last_seen): (String, i64) = sqlite_conn
+        .query_row(
+            "SELECT created_at, last_seen FROM Profile WHERE id = ?",
+            [ids[0].into_i64()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(created_at, "2022-04-01T12:30:15+00:00");
+    assert_eq!(last_seen, time.timestamp());
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    for (column, rust_type, bad_value, value) in [
+        (
+            "role",
+            "tests::Role",
+            Value::Text("Owner".into()),
+            Value::Text("Guest".into()),
+        ),
+        ("followers", "u32", Value::Integer(-1), Value::Integer(0)),
+        ("level", "i32", Value::Null, Value::Integer(3)),
+    ] {
+        let set_column = |value: &Value| {
+            sqlite_conn
+                .execute(
+                    &format!("UPDATE Profile SET {} = ? WHERE id = ?", column),
+                    params![value, ids[1].into_i64()],
+                )
+                .unwrap();
+        };
+
+        set_column(&bad_value);
+        let tx = conn.new_transaction().unwrap();
+        match tx.get::<Profile>(ids[1]) {
+            Err(orm::Error::UnexpectedType(err)) => {
+                assert_eq!(err.type_name, "Profile");
+                assert_eq!(err.attr_name, column);
+                assert_eq!(err.rust_type, rust_type);
+            }
+            res => panic!("expected UnexpectedType error, got {}", fmt_res(&res)),
+        }
+        tx.rollback().unwrap();
+
+        set_column(&value);
+        let tx = conn.new_transaction().unwrap();
+        assert_eq!(*tx.get::<Profile>(ids[1]).unwrap().borrow(), profiles[1]);
+        tx.rollback().unwrap();
+    }
+}
+
 #[cfg(feature = "test-lifetimes-create")]
 #[test]
 fn lifetimes_create() {
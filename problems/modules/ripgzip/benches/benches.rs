@@ -0,0 +1,82 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// About 16Mb of pseudo-random words.
+fn text() -> Vec<u8> {
+    const WORDS: &[&str] = &[
+        "gzip", "member", "deflate", "block", "huffman", "window", "thread", "chunk", "the", "a",
+        "of", "and", "\n",
+    ];
+    let mut state = 0x2545f491u32;
+    let mut text = vec![];
+    while text.len() < 16 << 20 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        text.extend_from_slice(WORDS[state as usize % WORDS.len()].as_bytes());
+        text.push(b' ');
+    }
+    text
+}
+
+fn bench_compress(c: &mut Criterion) {
+    let data = text();
+    let mut group = c.benchmark_group("compress");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    for threads in THREADS {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let mut output = vec![];
+                    ripgzip::compress_parallel(data.as_slice(), &mut output, 6, threads).unwrap();
+                    output
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_decompress_members(c: &mut Criterion) {
+    let data = text();
+    let mut file = vec![];
+    for part in data.chunks(data.len() / 16) {
+        ripgzip::compress(part, &mut file, 6).unwrap();
+    }
+
+    let mut group = c.benchmark_group("decompress_16_members");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            let mut output = vec![];
+            ripgzip::decompress(file.as_slice(), &mut output).unwrap();
+            output
+        })
+    });
+    for threads in THREADS {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let mut output = vec![];
+                    ripgzip::decompress_parallel(file.as_slice(), &mut output, threads).unwrap();
+                    output
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_compress, bench_decompress_members);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct BitWriter<T> {
+    stream: T,
+    buffer: u64,
+    len: u8,
+}
+
+impl<T: Write> BitWriter<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            buffer: 0,
+            len: 0,
+        }
+    }
+
+    /// Write the `len` lowest bits of `bits`, starting from the least significant one.
+    pub fn write_bits(&mut self, bits: u32, len: u8) -> io::Result<()> {
+        debug_assert!(len <= 32);
+        let mask = (1u64 << len) - 1;
+        self.buffer |= (bits as u64 & mask) << self.len;
+        self.len += len;
+        if self.len >= 32 {
+            self.stream.write_all(&(self.buffer as u32).to_le_bytes())?;
+            self.buffer >>= 32;
+            self.len -= 32;
+        }
+        Ok(())
+    }
+
+    /// Write the first `bit_len` bits of `bits`, as produced by another `BitWriter`.
+    pub fn write_bit_string(&mut self, bits: &[u8], bit_len: usize) -> io::Result<()> {
+        let (bytes, rest) = (bit_len / 8, (bit_len % 8) as u8);
+        if self.len == 0 {
+            self.stream.write_all(&bits[..bytes])?;
+        } else {
+            for &byte in &bits[..bytes] {
+                self.write_bits(byte as u32, 8)?;
+            }
+        }
+        if rest > 0 {
+            self.write_bits(bits[bytes] as u32, rest)?;
+        }
+        Ok(())
+    }
+
+    /// Pad the current byte with zero bits, write all the buffered bytes and return
+    /// a mutable reference to the underlying writer.
+    pub fn borrow_writer_from_boundary(&mut self) -> io::Result<&mut T> {
+        let bytes = (self.len as usize).div_ceil(8);
+        self.stream.write_all(&self.buffer.to_le_bytes()[..bytes])?;
+        self.buffer = 0;
+        self.len = 0;
+        Ok(&mut self.stream)
+    }
+
+    pub fn finish(mut self) -> io::Result<T> {
+        self.borrow_writer_from_boundary()?;
+        Ok(self.stream)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_bits() -> io::Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b1, 1)?;
+        writer.write_bits(0b01, 2)?;
+        writer.write_bits(0b100, 3)?;
+        writer.write_bits(0b1101, 4)?;
+        writer.write_bits(0b10110, 5)?;
+        writer.write_bits(0b01011111, 8)?;
+        writer.write_bits(0b11, 2)?;
+        assert_eq!(writer.finish()?, [0b01100011, 0b11011011, 0b10101111, 0b1]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_bit_string() -> io::Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b1, 1)?;
+        writer.write_bit_string(&[0b10101010, 0b110], 11)?;
+        writer.write_bit_string(&[0xff, 0xff], 16)?;
+        writer.borrow_writer_from_boundary()?.write_all(&[42])?;
+        writer.write_bit_string(&[0b101], 3)?;
+        assert_eq!(
+            writer.finish()?,
+            [0b01010101, 0b11111101, 0xff, 0b1111, 42, 0b101]
+        );
+        Ok(())
+    }
+}
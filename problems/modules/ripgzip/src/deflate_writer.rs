@@ -0,0 +1,423 @@
+#![forbid(unsafe_code)]
+
+use std::io::Write;
+
+use anyhow::Result;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::bit_writer::BitWriter;
+use crate::deflate::{BlockHeader, CompressionType};
+use crate::huffman_encoder::HuffmanEncoder;
+use crate::lz77::{self, Token};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The input is compressed by chunks of this size, each one given the previous
+/// `lz77::WINDOW_SIZE` bytes as a dictionary.
+pub const CHUNK_SIZE: usize = 128 * 1024;
+
+const MAX_STORED_LEN: usize = 65535;
+const MAX_CODE_LEN: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LEN: u8 = 7;
+
+const END_OF_BLOCK: u16 = 256;
+const LITLEN_CODES: usize = 286;
+const DISTANCE_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+fn distance_code(distance: u16) -> usize {
+    DISTANCE_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A chunk of input compressed independently of the others.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EncodedChunk {
+    /// The chunk doesn't compress and is written in stored blocks.
+    Stored { is_final: bool },
+    /// A block with Huffman codes, ready to be appended at any bit position.
+    Compressed { bits: Vec<u8>, bit_len: usize },
+}
+
+/// Compress `data`, which follows `dictionary` in the input. `level` must be in `0..=9`,
+/// level 0 means no compression at all.
+pub fn encode_chunk(
+    dictionary: &[u8],
+    data: &[u8],
+    level: u32,
+    is_final: bool,
+) -> Result<EncodedChunk> {
+    if level == 0 {
+        return Ok(EncodedChunk::Stored { is_final });
+    }
+
+    let tokens = lz77::find_matches(dictionary, data, level);
+    let block = HuffmanBlock::new(&tokens);
+
+    // Assume the worst padding, so that the choice doesn't depend on the position.
+    let stored_blocks = data.len().div_ceil(MAX_STORED_LEN).max(1);
+    let stored_cost = stored_blocks * (3 + 7 + 32) + data.len() * 8;
+    if stored_cost <= block.cost() {
+        return Ok(EncodedChunk::Stored { is_final });
+    }
+
+    let mut writer = BitWriter::new(Vec::with_capacity(block.cost() / 8 + 1));
+    block.write(&mut writer, &tokens, is_final)?;
+    let bit_len = block.cost();
+    Ok(EncodedChunk::Compressed {
+        bits: writer.finish()?,
+        bit_len,
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The cheapest codes for the symbols of a block.
+struct HuffmanBlock {
+    trees: Trees,
+    cost: usize,
+}
+
+enum Trees {
+    Fixed,
+    Dynamic(DynamicTrees),
+}
+
+impl HuffmanBlock {
+    fn new(tokens: &[Token]) -> Self {
+        let mut litlen_freqs = vec![0; LITLEN_CODES];
+        let mut distance_freqs = vec![0; DISTANCE_CODES];
+        let mut extra_bits = 0;
+        litlen_freqs[END_OF_BLOCK as usize] = 1;
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => litlen_freqs[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    let code = length_code(length);
+                    litlen_freqs[257 + code] += 1;
+                    extra_bits += LENGTH_EXTRA_BITS[code] as usize;
+                    let code = distance_code(distance);
+                    distance_freqs[code] += 1;
+                    extra_bits += DISTANCE_EXTRA_BITS[code] as usize;
+                }
+            }
+        }
+
+        let (fixed_litlen, fixed_distance) = fixed_encoders();
+        let fixed_cost = 3
+            + fixed_litlen.cost(&litlen_freqs)
+            + fixed_distance.cost(&distance_freqs)
+            + extra_bits;
+
+        let dynamic = DynamicTrees::new(&litlen_freqs, &distance_freqs);
+        let dynamic_cost = 3
+            + dynamic.header_cost()
+            + dynamic.litlen.cost(&litlen_freqs)
+            + dynamic.distance.cost(&distance_freqs)
+            + extra_bits;
+
+        let (trees, cost) = if dynamic_cost < fixed_cost {
+            (Trees::Dynamic(dynamic), dynamic_cost)
+        } else {
+            (Trees::Fixed, fixed_cost)
+        };
+        Self { trees, cost }
+    }
+
+    /// The size of the block in bits.
+    fn cost(&self) -> usize {
+        self.cost
+    }
+
+    fn write<T: Write>(
+        &self,
+        writer: &mut BitWriter<T>,
+        tokens: &[Token],
+        is_final: bool,
+    ) -> Result<()> {
+        match &self.trees {
+            Trees::Fixed => {
+                write_block_header(writer, is_final, CompressionType::FixedTree)?;
+                let (litlen, distance) = fixed_encoders();
+                write_tokens(writer, tokens, &litlen, &distance)
+            }
+            Trees::Dynamic(trees) => {
+                write_block_header(writer, is_final, CompressionType::DynamicTree)?;
+                trees.write_header(writer)?;
+                write_tokens(writer, tokens, &trees.litlen, &trees.distance)
+            }
+        }
+    }
+}
+
+fn fixed_encoders() -> (HuffmanEncoder, HuffmanEncoder) {
+    // See RFC 1951, section 3.2.6.
+    let mut litlen = vec![8; 144];
+    litlen.resize(256, 9);
+    litlen.resize(280, 7);
+    litlen.resize(288, 8);
+    (
+        HuffmanEncoder::from_lengths(&litlen),
+        HuffmanEncoder::from_lengths(&[5; DISTANCE_CODES]),
+    )
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct DynamicTrees {
+    litlen: HuffmanEncoder,
+    distance: HuffmanEncoder,
+    code_length: HuffmanEncoder,
+    litlen_count: usize,
+    distance_count: usize,
+    code_length_count: usize,
+    /// Run-length encoded code lengths as `(symbol, extra bits value)`.
+    code_lengths: Vec<(u8, u8)>,
+}
+
+impl DynamicTrees {
+    fn new(litlen_freqs: &[u32], distance_freqs: &[u32]) -> Self {
+        // See RFC 1951, section 3.2.7.
+        let litlen = HuffmanEncoder::from_frequencies(litlen_freqs, MAX_CODE_LEN);
+        let distance = HuffmanEncoder::from_frequencies(distance_freqs, MAX_CODE_LEN);
+        let used = |lengths: &[u8]| lengths.iter().rposition(|&len| len > 0).unwrap() + 1;
+        let litlen_count = used(litlen.lengths()).max(257);
+        let distance_count = used(distance.lengths());
+
+        let lengths = [
+            &litlen.lengths()[..litlen_count],
+            &distance.lengths()[..distance_count],
+        ]
+        .concat();
+        let code_lengths = run_length_encode(&lengths);
+
+        let mut freqs = vec![0; 19];
+        for &(symbol, _) in &code_lengths {
+            freqs[symbol as usize] += 1;
+        }
+        let code_length = HuffmanEncoder::from_frequencies(&freqs, MAX_CODE_LENGTH_CODE_LEN);
+        let code_length_count = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| code_length.lengths()[symbol] > 0)
+            .unwrap()
+            .max(3)
+            + 1;
+
+        Self {
+            litlen,
+            distance,
+            code_length,
+            litlen_count,
+            distance_count,
+            code_length_count,
+            code_lengths,
+        }
+    }
+
+    fn header_cost(&self) -> usize {
+        5 + 5
+            + 4
+            + 3 * self.code_length_count
+            + self
+                .code_lengths
+                .iter()
+                .map(|&(symbol, _)| {
+                    self.code_length.lengths()[symbol as usize] as usize
+                        + code_length_extra_bits(symbol) as usize
+                })
+                .sum::<usize>()
+    }
+
+    fn write_header<T: Write>(&self, writer: &mut BitWriter<T>) -> Result<()> {
+        writer.write_bits((self.litlen_count - 257) as u32, 5)?;
+        writer.write_bits((self.distance_count - 1) as u32, 5)?;
+        writer.write_bits((self.code_length_count - 4) as u32, 4)?;
+        for &symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write_bits(self.code_length.lengths()[symbol] as u32, 3)?;
+        }
+        for &(symbol, extra) in &self.code_lengths {
+            self.code_length.write_symbol(writer, symbol as u16)?;
+            writer.write_bits(extra as u32, code_length_extra_bits(symbol))?;
+        }
+        Ok(())
+    }
+}
+
+fn code_length_extra_bits(symbol: u8) -> u8 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut symbols = vec![];
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&x| x == len).count();
+        i += run;
+        if len == 0 {
+            while run >= 11 {
+                let repeat = run.min(138);
+                symbols.push((18, (repeat - 11) as u8));
+                run -= repeat;
+            }
+            if run >= 3 {
+                symbols.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            symbols.push((len, 0));
+            run -= 1;
+            while run >= 3 {
+                let repeat = run.min(6);
+                symbols.push((16, (repeat - 3) as u8));
+                run -= repeat;
+            }
+        }
+        symbols.extend(std::iter::repeat_n((len, 0), run));
+    }
+    symbols
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn write_block_header<T: Write>(
+    writer: &mut BitWriter<T>,
+    is_final: bool,
+    compression_type: CompressionType,
+) -> Result<()> {
+    let header = BlockHeader {
+        is_final,
+        compression_type,
+    };
+    writer.write_bits(header.is_final as u32, 1)?;
+    writer.write_bits(header.compression_type as u32, 2)?;
+    Ok(())
+}
+
+fn write_tokens<T: Write>(
+    writer: &mut BitWriter<T>,
+    tokens: &[Token],
+    litlen: &HuffmanEncoder,
+    distance: &HuffmanEncoder,
+) -> Result<()> {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => litlen.write_symbol(writer, byte as u16)?,
+            Token::Match {
+                length,
+                distance: dist,
+            } => {
+                let code = length_code(length);
+                litlen.write_symbol(writer, 257 + code as u16)?;
+                writer.write_bits((length - LENGTH_BASE[code]) as u32, LENGTH_EXTRA_BITS[code])?;
+                let code = distance_code(dist);
+                distance.write_symbol(writer, code as u16)?;
+                writer.write_bits(
+                    (dist - DISTANCE_BASE[code]) as u32,
+                    DISTANCE_EXTRA_BITS[code],
+                )?;
+            }
+        }
+    }
+    litlen.write_symbol(writer, END_OF_BLOCK)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct DeflateWriter<T> {
+    bit_writer: BitWriter<T>,
+}
+
+impl<T: Write> DeflateWriter<T> {
+    pub fn new(bit_writer: BitWriter<T>) -> Self {
+        Self { bit_writer }
+    }
+
+    /// Append the chunk returned by `encode_chunk` for `data`.
+    pub fn write_chunk(&mut self, data: &[u8], chunk: &EncodedChunk) -> Result<()> {
+        match chunk {
+            EncodedChunk::Compressed { bits, bit_len } => {
+                self.bit_writer.write_bit_string(bits, *bit_len)?;
+            }
+            EncodedChunk::Stored { is_final } => {
+                let block_count = data.len().div_ceil(MAX_STORED_LEN).max(1);
+                for i in 0..block_count {
+                    let block = &data[(i * MAX_STORED_LEN).min(data.len())
+                        ..((i + 1) * MAX_STORED_LEN).min(data.len())];
+                    let is_final = *is_final && i + 1 == block_count;
+                    write_block_header(
+                        &mut self.bit_writer,
+                        is_final,
+                        CompressionType::Uncompressed,
+                    )?;
+                    let writer = self.bit_writer.borrow_writer_from_boundary()?;
+                    writer.write_u16::<LittleEndian>(block.len() as u16)?;
+                    writer.write_u16::<LittleEndian>(!(block.len() as u16))?;
+                    writer.write_all(block)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pad the last block to a byte boundary and return the underlying writer.
+    pub fn finish(self) -> Result<T> {
+        Ok(self.bit_writer.finish()?)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(length_code(3), 0);
+        assert_eq!(length_code(10), 7);
+        assert_eq!(length_code(12), 8);
+        assert_eq!(length_code(257), 27);
+        assert_eq!(length_code(258), 28);
+        assert_eq!(distance_code(1), 0);
+        assert_eq!(distance_code(6), 4);
+        assert_eq!(distance_code(32768), 29);
+    }
+
+    #[test]
+    fn run_length_encode() {
+        assert_eq!(
+            super::run_length_encode(&[0, 0, 0, 0, 5, 5, 5, 5, 5, 5, 5, 5, 0, 0, 8]),
+            [(17, 1), (5, 0), (16, 3), (5, 0), (0, 0), (0, 0), (8, 0)]
+        );
+        assert_eq!(super::run_length_encode(&[0; 150]), [(18, 127), (18, 1)]);
+    }
+}
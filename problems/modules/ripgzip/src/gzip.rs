@@ -3,7 +3,7 @@
 use std::io::{self, BufRead, Write};
 
 use anyhow::{bail, ensure, Context, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc::Crc;
 
 use crate::{
@@ -25,6 +25,10 @@ const FEXTRA_OFFSET: u8 = 2;
 const FNAME_OFFSET: u8 = 3;
 const FCOMMENT_OFFSET: u8 = 4;
 
+pub const XFL_BEST: u8 = 2;
+pub const XFL_FASTEST: u8 = 4;
+pub const OS_UNKNOWN: u8 = 255;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Debug)]
@@ -208,3 +212,66 @@ impl<T: BufRead> MemberReader<T> {
         unimplemented!()
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct GzipWriter<T> {
+    writer: T,
+}
+
+impl<T: Write> GzipWriter<T> {
+    pub fn new(writer: T) -> Self {
+        Self { writer }
+    }
+
+    pub fn write_header(mut self, header: &MemberHeader) -> Result<MemberWriter<T>> {
+        // See RFC 1952, section 2.3.
+        let writer = &mut self.writer;
+        writer.write_all(&[ID1, ID2, header.compression_method.into(), header.flags().0])?;
+        writer.write_u32::<LittleEndian>(header.modification_time)?;
+        writer.write_all(&[header.extra_flags, header.os])?;
+
+        if let Some(extra) = &header.extra {
+            ensure!(extra.len() <= u16::MAX as usize, "extra field is too long");
+            writer.write_u16::<LittleEndian>(extra.len() as u16)?;
+            writer.write_all(extra)?;
+        }
+
+        for field in [&header.name, &header.comment].into_iter().flatten() {
+            ensure!(
+                !field.contains('\0'),
+                "name and comment must not contain zero bytes"
+            );
+            writer.write_all(field.as_bytes())?;
+            writer.write_u8(0)?;
+        }
+
+        if header.has_crc {
+            writer.write_u16::<LittleEndian>(header.crc16())?;
+        }
+
+        Ok(MemberWriter { inner: self.writer })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.writer
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct MemberWriter<T> {
+    inner: T,
+}
+
+impl<T: Write> MemberWriter<T> {
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn write_footer(mut self, footer: &MemberFooter) -> Result<GzipWriter<T>> {
+        self.inner.write_u32::<LittleEndian>(footer.data_crc32)?;
+        self.inner.write_u32::<LittleEndian>(footer.data_size)?;
+        Ok(GzipWriter::new(self.inner))
+    }
+}
@@ -0,0 +1,195 @@
+#![forbid(unsafe_code)]
+
+use std::{cmp::Reverse, collections::BinaryHeap, io::Write};
+
+use anyhow::Result;
+
+use crate::bit_writer::BitWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Lengths of an optimal prefix code for the symbols with the given frequencies,
+/// limited to `max_len` bits. Unused symbols get zero length.
+///
+/// At least two symbols always get a code, so that the code is complete and
+/// every decoder accepts it.
+pub fn code_lengths(freqs: &[u32], max_len: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    for symbol in 0..freqs.len().min(2) {
+        if freqs.iter().filter(|&&freq| freq > 0).count() >= 2 {
+            break;
+        }
+        if freqs[symbol] == 0 {
+            freqs[symbol] = 1;
+        }
+    }
+
+    // Build the Huffman tree, leaves are `0..freqs.len()`.
+    let mut parents = vec![0; freqs.len()];
+    let mut heap = BinaryHeap::new();
+    for (symbol, &freq) in freqs.iter().enumerate() {
+        if freq > 0 {
+            heap.push(Reverse((freq as u64, symbol)));
+        }
+    }
+    while heap.len() > 1 {
+        let Reverse((first_freq, first)) = heap.pop().unwrap();
+        let Reverse((second_freq, second)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(0);
+        parents[first] = node;
+        parents[second] = node;
+        heap.push(Reverse((first_freq + second_freq, node)));
+    }
+
+    // Count the leaves of each depth, moving the ones deeper than `max_len` up.
+    let max_len = max_len as usize;
+    let mut depths = vec![0; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    let mut counts = vec![0u32; max_len + 1];
+    for symbol in 0..freqs.len() {
+        if freqs[symbol] > 0 {
+            counts[depths[symbol].min(max_len)] += 1;
+        }
+    }
+
+    // Restore the Kraft equality: each step removes a leaf from the deepest level
+    // and splits a shallower one into two, which decreases the sum by one unit.
+    let mut kraft_sum: u64 = (1..=max_len)
+        .map(|len| (counts[len] as u64) << (max_len - len))
+        .sum();
+    while kraft_sum > 1 << max_len {
+        counts[max_len] -= 1;
+        let len = (1..max_len).rev().find(|&len| counts[len] > 0).unwrap();
+        counts[len] -= 1;
+        counts[len + 1] += 2;
+        kraft_sum -= 1;
+    }
+
+    // The most frequent symbols get the shortest codes.
+    let mut symbols: Vec<usize> = (0..freqs.len()).filter(|&s| freqs[s] > 0).collect();
+    symbols.sort_by_key(|&symbol| Reverse(freqs[symbol]));
+    let mut lengths = vec![0; freqs.len()];
+    let mut symbols = symbols.into_iter();
+    for (len, &count) in counts.iter().enumerate() {
+        for symbol in symbols.by_ref().take(count as usize) {
+            lengths[symbol] = len as u8;
+        }
+    }
+    lengths
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct HuffmanEncoder {
+    lengths: Vec<u8>,
+    /// Canonical codes, bit-reversed to be written starting from the least significant bit.
+    codes: Vec<u16>,
+}
+
+impl HuffmanEncoder {
+    pub fn from_lengths(lengths: &[u8]) -> Self {
+        // See RFC 1951, section 3.2.2.
+        let max_len = lengths.iter().copied().max().unwrap_or(0) as usize;
+        let mut counts = vec![0u16; max_len + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut next_code = vec![0u16; max_len + 1];
+        let mut code = 0;
+        for len in 1..=max_len {
+            code = (code + counts[len - 1]) << 1;
+            next_code[len] = code;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&len| {
+                if len == 0 {
+                    return 0;
+                }
+                let code = next_code[len as usize];
+                next_code[len as usize] += 1;
+                code.reverse_bits() >> (16 - len)
+            })
+            .collect();
+
+        Self {
+            lengths: lengths.to_vec(),
+            codes,
+        }
+    }
+
+    pub fn from_frequencies(freqs: &[u32], max_len: u8) -> Self {
+        Self::from_lengths(&code_lengths(freqs, max_len))
+    }
+
+    pub fn lengths(&self) -> &[u8] {
+        &self.lengths
+    }
+
+    /// The number of bits the symbols with the given frequencies take.
+    pub fn cost(&self, freqs: &[u32]) -> usize {
+        freqs
+            .iter()
+            .zip(&self.lengths)
+            .map(|(&freq, &len)| freq as usize * len as usize)
+            .sum()
+    }
+
+    pub fn write_symbol<T: Write>(&self, writer: &mut BitWriter<T>, symbol: u16) -> Result<()> {
+        let len = self.lengths[symbol as usize];
+        debug_assert!(len > 0, "symbol {} has no code", symbol);
+        writer.write_bits(self.codes[symbol as usize] as u32, len)?;
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kraft_sum(lengths: &[u8]) -> f64 {
+        lengths
+            .iter()
+            .filter(|&&len| len > 0)
+            .map(|&len| 0.5f64.powi(len as i32))
+            .sum()
+    }
+
+    #[test]
+    fn code_lengths() {
+        assert_eq!(super::code_lengths(&[5, 1, 1, 2, 0], 15), [1, 3, 3, 2, 0]);
+        assert_eq!(super::code_lengths(&[0, 0, 7], 15), [1, 0, 1]);
+        assert_eq!(super::code_lengths(&[0, 0, 0], 15), [1, 1, 0]);
+    }
+
+    #[test]
+    fn limited_code_lengths() {
+        let freqs: Vec<u32> = (0..30).map(|i| 1 << i).collect();
+        let lengths = super::code_lengths(&freqs, 7);
+        assert_eq!(lengths.iter().max(), Some(&7));
+        assert_eq!(kraft_sum(&lengths), 1.0);
+        for i in 1..freqs.len() {
+            assert!(lengths[i] <= lengths[i - 1]);
+        }
+    }
+
+    #[test]
+    fn write_symbol() -> Result<()> {
+        // The example from RFC 1951, section 3.2.2.
+        let encoder = HuffmanEncoder::from_lengths(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        let mut writer = BitWriter::new(vec![]);
+        encoder.write_symbol(&mut writer, 5)?; // 00
+        encoder.write_symbol(&mut writer, 0)?; // 010
+        encoder.write_symbol(&mut writer, 7)?; // 1111
+        assert_eq!(writer.finish()?, [0b11101000, 0b1]);
+        Ok(())
+    }
+}
@@ -1,19 +1,239 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
+use crc::Crc;
 use log::*;
 
-use crate::gzip::GzipReader;
+use crate::bit_writer::BitWriter;
+use crate::deflate_writer::{DeflateWriter, CHUNK_SIZE};
+use crate::gzip::{
+    CompressionMethod, GzipReader, GzipWriter, MemberFooter, MemberHeader, OS_UNKNOWN, XFL_BEST,
+    XFL_FASTEST,
+};
 
 mod bit_reader;
+mod bit_writer;
 mod deflate;
+mod deflate_writer;
 mod gzip;
 mod huffman_coding;
+mod huffman_encoder;
+mod lz77;
+mod parallel;
 mod tracking_writer;
 
+pub const MAX_LEVEL: u32 = 9;
+
 pub fn decompress<R: BufRead, W: Write>(input: R, mut output: W) -> Result<()> {
     // TODO: your code goes here.
     unimplemented!()
 }
+
+/// Decompress a single member, consuming exactly its bytes from `input`.
+pub fn decompress_member<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
+    // TODO: your code goes here.
+    unimplemented!()
+}
+
+/// Same as `decompress`, but up to `threads` members are decompressed at once.
+///
+/// Member boundaries are only known after the previous member is decompressed.
+/// The input is read ahead in batches of `threads * CHUNK_SIZE` bytes: the member at
+/// the start of the batch is streamed to the output, while every later position
+/// in the batch that looks like a member header is tried speculatively. So the
+/// batch and the decompressed members that fit in it are kept in memory, however
+/// large the members are.
+pub fn decompress_parallel<R: Read, W: Write>(input: R, output: W, threads: usize) -> Result<()> {
+    ensure!(threads > 0, "at least one thread is required");
+
+    let pool = parallel::thread_pool(threads)?;
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+
+    let batch_size = threads * CHUNK_SIZE;
+    // Read ahead input, starting at a member.
+    let mut data = vec![];
+    // Positions in `data` that look like member headers.
+    let mut candidates = VecDeque::new();
+    let mut is_eof = false;
+    loop {
+        if !is_eof && data.len() < batch_size {
+            // The last bytes may be the beginning of a header split by the previous read.
+            let scanned = data.len().saturating_sub(3);
+            is_eof = read_to_size(&mut input, &mut data, batch_size)?;
+            candidates.extend(
+                parallel::member_offsets(&data[scanned..])
+                    .into_iter()
+                    .map(|start| start + scanned),
+            );
+        }
+        if data.is_empty() {
+            break;
+        }
+
+        let starts: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&start| start > 0)
+            .take(threads - 1)
+            .collect();
+        debug!("decompressing members at 0 and {:?}", starts);
+        let mut speculative = vec![];
+        let mut rest = data.as_slice();
+        pool.in_place_scope(|scope| {
+            if !starts.is_empty() {
+                scope.spawn(|_| speculative = parallel::decode_members(&pool, &data, &starts));
+            }
+            decompress_member((&mut rest).chain(&mut input), &mut output)
+        })?;
+
+        if rest.is_empty() {
+            // The member went on past the read ahead input.
+            data.clear();
+            candidates.clear();
+            continue;
+        }
+        let mut offset = data.len() - rest.len();
+        for member in speculative {
+            // A false candidate, a member we haven't reached yet, or one that
+            // doesn't fit in the batch and will be streamed.
+            if member.start != offset {
+                continue;
+            }
+            match member.result {
+                Ok((decompressed, end)) => {
+                    output.write_all(&decompressed)?;
+                    offset = end;
+                }
+                Err(_) => break,
+            }
+        }
+
+        data.drain(..offset);
+        while candidates.front().is_some_and(|&start| start < offset) {
+            candidates.pop_front();
+        }
+        candidates.iter_mut().for_each(|start| *start -= offset);
+    }
+    output.flush().context("failed to write the output")
+}
+
+/// Compress `input` into a single `gzip` member. `level` is from 0 (no compression)
+/// to `MAX_LEVEL` (best compression).
+pub fn compress<R: Read, W: Write>(input: R, output: W, level: u32) -> Result<()> {
+    compress_parallel(input, output, level, 1)
+}
+
+/// Same as `compress`, but up to `threads` chunks are compressed at once, like `pigz` does.
+/// Since every chunk is given the previous one as a dictionary, the output doesn't
+/// depend on the number of threads.
+pub fn compress_parallel<R: Read, W: Write>(
+    mut input: R,
+    output: W,
+    level: u32,
+    threads: usize,
+) -> Result<()> {
+    ensure!(
+        level <= MAX_LEVEL,
+        "unsupported compression level {}",
+        level
+    );
+    ensure!(threads > 0, "at least one thread is required");
+
+    let header = MemberHeader {
+        compression_method: CompressionMethod::Deflate,
+        modification_time: 0,
+        extra: None,
+        name: None,
+        comment: None,
+        extra_flags: match level {
+            1 => XFL_FASTEST,
+            MAX_LEVEL => XFL_BEST,
+            _ => 0,
+        },
+        os: OS_UNKNOWN,
+        has_crc: false,
+        is_text: false,
+    };
+    let mut member = GzipWriter::new(BufWriter::new(output)).write_header(&header)?;
+
+    let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    let mut digest = crc.digest();
+    let mut size = 0;
+
+    let pool = parallel::thread_pool(threads)?;
+    let mut deflate_writer = DeflateWriter::new(BitWriter::new(member.inner_mut()));
+    let mut chunks = read_chunks(&mut input, threads)?;
+    if chunks.is_empty() {
+        chunks.push(vec![]);
+    }
+    let mut dictionary = vec![];
+    loop {
+        let next = read_chunks(&mut input, threads)?;
+        let is_final = next.is_empty();
+        debug!("compressing {} chunks", chunks.len());
+        let encoded = parallel::encode_chunks(&pool, &dictionary, &chunks, level, is_final)?;
+        for (chunk, encoded) in chunks.iter().zip(&encoded) {
+            deflate_writer.write_chunk(chunk, encoded)?;
+            digest.update(chunk);
+            size += chunk.len();
+        }
+        if is_final {
+            break;
+        }
+        dictionary = chunks.pop().unwrap();
+        chunks = next;
+    }
+    deflate_writer.finish()?;
+
+    let footer = MemberFooter {
+        data_crc32: digest.finalize(),
+        data_size: size as u32,
+    };
+    member
+        .write_footer(&footer)?
+        .into_inner()
+        .flush()
+        .context("failed to write the output")
+}
+
+/// Read `CHUNK_SIZE` bytes, or less if the input ends.
+fn read_chunk<R: Read>(input: &mut R) -> Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    input
+        .take(CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)
+        .context("failed to read the input")?;
+    Ok(chunk)
+}
+
+/// Read until `data` holds `size` bytes. Returns whether the input has ended.
+fn read_to_size<R: Read>(input: &mut R, data: &mut Vec<u8>, size: usize) -> Result<bool> {
+    let wanted = size.saturating_sub(data.len());
+    let read = input
+        .take(wanted as u64)
+        .read_to_end(data)
+        .context("failed to read the input")?;
+    Ok(read < wanted)
+}
+
+/// Read up to `count` non-empty chunks, stopping at the end of the input.
+fn read_chunks<R: Read>(input: &mut R, count: usize) -> Result<Vec<Vec<u8>>> {
+    let mut chunks = vec![];
+    while chunks.len() < count {
+        let chunk = read_chunk(input)?;
+        let is_last = chunk.len() < CHUNK_SIZE;
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        if is_last {
+            break;
+        }
+    }
+    Ok(chunks)
+}
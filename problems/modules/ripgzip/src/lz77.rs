@@ -0,0 +1,248 @@
+#![forbid(unsafe_code)]
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const WINDOW_SIZE: usize = 32768;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+const HASH_BITS: usize = 15;
+const HASH_MASK: usize = (1 << HASH_BITS) - 1;
+const NIL: u32 = u32::MAX;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Search parameters of a compression level, the same as in zlib.
+struct Config {
+    /// Don't look for a better match at the next position if the current one is
+    /// at least this long. Zero means no lazy matching at all.
+    max_lazy: usize,
+    /// Stop searching once a match of this length is found.
+    nice_length: usize,
+    /// The number of hash chain entries to check.
+    max_chain: usize,
+}
+
+impl Config {
+    fn new(level: u32) -> Self {
+        let (max_lazy, nice_length, max_chain) = match level {
+            1 => (0, 8, 4),
+            2 => (0, 16, 8),
+            3 => (0, 32, 32),
+            4 => (4, 16, 16),
+            5 => (16, 32, 32),
+            6 => (16, 128, 128),
+            7 => (32, 128, 256),
+            8 => (128, 258, 1024),
+            9 => (258, 258, 4096),
+            _ => panic!("no matching at compression level {}", level),
+        };
+        Self {
+            max_lazy,
+            nice_length,
+            max_chain,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Finds repeated strings with hash chains: `head` holds the last position of each
+/// hash of three bytes, `prev` links every position to the previous one with the same hash.
+struct MatchFinder<'a> {
+    buffer: &'a [u8],
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    config: Config,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(buffer: &'a [u8], config: Config) -> Self {
+        Self {
+            buffer,
+            head: vec![NIL; 1 << HASH_BITS],
+            prev: vec![NIL; buffer.len()],
+            config,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = &self.buffer[pos..pos + MIN_MATCH];
+        (((bytes[0] as usize) << 10) ^ ((bytes[1] as usize) << 5) ^ bytes[2] as usize) & HASH_MASK
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.buffer.len() {
+            let hash = self.hash(pos);
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos as u32;
+        }
+    }
+
+    /// The longest match for the string at `pos` as `(length, distance)`. The length
+    /// is zero if there is no match of at least `MIN_MATCH` bytes.
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        let max_length = MAX_MATCH.min(self.buffer.len() - pos);
+        if max_length < MIN_MATCH {
+            return (0, 0);
+        }
+
+        let target = &self.buffer[pos..pos + max_length];
+        let (mut best_length, mut best_distance) = (MIN_MATCH - 1, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..self.config.max_chain {
+            if candidate == NIL || pos - candidate as usize > WINDOW_SIZE {
+                break;
+            }
+            let start = candidate as usize;
+            let source = &self.buffer[start..start + max_length];
+            if source[best_length] == target[best_length] {
+                let length = source
+                    .iter()
+                    .zip(target)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = pos - start;
+                    if length >= self.config.nice_length.min(max_length) {
+                        break;
+                    }
+                }
+            }
+            candidate = self.prev[start];
+        }
+
+        if best_distance == 0 {
+            (0, 0)
+        } else {
+            (best_length, best_distance)
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Split `data` into literals and back references, which may point up to
+/// `WINDOW_SIZE` bytes back into `dictionary`. `level` must be in `1..=9`.
+pub fn find_matches(dictionary: &[u8], data: &[u8], level: u32) -> Vec<Token> {
+    let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+    let buffer = [dictionary, data].concat();
+    let mut finder = MatchFinder::new(&buffer, Config::new(level));
+    for pos in 0..dictionary.len() {
+        finder.insert(pos);
+    }
+
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    let mut pos = dictionary.len();
+    while pos < buffer.len() {
+        let (length, distance) = finder.longest_match(pos);
+        finder.insert(pos);
+        if length == 0 {
+            tokens.push(Token::Literal(buffer[pos]));
+            pos += 1;
+            continue;
+        }
+
+        // Lazy evaluation: emit a literal instead if the next string has a longer match.
+        if length < finder.config.max_lazy && finder.longest_match(pos + 1).0 > length {
+            tokens.push(Token::Literal(buffer[pos]));
+            pos += 1;
+            continue;
+        }
+
+        tokens.push(Token::Match {
+            length: length as u16,
+            distance: distance as u16,
+        });
+        for next in pos + 1..pos + length {
+            finder.insert(next);
+        }
+        pos += length;
+    }
+    tokens
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpack(dictionary: &[u8], tokens: &[Token]) -> Vec<u8> {
+        let mut buffer = dictionary.to_vec();
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => buffer.push(byte),
+                Token::Match { length, distance } => {
+                    for _ in 0..length {
+                        buffer.push(buffer[buffer.len() - distance as usize]);
+                    }
+                }
+            }
+        }
+        buffer.split_off(dictionary.len())
+    }
+
+    #[test]
+    fn find_matches() {
+        let tokens = super::find_matches(b"", b"abcabcabcabcx", 6);
+        assert_eq!(
+            tokens,
+            [
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match {
+                    length: 9,
+                    distance: 3
+                },
+                Token::Literal(b'x'),
+            ]
+        );
+
+        let tokens = super::find_matches(b"hello, ", b"hello", 1);
+        assert_eq!(
+            tokens,
+            [Token::Match {
+                length: 5,
+                distance: 7
+            }]
+        );
+    }
+
+    #[test]
+    fn lazy_matching() {
+        let data = b"abcdbcdexabcdexx";
+        let greedy = super::find_matches(b"", data, 1);
+        let lazy = super::find_matches(b"", data, 9);
+        assert_eq!(unpack(b"", &greedy), data);
+        assert_eq!(unpack(b"", &lazy), data);
+        assert!(lazy.contains(&Token::Match {
+            length: 5,
+            distance: 6
+        }));
+        assert!(lazy.len() < greedy.len());
+    }
+
+    #[test]
+    fn window() {
+        let dictionary: Vec<u8> = (0..WINDOW_SIZE + 100)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        let data = dictionary[..50].to_vec();
+        let tokens = super::find_matches(&dictionary, &data, 9);
+        assert_eq!(unpack(&dictionary, &tokens), data);
+        for token in tokens {
+            if let Token::Match { distance, .. } = token {
+                assert!(distance as usize <= WINDOW_SIZE);
+            }
+        }
+    }
+}
@@ -5,7 +5,7 @@ use std::io::{stdin, stdout};
 use log::*;
 use structopt::StructOpt;
 
-use ripgzip::decompress;
+use ripgzip::{compress_parallel, decompress, decompress_parallel};
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
@@ -13,6 +13,12 @@ struct Opts {
     /// Decompress data
     #[structopt(short = "d", long = "decompress")]
     decompress: bool,
+    /// Compression level, from 0 (no compression) to 9 (best compression)
+    #[structopt(short = "l", long = "level", default_value = "6")]
+    level: u32,
+    /// Number of threads to compress or decompress with
+    #[structopt(short = "p", long = "threads", default_value = "1")]
+    threads: usize,
     /// Verbose mode (-v, -vv, -vvv, etc)
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
@@ -27,10 +33,15 @@ fn main() {
         .init()
         .expect("failed to initialize logging");
 
-    if opts.decompress {
-        if let Err(err) = decompress(stdin().lock(), stdout().lock()) {
-            error!("{:#}", err);
-            std::process::exit(1);
-        }
+    let result = if opts.decompress && opts.threads > 1 {
+        decompress_parallel(stdin().lock(), stdout().lock(), opts.threads)
+    } else if opts.decompress {
+        decompress(stdin().lock(), stdout().lock())
+    } else {
+        compress_parallel(stdin().lock(), stdout().lock(), opts.level, opts.threads)
+    };
+    if let Err(err) = result {
+        error!("{:#}", err);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,121 @@
+#![forbid(unsafe_code)]
+
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::deflate_writer::{self, EncodedChunk};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The pool shared by all batches of a single run.
+pub fn thread_pool(threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .context("failed to start worker threads")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Compress consecutive chunks of the input on `pool`. `dictionary` is the
+/// chunk before the first one, `is_final` tells whether the last one ends the input.
+pub fn encode_chunks(
+    pool: &ThreadPool,
+    dictionary: &[u8],
+    chunks: &[Vec<u8>],
+    level: u32,
+    is_final: bool,
+) -> Result<Vec<EncodedChunk>> {
+    pool.install(|| {
+        chunks
+            .par_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let dictionary = match i {
+                    0 => dictionary,
+                    _ => &chunks[i - 1],
+                };
+                let is_final = is_final && i + 1 == chunks.len();
+                deflate_writer::encode_chunk(dictionary, chunk, level, is_final)
+            })
+            .collect()
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Positions in `data` that look like the start of a member: the magic bytes,
+/// the deflate compression method and no reserved flags.
+pub fn member_offsets(data: &[u8]) -> Vec<usize> {
+    data.windows(4)
+        .enumerate()
+        .filter(|(_, header)| header[..3] == [0x1f, 0x8b, 0x08] && header[3] & 0xe0 == 0)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+pub struct DecodedMember {
+    pub start: usize,
+    /// The decompressed data and the position right after the member.
+    pub result: Result<(Vec<u8>, usize)>,
+}
+
+fn decode_member(data: &[u8], start: usize) -> Result<(Vec<u8>, usize)> {
+    let mut rest = &data[start..];
+    let mut output = vec![];
+    crate::decompress_member(&mut rest, &mut output)?;
+    Ok((output, data.len() - rest.len()))
+}
+
+/// Decompress the members starting at `starts` on `pool`. Errors are returned,
+/// not propagated, since some of the starts may be wrong guesses.
+pub fn decode_members(pool: &ThreadPool, data: &[u8], starts: &[usize]) -> Vec<DecodedMember> {
+    pool.install(|| {
+        starts
+            .par_iter()
+            .map(|&start| DecodedMember {
+                start,
+                result: panic::catch_unwind(AssertUnwindSafe(|| decode_member(data, start)))
+                    .unwrap_or_else(|_| Err(anyhow!("decompression thread panicked"))),
+            })
+            .collect()
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn member_offsets() {
+        let data = [
+            0x1f, 0x8b, 0x08, 0x00, 0x1f, 0x8b, 0x08, 0x1f, 0x8b, 0x08, 0x20, 0x1f, 0x8b, 0x07,
+            0x00, 0x1f, 0x8b, 0x08, 0x1e,
+        ];
+        assert_eq!(super::member_offsets(&data), [0, 4, 15]);
+        assert!(super::member_offsets(&[0x1f, 0x8b, 0x08]).is_empty());
+    }
+
+    #[test]
+    fn encode_chunks() -> Result<()> {
+        let chunks: Vec<Vec<u8>> = (0..4).map(|i| vec![i; 1000]).collect();
+        let pool = thread_pool(2)?;
+        let encoded = super::encode_chunks(&pool, b"dictionary", &chunks, 6, true)?;
+        assert_eq!(encoded.len(), 4);
+        for (i, (chunk, encoded)) in chunks.iter().zip(&encoded).enumerate() {
+            let dictionary = if i == 0 {
+                &b"dictionary"[..]
+            } else {
+                &chunks[i - 1]
+            };
+            let expected = deflate_writer::encode_chunk(dictionary, chunk, 6, i == 3)?;
+            assert_eq!(encoded, &expected);
+        }
+        Ok(())
+    }
+}
@@ -20,6 +20,16 @@ def decompress_file_ripgzip(data, debug=False):
     return proc.stdout
 
 
+def compress_file_ripgzip(data, level, threads=1):
+    proc = subprocess.run(
+        [RELEASE_BINARY_PATH, "--level", str(level), "--threads", str(threads)],
+        input=data,
+        capture_output=True,
+        check=True,
+    )
+    return proc.stdout
+
+
 def test_static_cases():
     for file_path in sorted(OK_TESTS_PATH.iterdir()):
         print(f"checking file '{file_path}'")
@@ -71,11 +81,52 @@ def test_big_random_cases():
             raise
 
 
+def test_compression_cases():
+    random.seed(8472156)
+
+    inputs = []
+    for file_path in sorted(OK_TESTS_PATH.iterdir()):
+        with open(file_path, "rb") as f:
+            inputs.append((str(file_path), gzip.decompress(f.read())))
+    for i in range(10):
+        data = bytes(random.randrange(16) for _ in range(random.randrange(100000)))
+        inputs.append((f"random data #{i + 1}", data))
+
+    for name, data in inputs:
+        for level in [0, 1, 6, 9]:
+            print(f"compressing {name} with level {level}")
+
+            compressed = compress_file_ripgzip(data, level)
+            assert gzip.decompress(compressed) == data, f"incorrect output"
+            assert decompress_file_ripgzip(compressed) == data, f"incorrect output"
+
+
+def test_parallel_cases():
+    for file_path in sorted(OK_TESTS_PATH.iterdir()):
+        print(f"checking file '{file_path}' with 4 threads")
+
+        with open(file_path, "rb") as f:
+            data = f.read()
+
+        proc = subprocess.run(
+            [RELEASE_BINARY_PATH, "-d", "--threads", "4"],
+            input=data,
+            capture_output=True,
+            check=True,
+        )
+        assert gzip.decompress(data) == proc.stdout, f"incorrect output"
+
+        expected = compress_file_ripgzip(data, 6)
+        assert compress_file_ripgzip(data, 6, threads=4) == expected, f"output differs"
+
+
 def main():
     bundles = [
         test_static_cases,
         test_small_random_cases,
         test_big_random_cases,
+        test_compression_cases,
+        test_parallel_cases,
     ]
 
     if len(sys.argv) > 1:
@@ -0,0 +1,99 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+fn gzip(args: &[&str], input: Vec<u8>) -> Vec<u8> {
+    let mut child = Command::new("gzip")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run gzip");
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "gzip {:?} failed", args);
+    output.stdout
+}
+
+fn compress(mut data: &[u8], level: u32) -> Vec<u8> {
+    let mut compressed = vec![];
+    ripgzip::compress(&mut data, &mut compressed, level).unwrap();
+    compressed
+}
+
+fn corpus() -> Vec<Vec<u8>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/ok");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| gzip(&["-dc"], fs::read(path).unwrap()))
+        .collect()
+}
+
+#[test]
+fn compress_corpus() {
+    for data in corpus() {
+        for level in [0, 1, 6, 9] {
+            let compressed = compress(&data, level);
+            assert!(gzip(&["-dc"], compressed) == data, "level {}", level);
+        }
+    }
+}
+
+#[test]
+fn compress_small() {
+    let mut inputs = vec![
+        vec![],
+        b"a".to_vec(),
+        b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+        (0..=255).collect(),
+    ];
+    // Random data repeated with a period just under the window size and just over it.
+    let mut state = 0x2545f491u32;
+    let random: Vec<u8> = (0..32769)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    for period in [32768, 32769] {
+        inputs.push(random[..period].repeat(6));
+    }
+    for data in inputs {
+        for level in 0..=9 {
+            let compressed = compress(&data, level);
+            assert!(gzip(&["-t"], compressed.clone()).is_empty());
+            assert!(gzip(&["-dc"], compressed) == data, "level {}", level);
+        }
+    }
+}
+
+#[test]
+fn compress_level() {
+    let data = b"abracadabra".repeat(1000);
+    assert!(compress(&data, 9).len() < compress(&data, 0).len());
+    let err = ripgzip::compress(&mut data.as_slice(), &mut vec![], 10).unwrap_err();
+    assert!(err.to_string().contains("unsupported compression level"));
+}
+
+#[test]
+fn roundtrip() {
+    for data in corpus() {
+        let compressed = compress(&data, 6);
+        let mut decompressed = vec![];
+        ripgzip::decompress(compressed.as_slice(), &mut decompressed).unwrap();
+        assert!(decompressed == data);
+    }
+}
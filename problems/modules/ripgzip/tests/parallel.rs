@@ -0,0 +1,90 @@
+const CHUNK_SIZE: usize = 128 * 1024;
+
+/// Pseudo-random words, compressible but with some entropy.
+fn text(len: usize, seed: u32) -> Vec<u8> {
+    const WORDS: &[&str] = &[
+        "gzip", "member", "deflate", "block", "huffman", "window", "thread", "chunk", "the", "a",
+        "of", "and", "\n",
+    ];
+    let mut state = seed | 1;
+    let mut text = vec![];
+    while text.len() < len {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        text.extend_from_slice(WORDS[state as usize % WORDS.len()].as_bytes());
+        text.push(b' ');
+    }
+    text.truncate(len);
+    text
+}
+
+fn compress(mut data: &[u8], level: u32, threads: usize) -> Vec<u8> {
+    let mut compressed = vec![];
+    ripgzip::compress_parallel(&mut data, &mut compressed, level, threads).unwrap();
+    compressed
+}
+
+#[test]
+fn compress_parallel() {
+    let inputs = [
+        vec![],
+        b"x".to_vec(),
+        text(CHUNK_SIZE, 1),
+        text(CHUNK_SIZE + 1, 2),
+        text(5 * CHUNK_SIZE + 17, 3),
+        text(9 * CHUNK_SIZE, 4),
+    ];
+    for data in inputs {
+        for level in [0, 1, 9] {
+            let mut expected = vec![];
+            ripgzip::compress(data.as_slice(), &mut expected, level).unwrap();
+            for threads in [2, 3, 8] {
+                assert!(
+                    compress(&data, level, threads) == expected,
+                    "{} bytes, level {}, {} threads",
+                    data.len(),
+                    level,
+                    threads
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn decompress_parallel() {
+    let mut members: Vec<(Vec<u8>, u32)> = (0..8)
+        .map(|i| (text(100_000 * i, i as u32), i as u32 + 2))
+        .collect();
+    // Stored as is, so the compressed data has a fake member header inside.
+    members.push((b"\x1f\x8b\x08\x00 is not a header".repeat(100), 0));
+    members.push((vec![], 0));
+    members.push((text(3 * CHUNK_SIZE, 42), 6));
+    // Bigger than a batch even for 4 threads.
+    members.push((text(5 * CHUNK_SIZE, 43), 0));
+    members.push((b"tail".to_vec(), 6));
+
+    let mut file = vec![];
+    let mut expected = vec![];
+    for (data, level) in &members {
+        file.extend(compress(data, *level, 2));
+        expected.extend_from_slice(data);
+    }
+
+    for threads in [1, 2, 4, 16] {
+        let mut decompressed = vec![];
+        ripgzip::decompress_parallel(file.as_slice(), &mut decompressed, threads).unwrap();
+        assert!(decompressed == expected, "{} threads", threads);
+    }
+}
+
+#[test]
+fn decompress_parallel_errors() {
+    let mut file = compress(&text(10_000, 1), 6, 1);
+    let mut corrupted = compress(&text(10_000, 2), 6, 1);
+    let len = corrupted.len();
+    corrupted[len - 8] ^= 1;
+    file.extend(corrupted);
+    assert!(ripgzip::decompress_parallel(file.as_slice(), &mut vec![], 4).is_err());
+}